@@ -1,34 +1,196 @@
-use std::{thread, time::Duration};
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::mpsc::RecvTimeoutError,
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Result};
-use opilio_lib::{serial::OpilioSerialDevice, PID, VID};
+use opilio_lib::{
+    control::Controller,
+    hotplug::{HotplugEvent, HotplugWatcher},
+    manager::{DeviceManager, ALIASES_FILE_NAME},
+    Config,
+};
+
+const CONFIG_DIR_NAME: &str = "opilio";
+const RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// A device whose config hands control to the host.
+struct Driven {
+    config: Config,
+    controller: Controller,
+    last_step: Instant,
+}
+
+/// What the daemon does for each open device, `None` for those running on
+/// their own.
+type Devices = BTreeMap<String, Option<Driven>>;
 
 fn main() {
+    let mut manager = DeviceManager::new();
+    if let Err(e) = load_aliases(&mut manager) {
+        eprintln!("Failed to read device aliases ({e})");
+    }
+    let watcher = HotplugWatcher::usb();
+    let mut devices = Devices::new();
+    let mut ping_at = Instant::now();
     loop {
-        let sleep_for = get_sleep_time().unwrap_or_else(|_| {
-            eprintln!(
-                "Failed to connect to opilio device, will try again in 30 secs"
-            );
-            Duration::from_secs(30)
+        if Instant::now() >= ping_at {
+            ping_at = Instant::now() + get_sleep_time(&mut manager);
+            // configs may have changed since, they are read again
+            refresh(&mut manager, &mut devices);
+        }
+        let mut sleep_for = ping_at.saturating_duration_since(Instant::now());
+        if let Some(next) = drive(&mut manager, &mut devices) {
+            sleep_for = sleep_for.min(next);
+        }
+        // wake up early to look after a device that was just plugged in
+        match watcher.events().recv_timeout(sleep_for) {
+            Ok(HotplugEvent::Attached(port)) => {
+                println!("{port} attached");
+                ping_at = Instant::now();
+            }
+            Ok(HotplugEvent::Detached(port)) => {
+                println!("{port} detached");
+                // starts over with a fresh controller once it is back
+                devices.remove(port.key());
+                ping_at = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(sleep_for),
+        }
+    }
+}
+
+/// Sends the next duties to every device under host control, returns how
+/// long until they are due again, `None` if no device is.
+fn drive(
+    manager: &mut DeviceManager,
+    devices: &mut Devices,
+) -> Option<Duration> {
+    let mut next: Option<Duration> = None;
+    for (key, device) in manager.open_devices() {
+        let driven = match devices.entry(key.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match device.get_config() {
+                Ok(config) => entry.insert(driven(config, None)),
+                Err(e) => {
+                    eprintln!("Failed to read the config of {key} ({e})");
+                    continue;
+                }
+            },
+        };
+        let Some(driven) = driven else {
+            continue;
+        };
+        let Some(host_control) = driven.config.host_control else {
+            continue;
+        };
+        let dt = driven.last_step.elapsed();
+        driven.last_step = Instant::now();
+        // on failure the watchdog hands control back to the device
+        let sent = device.get_stats().and_then(|stats| {
+            // the device's own curves for now, policies using what only the
+            // host knows plug in here
+            let duties =
+                driven.controller.update(&driven.config, &stats, None, dt);
+            device.set_host_duties(duties)
         });
-        thread::sleep(sleep_for);
+        if let Err(e) = sent {
+            eprintln!("Failed to send duties to {key} ({e})");
+        }
+        let due = host_control.watchdog() / 2;
+        next = Some(next.map_or(due, |n| n.min(due)));
+    }
+    next
+}
+
+/// Reads the config of every open device again. Devices whose config did not
+/// change keep their controller, so the ramp carries on where it was.
+fn refresh(manager: &mut DeviceManager, devices: &mut Devices) {
+    let mut refreshed = Devices::new();
+    for (key, device) in manager.open_devices() {
+        let previous = devices.remove(key);
+        match device.get_config() {
+            Ok(config) => {
+                refreshed.insert(
+                    key.to_string(),
+                    driven(config, previous.flatten()),
+                );
+            }
+            Err(e) => {
+                eprintln!("Failed to read the config of {key} ({e})");
+                if let Some(previous) = previous {
+                    refreshed.insert(key.to_string(), previous);
+                }
+            }
+        }
+    }
+    // devices that are no longer open are dropped with their controllers
+    *devices = refreshed;
+}
+
+/// How a device with `config` is driven, `previous` is kept while its config
+/// stays the same.
+fn driven(config: Config, previous: Option<Driven>) -> Option<Driven> {
+    config.host_control?;
+    match previous {
+        Some(previous) if previous.config == config => Some(previous),
+        _ => Some(Driven {
+            config,
+            controller: Controller::default(),
+            last_step: Instant::now(),
+        }),
+    }
+}
+
+/// Pings every attached device, then sleeps until the one that would fall
+/// asleep first needs pinging again.
+fn get_sleep_time(manager: &mut DeviceManager) -> Duration {
+    let checks = match manager.health_check() {
+        Ok(checks) if !checks.is_empty() => checks,
+        Ok(_) => {
+            eprintln!("No opilio device found, will try again in 30 secs");
+            return RETRY_AFTER;
+        }
+        Err(e) => {
+            eprintln!("Failed to look for opilio devices ({e}), will try again in 30 secs");
+            return RETRY_AFTER;
+        }
+    };
+    let mut sleep_for: Option<Duration> = None;
+    for (id, result) in checks {
+        let next = match result {
+            Ok(sleep_after_seconds) => {
+                // sleep for 90% of the time, so we can ping again.
+                // 1000ms * 90% = 900ms
+                let next =
+                    Duration::from_millis((sleep_after_seconds as u64) * 900);
+                println!(
+                    "{id} sleep settings {sleep_after_seconds}s, ping again \
+                     in {next:?}"
+                );
+                next
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to connect to {id} ({e}), will try again in 30 \
+                     secs"
+                );
+                RETRY_AFTER
+            }
+        };
+        sleep_for = Some(sleep_for.map_or(next, |s| s.min(next)));
     }
+    sleep_for.unwrap_or(RETRY_AFTER)
 }
 
-fn get_sleep_time() -> Result<Duration> {
-    let ports = OpilioSerialDevice::find_ports(VID, PID)?;
-    let port = ports
-        .first()
-        .ok_or_else(|| anyhow!("No Opilio device found"))?;
-    let mut serial = OpilioSerialDevice::new(&port.port_name)?;
-    println!("{serial:?}");
-    let sleep_after_seconds = serial.ping()?;
-    // sleep for 90% of the time, so we can ping again.
-    // 1000ms * 90% = 900ms
-    let sleep_for = Duration::from_millis((sleep_after_seconds as u64) * 900);
-    println!(
-        "Sleep settings {sleep_after_seconds}s, Ping again in {sleep_for:?}"
-    );
-
-    Ok(sleep_for)
+/// Friendly names for devices, a JSON object keyed by USB serial number.
+fn load_aliases(manager: &mut DeviceManager) -> Result<()> {
+    let path = dirs::config_dir()
+        .ok_or_else(|| anyhow!("User config directory does not exist"))?
+        .join(CONFIG_DIR_NAME)
+        .join(ALIASES_FILE_NAME);
+    Ok(manager.load_aliases(&path)?)
 }
@@ -0,0 +1,608 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use heapless::Vec;
+use opilio_lib::{
+    control::Controller,
+    error::Error,
+    otw::{
+        self, encode_frame, FrameDecoder, Reassembly, Transfer, TransferKind,
+        MAX_FRAME_SIZE, MAX_TRANSFER_SIZE, UNCORRELATED_SEQ,
+    },
+    transport::Transport,
+    wire::{
+        ConfigLayout, ControlModeV2, FanSettingV2, FixedSample, FixedStats,
+        WireConfig, ENVELOPE_PROTOCOL,
+    },
+    Config, ConfigPart, ControlMode, Data, DataRef, FanSetting, Id, Msg,
+    Override, Response, Sample, SemVer, Stats, Version, HOST_TEMP_TIMEOUT_S,
+    MAX_SERIAL_DATA_SIZE, MIN_PROTOCOL_VERSION, MIN_STATS_INTERVAL_MS, OTW,
+    PROTOCOL_VERSION,
+};
+
+use crate::thermal::ThermalModel;
+
+/// Reported as the board revision, real boards start at 1.
+const HARDWARE_REVISION: u8 = 0;
+/// How often the model advances while no requests are coming in.
+const IDLE_TICK: Duration = Duration::from_millis(100);
+
+/// Stats pushed without being asked for, see [`Msg::SubscribeStats`].
+struct Subscription {
+    interval: Duration,
+    index: u32,
+    due: Instant,
+}
+
+/// Answers OTW requests the way the Opilio firmware does.
+pub struct Emulator {
+    /// Config the controller is running with.
+    config: Config,
+    /// Config persisted in "flash", restored by [`Msg::Reload`].
+    saved: Config,
+    model: ThermalModel,
+    controller: Controller,
+    /// Error to answer the next matching command with.
+    fault: Option<(Msg, Error)>,
+    subscription: Option<Subscription>,
+    /// Last [`Msg::HostTemp`] and when it arrived.
+    host_temp: Option<(f32, Instant)>,
+    /// Duty each channel is held at by [`Msg::SetOverride`] and until
+    /// when, by [`Id::index`].
+    overrides: [Option<(f32, Instant)>; 4],
+    /// Protocol revision the host announced with [`Msg::GetVersion`], picks
+    /// the form stats are sent in.
+    host_protocol: u16,
+    transfer: Reassembly<MAX_TRANSFER_SIZE>,
+    /// Config too large for a reply to [`Msg::GetConfig`], handed out with
+    /// [`Msg::GetChunk`].
+    download: Option<Vec<u8, MAX_TRANSFER_SIZE>>,
+    /// Samples to leave out of the stream, to see how a frontend copes
+    /// with gaps.
+    dropped_samples: u32,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl Emulator {
+    pub fn new(config: Config) -> Self {
+        let mut emulator = Self {
+            saved: config.clone(),
+            config,
+            model: ThermalModel::default(),
+            controller: Controller::default(),
+            fault: None,
+            subscription: None,
+            host_temp: None,
+            overrides: [None; 4],
+            host_protocol: MIN_PROTOCOL_VERSION,
+            transfer: Reassembly::new(),
+            download: None,
+            dropped_samples: 0,
+        };
+        emulator.update_duties(Duration::ZERO);
+        emulator
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn model_mut(&mut self) -> &mut ThermalModel {
+        &mut self.model
+    }
+
+    /// Makes the next `msg` fail with `error`, e.g. to see how a frontend
+    /// copes with a worn out flash.
+    pub fn inject_fault(&mut self, msg: Msg, error: Error) {
+        self.fault = Some((msg, error));
+    }
+
+    /// Leaves the next `count` samples out of the stats stream.
+    pub fn drop_samples(&mut self, count: u32) {
+        self.dropped_samples = count;
+    }
+
+    pub fn version(&self) -> Version {
+        let part = |s: &str| s.parse().unwrap_or_default();
+        Version {
+            firmware: SemVer {
+                major: part(env!("CARGO_PKG_VERSION_MAJOR")),
+                minor: part(env!("CARGO_PKG_VERSION_MINOR")),
+                patch: part(env!("CARGO_PKG_VERSION_PATCH")),
+            },
+            protocol: PROTOCOL_VERSION,
+            hardware: HARDWARE_REVISION,
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let m = &self.model;
+        let now = Instant::now();
+        let overrides = Id::ALL.map(|id| {
+            let (duty_percent, until) = self.overrides[id.index()]?;
+            let left = until.checked_duration_since(now)?;
+            Some(Override {
+                id,
+                duty_percent,
+                timeout_s: left.as_secs_f32().ceil() as u32,
+            })
+        });
+        Stats {
+            pump1_rpm: m.rpms[0],
+            fan1_rpm: m.rpms[1],
+            fan2_rpm: m.rpms[2],
+            fan3_rpm: m.rpms[3],
+            coolant_temp: m.coolant_temp,
+            ambient_temp: m.ambient_temp,
+            coolant_out_temp: m.coolant_out_temp,
+            overrides,
+        }
+    }
+
+    /// Advances the thermal model and re-evaluates the duties.
+    pub fn step(&mut self, dt: Duration) {
+        self.model.step(dt);
+        self.update_duties(dt);
+    }
+
+    /// Re-evaluates the duties `dt` after the last time.
+    fn update_duties(&mut self, dt: Duration) {
+        let stats = self.stats();
+        let host_temp = self
+            .host_temp
+            .filter(|(_, at)| {
+                at.elapsed() < Duration::from_secs(HOST_TEMP_TIMEOUT_S.into())
+            })
+            .map(|(temp, _)| temp);
+        self.model.duties =
+            self.controller.update(&self.config, &stats, host_temp, dt);
+
+        // overrides win over the host, curves and smart mode alike
+        let now = Instant::now();
+        for (index, held) in self.overrides.iter_mut().enumerate() {
+            match *held {
+                Some((duty, until)) if now < until => {
+                    self.model.duties[index] = duty;
+                }
+                _ => *held = None,
+            }
+        }
+    }
+
+    /// Builds the serialised reply for a single request.
+    pub fn handle(
+        &mut self,
+        request: OTW,
+    ) -> opilio_lib::Result<Vec<u8, MAX_SERIAL_DATA_SIZE>> {
+        log::debug!("handling {:?} #{}", request.msg, request.seq);
+        let ok = Response::Ok;
+        let seq = request.seq;
+        let error = |e| {
+            OTW::serialised_vec(
+                seq,
+                Msg::Result,
+                DataRef::Result(&Response::Error(e)),
+            )
+        };
+        if let Some((msg, e)) = self.fault {
+            if msg == request.msg {
+                self.fault = None;
+                return error(e);
+            }
+        }
+        match (request.msg, request.data) {
+            (Msg::Ping, _) => OTW::serialised_vec(
+                seq,
+                Msg::Pong,
+                DataRef::Pong(&self.config.general.sleep_after),
+            ),
+            (Msg::GetVersion, data) => {
+                // hosts before fixed point do not say what they speak
+                self.host_protocol = match data {
+                    Data::Protocol(protocol) => protocol,
+                    _ => MIN_PROTOCOL_VERSION,
+                };
+                OTW::serialised_vec(
+                    seq,
+                    Msg::Version,
+                    DataRef::Version(&self.version()),
+                )
+            }
+            (Msg::GetStats, _) => {
+                let stats = self.stats();
+                match self.host_protocol {
+                    ENVELOPE_PROTOCOL.. => OTW::serialised_vec(
+                        seq,
+                        Msg::Stats,
+                        DataRef::FixedStats(&FixedStats::from(&stats)),
+                    ),
+                    _ => OTW::serialised_vec(
+                        seq,
+                        Msg::Stats,
+                        DataRef::Stats(&stats),
+                    ),
+                }
+            }
+            (Msg::GetConfig, data) => {
+                // hosts before the config envelope ask with no data
+                let layout = match data {
+                    Data::Layout(layout) => layout.min(ConfigLayout::CURRENT),
+                    _ => ConfigLayout::V1,
+                };
+                let wire = match WireConfig::new(&self.config, layout) {
+                    Ok(wire) => wire,
+                    Err(e) => return error(e),
+                };
+                match OTW::serialised_vec(seq, Msg::Config, wire.data()) {
+                    // too large for a single message
+                    Err(Error::Serialize)
+                        if self.host_protocol >= Msg::GetChunk.protocol() =>
+                    {
+                        self.begin_download(seq, &wire)
+                    }
+                    reply => reply,
+                }
+            }
+            (Msg::UploadConfig, data) => {
+                let config: Config = match data {
+                    Data::LegacyConfig(config) => config.into(),
+                    Data::Config(config) => config.into(),
+                    _ => return error(Error::InvalidMsgDataPair),
+                };
+                if !config.is_valid() {
+                    return error(Error::InvalidConfig);
+                }
+                self.config = config;
+                self.update_duties(Duration::ZERO);
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::SaveConfig, _) => {
+                self.saved = self.config.clone();
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::Reload, _) => {
+                self.config = self.saved.clone();
+                self.update_duties(Duration::ZERO);
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::SubscribeStats, Data::Interval(ms))
+                if ms < MIN_STATS_INTERVAL_MS =>
+            {
+                error(Error::InvalidInterval)
+            }
+            (Msg::SubscribeStats, Data::Interval(ms)) => {
+                let interval = Duration::from_millis(ms.into());
+                self.subscription = Some(Subscription {
+                    interval,
+                    index: 0,
+                    due: Instant::now() + interval,
+                });
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::HostTemp, Data::Temp(temp)) => {
+                self.host_temp = Some((temp, Instant::now()));
+                self.update_duties(Duration::ZERO);
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::Unsubscribe, _) => {
+                self.subscription = None;
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::GetFanSetting, Data::Id(id)) => match self.config.get(id) {
+                Some(setting) => OTW::serialised_vec(
+                    seq,
+                    Msg::FanSetting,
+                    DataRef::FanSetting(&FanSettingV2::from(setting)),
+                ),
+                None => error(Error::UnknownChannel),
+            },
+            (Msg::GetGeneral, _) => OTW::serialised_vec(
+                seq,
+                Msg::General,
+                DataRef::General(&self.config.general),
+            ),
+            (Msg::GetSmartMode, _) => {
+                let smart_mode = match self.config.mode {
+                    ControlMode::Smart(ref smart_mode) => {
+                        Some(smart_mode.into())
+                    }
+                    _ => None,
+                };
+                OTW::serialised_vec(
+                    seq,
+                    Msg::SmartMode,
+                    DataRef::SmartMode(&smart_mode),
+                )
+            }
+            (Msg::GetControlMode, _) => OTW::serialised_vec(
+                seq,
+                Msg::ControlMode,
+                DataRef::ControlMode(&ControlModeV2::from(&self.config.mode)),
+            ),
+            (Msg::UploadFanSetting, Data::FanSetting(setting)) => {
+                let setting = FanSetting::from(setting);
+                let part = ConfigPart::FanSetting(setting.id);
+                let applied = self.apply(|config| {
+                    match config
+                        .settings
+                        .iter_mut()
+                        .find(|s| s.id == setting.id)
+                    {
+                        // the part has no room for the channel mode
+                        Some(current) => {
+                            *current = FanSetting {
+                                mode: current.mode,
+                                ..setting
+                            }
+                        }
+                        // a full config missing the channel has another one
+                        // twice and is refused anyway
+                        None => {
+                            config.settings.push(setting).ok();
+                        }
+                    }
+                });
+                match applied {
+                    Ok(()) => OTW::serialised_vec(
+                        seq,
+                        Msg::Applied,
+                        DataRef::Applied(&part),
+                    ),
+                    Err(e) => error(e),
+                }
+            }
+            (Msg::UploadGeneral, Data::General(general)) => {
+                match self.apply(|config| config.general = general) {
+                    Ok(()) => OTW::serialised_vec(
+                        seq,
+                        Msg::Applied,
+                        DataRef::Applied(&ConfigPart::General),
+                    ),
+                    Err(e) => error(e),
+                }
+            }
+            (Msg::UploadSmartMode, Data::SmartMode(smart_mode)) => {
+                let smart_mode = smart_mode.map(Into::into);
+                let applied = self.apply(|config| {
+                    match smart_mode {
+                        Some(smart_mode) => {
+                            config.mode = ControlMode::Smart(smart_mode)
+                        }
+                        // switching smart mode off leaves other modes be
+                        None => {
+                            if let ControlMode::Smart(_) = config.mode {
+                                config.mode = ControlMode::Curves;
+                            }
+                        }
+                    }
+                });
+                match applied {
+                    Ok(()) => OTW::serialised_vec(
+                        seq,
+                        Msg::Applied,
+                        DataRef::Applied(&ConfigPart::SmartMode),
+                    ),
+                    Err(e) => error(e),
+                }
+            }
+            (Msg::UploadControlMode, Data::ControlMode(mode)) => {
+                match self.apply(|config| config.mode = mode.into()) {
+                    Ok(()) => OTW::serialised_vec(
+                        seq,
+                        Msg::Applied,
+                        DataRef::Applied(&ConfigPart::ControlMode),
+                    ),
+                    Err(e) => error(e),
+                }
+            }
+            (Msg::BeginTransfer, Data::Transfer(transfer)) => {
+                match self.transfer.begin(transfer) {
+                    Ok(()) => OTW::serialised_vec(
+                        seq,
+                        Msg::Result,
+                        DataRef::Result(&ok),
+                    ),
+                    Err(e) => error(e),
+                }
+            }
+            (Msg::Chunk, Data::Chunk(chunk)) => {
+                match self.transfer.chunk(&chunk) {
+                    Ok(received) => OTW::serialised_vec(
+                        seq,
+                        Msg::Received,
+                        DataRef::Received(&received),
+                    ),
+                    Err(e) => error(e),
+                }
+            }
+            (Msg::CommitTransfer, _) => match self.commit_transfer() {
+                Ok(()) => {
+                    OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+                }
+                Err(e) => error(e),
+            },
+            (Msg::GetChunk, Data::Received(received)) => {
+                let Some(payload) = &self.download else {
+                    return error(Error::NoTransfer);
+                };
+                match otw::chunks(payload).find(|c| c.offset == received) {
+                    Some(chunk) => OTW::serialised_vec(
+                        seq,
+                        Msg::Chunk,
+                        DataRef::Chunk(&chunk),
+                    ),
+                    None => error(Error::TransferOffset),
+                }
+            }
+            (Msg::AbortTransfer, _) => {
+                self.transfer.abort();
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::SetOverride, Data::Override(held)) => {
+                let held = Override::from(held);
+                if !held.is_valid() {
+                    return error(Error::InvalidOverride);
+                }
+                let until =
+                    Instant::now() + Duration::from_secs(held.timeout_s.into());
+                self.overrides[held.id.index()] =
+                    Some((held.duty_percent, until));
+                self.update_duties(Duration::ZERO);
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            (Msg::HostDuties, Data::Duties(duties)) => {
+                match self
+                    .controller
+                    .set_host_duties(&self.config, duties.into())
+                {
+                    Ok(()) => {
+                        self.update_duties(Duration::ZERO);
+                        OTW::serialised_vec(
+                            seq,
+                            Msg::Result,
+                            DataRef::Result(&ok),
+                        )
+                    }
+                    Err(e) => error(e),
+                }
+            }
+            (Msg::ClearOverride, Data::Id(id)) => {
+                self.overrides[id.index()] = None;
+                self.update_duties(Duration::ZERO);
+                OTW::serialised_vec(seq, Msg::Result, DataRef::Result(&ok))
+            }
+            _ => error(Error::InvalidMsgDataPair),
+        }
+    }
+
+    /// Runs with the config changed by `change`, as long as the whole
+    /// config stays valid.
+    fn apply(
+        &mut self,
+        change: impl FnOnce(&mut Config),
+    ) -> opilio_lib::Result<()> {
+        let mut config = self.config.clone();
+        change(&mut config);
+        if !config.is_valid() {
+            return Err(Error::InvalidConfig);
+        }
+        self.config = config;
+        self.update_duties(Duration::ZERO);
+        Ok(())
+    }
+
+    /// Announces `wire` as a transfer for the host to fetch with
+    /// [`Msg::GetChunk`].
+    fn begin_download(
+        &mut self,
+        seq: u16,
+        wire: &WireConfig,
+    ) -> opilio_lib::Result<Vec<u8, MAX_SERIAL_DATA_SIZE>> {
+        let payload = wire.to_vec()?;
+        let transfer = Transfer::new(TransferKind::Config, &payload)?;
+        self.download = Some(payload);
+        OTW::serialised_vec(
+            seq,
+            Msg::BeginTransfer,
+            DataRef::Transfer(&transfer),
+        )
+    }
+
+    /// Applies the payload of a completed transfer.
+    fn commit_transfer(&mut self) -> opilio_lib::Result<()> {
+        let config = match self.transfer.commit()? {
+            // sent again as the ack got lost, applied already
+            None => return Ok(()),
+            Some((TransferKind::Config, payload)) => {
+                Config::from(WireConfig::from_bytes(payload)?)
+            }
+        };
+        self.apply(|current| *current = config)
+    }
+
+    /// Builds the next pushed sample once it is due.
+    pub fn due_sample(
+        &mut self,
+        now: Instant,
+    ) -> Option<opilio_lib::Result<Vec<u8, MAX_SERIAL_DATA_SIZE>>> {
+        let stats = self.stats();
+        let subscription = self.subscription.as_mut()?;
+        if now < subscription.due {
+            return None;
+        }
+        // a late sample does not make the following ones come early
+        subscription.due = now.max(subscription.due) + subscription.interval;
+        let sample = Sample {
+            index: subscription.index,
+            stats,
+        };
+        subscription.index = subscription.index.wrapping_add(1);
+        if self.dropped_samples > 0 {
+            self.dropped_samples -= 1;
+            return None;
+        }
+        // pushed samples are not replies, no request to echo the seq of
+        Some(match self.host_protocol {
+            ENVELOPE_PROTOCOL.. => OTW::serialised_vec(
+                UNCORRELATED_SEQ,
+                Msg::StatsSample,
+                DataRef::FixedSample(&FixedSample::from(&sample)),
+            ),
+            _ => OTW::serialised_vec(
+                UNCORRELATED_SEQ,
+                Msg::StatsSample,
+                DataRef::Sample(&sample),
+            ),
+        })
+    }
+
+    /// Serves requests until the transport fails.
+    pub fn serve(&mut self, transport: &mut impl Transport) -> Result<()> {
+        let mut buffer = [0; MAX_FRAME_SIZE];
+        let mut decoder = FrameDecoder::new();
+        let mut last_step = Instant::now();
+        loop {
+            let timeout = match self.subscription {
+                Some(ref subscription) => subscription
+                    .due
+                    .saturating_duration_since(Instant::now())
+                    .min(IDLE_TICK),
+                None => IDLE_TICK,
+            };
+            let n = transport.receive(&mut buffer, timeout)?;
+            self.step(last_step.elapsed());
+            last_step = Instant::now();
+
+            for byte in &buffer[..n] {
+                let reply = match decoder.push(*byte) {
+                    None => continue,
+                    Some(Ok(request)) => self.handle(request),
+                    Some(Err(e)) => {
+                        // the sequence number is lost with the frame
+                        log::warn!("received a bad frame: {e}");
+                        OTW::serialised_vec(
+                            UNCORRELATED_SEQ,
+                            Msg::Result,
+                            DataRef::Result(&Response::Error(e)),
+                        )
+                    }
+                };
+                match reply.and_then(|reply| encode_frame(&reply)) {
+                    Ok(frame) => transport.send(&frame)?,
+                    Err(e) => log::error!("failed to serialise reply: {e}"),
+                }
+            }
+            while let Some(sample) = self.due_sample(Instant::now()) {
+                match sample.and_then(|sample| encode_frame(&sample)) {
+                    Ok(frame) => transport.send(&frame)?,
+                    Err(e) => log::error!("failed to serialise sample: {e}"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Software stand-in for the Opilio controller, speaking the same OTW
+//! protocol as the firmware.
+
+mod emulator;
+pub mod thermal;
+
+pub use emulator::Emulator;
@@ -0,0 +1,75 @@
+#[cfg(unix)]
+use std::{env, path::PathBuf, time::Duration};
+
+use anyhow::Result;
+#[cfg(unix)]
+use anyhow::{anyhow, bail};
+#[cfg(unix)]
+use opilio_emulator::Emulator;
+#[cfg(unix)]
+use opilio_lib::{serial::PORT_OVERRIDE_ENV, transport::SerialTransport};
+#[cfg(unix)]
+use serialport::{SerialPort, TTYPort};
+
+#[cfg(unix)]
+const USAGE: &str = "Usage: opilio-emulator [--link <path>]
+
+Creates a pseudo terminal that behaves like an Opilio controller.
+  --link <path>  also create a symlink to the terminal at <path>";
+
+#[cfg(not(unix))]
+fn main() -> Result<()> {
+    anyhow::bail!("opilio-emulator needs pseudo terminal support (unix only)")
+}
+
+#[cfg(unix)]
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("info"),
+    )
+    .init();
+
+    let mut link = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--link" => {
+                link = Some(PathBuf::from(
+                    args.next().ok_or_else(|| anyhow!(USAGE))?,
+                ))
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => bail!("Unknown argument '{arg}'\n{USAGE}"),
+        }
+    }
+
+    let (mut master, slave) = TTYPort::pair()?;
+    master.set_timeout(Duration::from_millis(100))?;
+    let pty_name = slave
+        .name()
+        .ok_or_else(|| anyhow!("Pseudo terminal has no name"))?;
+
+    let port_name = match link {
+        Some(link) => {
+            if link.is_symlink() {
+                std::fs::remove_file(&link)?;
+            }
+            std::os::unix::fs::symlink(&pty_name, &link)?;
+            link.display().to_string()
+        }
+        None => pty_name,
+    };
+
+    println!("Emulating Opilio on {port_name}");
+    println!("Point the frontends at it with:");
+    println!("  export {PORT_OVERRIDE_ENV}={port_name}");
+
+    let mut transport =
+        SerialTransport::from_port(&port_name, Box::new(master));
+    // the slave end stays open so the master keeps working between clients
+    let _slave = slave;
+    Emulator::default().serve(&mut transport)
+}
@@ -0,0 +1,76 @@
+//! A lumped thermal model of a water loop, good enough to make the emulated
+//! fans and temperatures react the way a real system does.
+
+use std::time::Duration;
+
+/// Heat the CPU/GPU dumps into the loop.
+const DEFAULT_HEAT_LOAD_W: f32 = 150.0;
+/// Roughly one litre of coolant plus blocks and radiator metal.
+const LOOP_HEAT_CAPACITY_J_PER_C: f32 = 5000.0;
+/// Radiator dissipation with the fans stopped.
+const PASSIVE_W_PER_C: f32 = 3.0;
+/// Extra radiator dissipation per fan at full speed.
+const FAN_W_PER_C: f32 = 10.0;
+/// Heat carried by the coolant flow at full pump speed.
+const FLOW_W_PER_C: f32 = 60.0;
+
+const PUMP_MAX_RPM: f32 = 4200.0;
+const FAN_MAX_RPM: f32 = 1600.0;
+/// Time constant of a fan spinning up or down.
+const RPM_TIME_CONSTANT_S: f32 = 0.8;
+
+#[derive(Debug, Clone)]
+pub struct ThermalModel {
+    pub heat_load_w: f32,
+    pub ambient_temp: f32,
+    pub coolant_temp: f32,
+    pub coolant_out_temp: f32,
+    /// Duty in percent for the pump followed by the three fans.
+    pub duties: [f32; 4],
+    /// Measured speed for the pump followed by the three fans.
+    pub rpms: [f32; 4],
+}
+
+impl Default for ThermalModel {
+    fn default() -> Self {
+        Self::new(22.0)
+    }
+}
+
+impl ThermalModel {
+    pub fn new(ambient_temp: f32) -> Self {
+        Self {
+            heat_load_w: DEFAULT_HEAT_LOAD_W,
+            ambient_temp,
+            coolant_temp: ambient_temp,
+            coolant_out_temp: ambient_temp,
+            duties: [0.0; 4],
+            rpms: [0.0; 4],
+        }
+    }
+
+    /// Advances the model by `dt` using the current duties.
+    pub fn step(&mut self, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        let flow = 0.3 + 0.7 * (self.duties[0] / 100.0);
+        let airflow: f32 = self.duties[1..].iter().map(|d| d / 100.0).sum();
+
+        let delta = self.coolant_temp - self.ambient_temp;
+        let dissipated = (PASSIVE_W_PER_C + FAN_W_PER_C * airflow) * delta;
+        let dissipated = dissipated * flow;
+
+        self.coolant_temp +=
+            (self.heat_load_w - dissipated) * dt / LOOP_HEAT_CAPACITY_J_PER_C;
+        self.coolant_out_temp =
+            self.coolant_temp - dissipated / (FLOW_W_PER_C * flow);
+
+        let max_rpms = [PUMP_MAX_RPM, FAN_MAX_RPM, FAN_MAX_RPM, FAN_MAX_RPM];
+        let alpha = (dt / RPM_TIME_CONSTANT_S).min(1.0);
+        for ((rpm, duty), max) in
+            self.rpms.iter_mut().zip(self.duties).zip(max_rpms)
+        {
+            let target = max * duty.clamp(0.0, 100.0) / 100.0;
+            *rpm += (target - *rpm) * alpha;
+        }
+    }
+}
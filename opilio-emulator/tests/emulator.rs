@@ -0,0 +1,453 @@
+use std::{thread, time::Duration};
+
+use opilio_emulator::Emulator;
+use opilio_lib::{
+    curve,
+    error::{ClientError, Error},
+    otw::TransferKind,
+    serial::{DeviceError, OpilioDevice, OpilioSerialDevice},
+    transport::{LoopbackTransport, SerialTransport},
+    wire::{ConfigLayout, FixedDuties, WireConfig},
+    Compatibility, Config, ControlMode, Curve, Data, DataRef, FixedMode,
+    HostControl, Id, Msg, Response, SmartMode, TempSource,
+    MAX_SERIAL_DATA_SIZE, OTW, PROTOCOL_VERSION,
+};
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+fn connect() -> OpilioDevice<LoopbackTransport> {
+    connect_to(Emulator::default())
+}
+
+fn connect_to(mut emulator: Emulator) -> OpilioDevice<LoopbackTransport> {
+    let (host, mut device) = LoopbackTransport::pair();
+    thread::spawn(move || emulator.serve(&mut device));
+    let mut client = OpilioDevice::with_transport("emulator", host);
+    client.set_timeout(TIMEOUT);
+    client
+}
+
+#[test]
+fn should_answer_ping_with_sleep_time() {
+    let mut client = connect();
+    assert_eq!(
+        client.ping().unwrap(),
+        Config::default().general.sleep_after
+    );
+}
+
+#[test]
+fn should_report_its_version() {
+    let mut client = connect();
+    let version = client.handshake().unwrap();
+    assert_eq!(version, Emulator::default().version());
+    assert_eq!(version.compatibility(), Compatibility::Full);
+}
+
+#[test]
+fn should_keep_uploaded_config_until_reload() {
+    let mut client = connect();
+    let mut config = Config::default();
+    config.general.sleep_after = 120;
+
+    client.upload_config(config.clone()).unwrap();
+    assert_eq!(client.get_config().unwrap(), config);
+    assert_eq!(client.ping().unwrap(), 120);
+
+    client.reload().unwrap();
+    assert_eq!(client.get_config().unwrap(), Config::default());
+
+    client.upload_config(config.clone()).unwrap();
+    client.save_config().unwrap();
+    client.reload().unwrap();
+    assert_eq!(client.get_config().unwrap(), config);
+}
+
+#[test]
+fn should_read_back_the_uploaded_config_exactly() {
+    let mut client = connect();
+    let mut config = Config::default();
+    config.settings[1].curve =
+        Curve::from_slice(&[(20.3, 37.3), (40.0, 100.0)]).unwrap();
+    config.mode = ControlMode::Fixed(FixedMode {
+        fan_duty: 37.3,
+        pump_duty: 80.0,
+    });
+
+    // values off the grid are snapped before they go out
+    client.upload_config(config.clone()).unwrap();
+    let device = client.get_config().unwrap();
+    assert_ne!(device, config);
+    assert_eq!(device, config.quantized());
+    assert_eq!(device.settings[1].curve[0], (20.3125, 37.3125));
+
+    // and a snapped config reads back as it was
+    client.upload_config(device.clone()).unwrap();
+    assert_eq!(client.get_config().unwrap(), device);
+}
+
+#[test]
+fn should_apply_config_parts_on_their_own() {
+    let mut client = connect();
+    let mut setting = client.get_fan_setting(Id::F2).unwrap();
+    assert_eq!(Some(&setting), Config::default().get(Id::F2));
+    setting.curve[0].1 = 25.0;
+    client.upload_fan_setting(setting.clone()).unwrap();
+    assert_eq!(client.get_config().unwrap().get(Id::F2), Some(&setting));
+
+    let mut general = client.get_general().unwrap();
+    general.sleep_after = 120;
+    client.upload_general(general.clone()).unwrap();
+    assert_eq!(client.ping().unwrap(), 120);
+
+    client.upload_smart_mode(None).unwrap();
+    assert_eq!(client.get_smart_mode().unwrap(), None);
+    assert_eq!(client.get_control_mode().unwrap(), ControlMode::Curves);
+    let fixed = ControlMode::Fixed(FixedMode::default());
+    client.upload_control_mode(&fixed).unwrap();
+    assert_eq!(client.get_control_mode().unwrap(), fixed);
+    // switching smart mode off leaves other modes be
+    client.upload_smart_mode(None).unwrap();
+    assert_eq!(client.get_control_mode().unwrap(), fixed);
+
+    // a part that would make the config invalid leaves it as it was
+    general.sleep_after = 1;
+    let err = client.upload_general(general).unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Device(DeviceError {
+            msg: Msg::UploadGeneral,
+            error: Error::InvalidConfig
+        })
+    ));
+    let config = client.get_config().unwrap();
+    assert_eq!(config.general.sleep_after, 120);
+    assert_eq!(config.mode, fixed);
+    assert_eq!(config.get(Id::F2), Some(&setting));
+}
+
+#[test]
+fn should_hold_overridden_channels() {
+    let mut client = connect();
+    client.handshake().unwrap();
+    client
+        .set_override(Id::F1, 100.0, Duration::from_secs(60))
+        .unwrap();
+    client
+        .set_override(Id::P1, 0.0, Duration::from_secs(60))
+        .unwrap();
+    let stats = client.get_stats().unwrap();
+    let fan = stats.overrides[Id::F1.index()].unwrap();
+    assert_eq!(fan.duty_percent, 100.0);
+    assert!(fan.timeout_s > 0 && fan.timeout_s <= 60);
+    assert_eq!(stats.overrides[Id::P1.index()].unwrap().duty_percent, 0.0);
+    assert_eq!(stats.overrides[Id::F2.index()], None);
+
+    client.clear_override(Id::P1).unwrap();
+    let stats = client.get_stats().unwrap();
+    assert_eq!(stats.overrides[Id::P1.index()], None);
+    assert!(stats.overrides[Id::F1.index()].is_some());
+
+    let err = client
+        .set_override(Id::F2, 150.0, Duration::from_secs(60))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Device(DeviceError {
+            msg: Msg::SetOverride,
+            error: Error::InvalidOverride
+        })
+    ));
+}
+
+#[test]
+fn should_fall_back_to_curves_when_the_host_goes_quiet() {
+    let mut emulator = Emulator::new(Config {
+        host_control: Some(HostControl { watchdog_s: 1 }),
+        ..Default::default()
+    });
+    let ask = |emulator: &mut Emulator, msg, data| {
+        let request =
+            OTW::from_bytes(&OTW::serialised_vec(1, msg, data).unwrap())
+                .unwrap();
+        OTW::from_bytes(&emulator.handle(request).unwrap())
+            .unwrap()
+            .data
+    };
+    let own = emulator.model_mut().duties;
+
+    let host = FixedDuties::from(&[100.0, 60.0, 60.0, 60.0]);
+    assert_eq!(
+        ask(&mut emulator, Msg::HostDuties, DataRef::Duties(&host)),
+        Data::Result(Response::Ok)
+    );
+    assert_eq!(emulator.model_mut().duties, [100.0, 60.0, 60.0, 60.0]);
+
+    emulator.step(Duration::from_millis(600));
+    assert_eq!(emulator.model_mut().duties, [100.0, 60.0, 60.0, 60.0]);
+    emulator.step(Duration::from_millis(600));
+    assert_eq!(emulator.model_mut().duties[0], own[0]);
+
+    // duties are refused unless the config asks for them
+    emulator = Emulator::default();
+    assert_eq!(
+        ask(&mut emulator, Msg::HostDuties, DataRef::Duties(&host)),
+        Data::Result(Response::Error(Error::NoHostControl))
+    );
+}
+
+#[test]
+fn should_take_configs_in_chunks() {
+    let mut emulator = Emulator::default();
+    // the first chunk gets lost for good, the transfer is aborted
+    emulator.inject_fault(Msg::Chunk, Error::SerialRead);
+    let mut client = connect_to(emulator);
+
+    let mut config = Config::default();
+    config.general.sleep_after = 300;
+    let wire = WireConfig::new(&config, ConfigLayout::CURRENT).unwrap();
+    let payload = wire.to_vec().unwrap();
+
+    let err = client.transfer(TransferKind::Config, &payload).unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Device(DeviceError {
+            msg: Msg::Chunk,
+            error: Error::SerialRead
+        })
+    ));
+    assert_eq!(client.get_config().unwrap(), Config::default());
+
+    client.transfer(TransferKind::Config, &payload).unwrap();
+    assert_eq!(client.get_config().unwrap(), config);
+    assert_eq!(client.ping().unwrap(), 300);
+}
+
+#[test]
+fn should_hand_out_large_configs_in_chunks() {
+    // far outside what validation allows, but it takes several chunks
+    let mut config = Config {
+        mode: ControlMode::Curves,
+        ..Default::default()
+    };
+    for setting in config.settings.iter_mut() {
+        setting.curve.clear();
+        for i in 0..curve::MAX_CURVE_POINTS {
+            let i = i as f32;
+            setting.curve.push((1e5 + i, 1e5 + i)).unwrap();
+        }
+    }
+    let wire = WireConfig::new(&config, ConfigLayout::CURRENT).unwrap();
+    assert!(wire.to_vec().unwrap().len() > MAX_SERIAL_DATA_SIZE);
+
+    let mut client = connect_to(Emulator::new(config.clone()));
+    assert_eq!(client.get_config().unwrap(), config);
+}
+
+#[test]
+fn should_report_device_errors() {
+    let mut emulator = Emulator::default();
+    emulator.inject_fault(Msg::SaveConfig, Error::FlashWrite);
+    let mut client = connect_to(emulator);
+
+    let err = client.save_config().unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Device(DeviceError {
+            msg: Msg::SaveConfig,
+            error: Error::FlashWrite
+        })
+    ));
+    assert_eq!(
+        err.to_string(),
+        "Opilio could not save the config, failed to write settings to flash"
+    );
+    // only the next command fails
+    client.save_config().unwrap();
+
+    let config = Config {
+        mode: ControlMode::Smart(SmartMode {
+            pump_duty: 10.0,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let err = client.upload_config(config).unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Device(DeviceError {
+            error: Error::InvalidConfig,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn should_push_stats_while_subscribed() {
+    let mut client = connect();
+
+    client.subscribe_stats(Duration::from_millis(100)).unwrap();
+    let samples: Vec<_> = client.stats_stream().take(3).collect();
+    for (index, sample) in samples.into_iter().enumerate() {
+        let sample = sample.unwrap();
+        assert_eq!(sample.index, index as u32);
+        assert_eq!(sample.missed, 0);
+    }
+    // requests still get their replies in between samples
+    assert_eq!(client.get_config().unwrap(), Config::default());
+
+    client.unsubscribe_stats().unwrap();
+    assert!(client.stats_stream().next().is_none());
+    assert!(matches!(
+        client.next_stats(),
+        Err(ClientError::NotSubscribed)
+    ));
+}
+
+#[test]
+fn should_report_gaps_in_the_stats_stream() {
+    let mut emulator = Emulator::default();
+    emulator.drop_samples(2);
+    let mut client = connect_to(emulator);
+
+    client.subscribe_stats(Duration::from_millis(100)).unwrap();
+    let sample = client.next_stats().unwrap();
+    assert_eq!(sample.index, 2);
+    assert_eq!(sample.missed, 2);
+    let sample = client.next_stats().unwrap();
+    assert_eq!(sample.missed, 0);
+}
+
+#[test]
+fn should_refuse_a_too_short_stats_interval() {
+    let mut client = connect();
+    let err = client
+        .subscribe_stats(Duration::from_millis(10))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Device(DeviceError {
+            msg: Msg::SubscribeStats,
+            error: Error::InvalidInterval
+        })
+    ));
+    assert!(!client.is_subscribed());
+}
+
+#[test]
+fn should_follow_the_host_temperature() {
+    let mut config = Config {
+        mode: ControlMode::Curves,
+        ..Default::default()
+    };
+    let mut setting = config.get(Id::F1).unwrap().clone();
+    setting.source = TempSource::Host;
+    config.set(setting);
+    let mut emulator = Emulator::new(config);
+    // the host has not said anything yet, the fan runs flat out to be safe
+    assert_eq!(emulator.model_mut().duties[1], 100.0);
+
+    let request = OTW::from_bytes(
+        &OTW::serialised_vec(1, Msg::HostTemp, DataRef::Temp(&25.0)).unwrap(),
+    )
+    .unwrap();
+    emulator.handle(request).unwrap();
+    assert_eq!(emulator.model_mut().duties[1], 0.0);
+    // other channels still follow the coolant
+    assert_eq!(emulator.model_mut().duties[2], 0.0);
+}
+
+#[test]
+fn should_answer_old_hosts_in_the_old_layout() {
+    let mut emulator = Emulator::default();
+    let mut ask = |msg, data| {
+        let request =
+            OTW::from_bytes(&OTW::serialised_vec(1, msg, data).unwrap())
+                .unwrap();
+        OTW::from_bytes(&emulator.handle(request).unwrap())
+            .unwrap()
+            .data
+    };
+
+    // hosts before the envelope ask without data and get a bare config
+    let Data::LegacyConfig(mut legacy) = ask(Msg::GetConfig, DataRef::Empty)
+    else {
+        panic!("expected a bare config");
+    };
+    let layout = ConfigLayout::CURRENT;
+    assert_eq!(
+        ask(Msg::GetConfig, layout.request()),
+        Data::Config(WireConfig::new(&Config::default(), layout).unwrap())
+    );
+
+    legacy.general.sleep_after = 120;
+    assert_eq!(
+        ask(Msg::UploadConfig, DataRef::LegacyConfig(&legacy)),
+        Data::Result(Response::Ok)
+    );
+    assert_eq!(emulator.config(), &Config::from(legacy));
+}
+
+#[test]
+fn should_send_stats_in_fixed_point_to_new_hosts() {
+    let mut emulator = Emulator::default();
+    let mut ask = |msg, data| {
+        let request =
+            OTW::from_bytes(&OTW::serialised_vec(1, msg, data).unwrap())
+                .unwrap();
+        OTW::from_bytes(&emulator.handle(request).unwrap())
+            .unwrap()
+            .data
+    };
+
+    // hosts before the envelope do not announce a revision
+    ask(Msg::GetVersion, DataRef::Empty);
+    assert!(matches!(ask(Msg::GetStats, DataRef::Empty), Data::Stats(_)));
+
+    ask(Msg::GetVersion, DataRef::Protocol(&PROTOCOL_VERSION));
+    assert!(matches!(
+        ask(Msg::GetStats, DataRef::Empty),
+        Data::FixedStats(_)
+    ));
+    let layout = ConfigLayout::for_protocol(PROTOCOL_VERSION);
+    assert!(matches!(
+        ask(Msg::GetConfig, layout.request()),
+        Data::Config(WireConfig::V2(_))
+    ));
+}
+
+#[test]
+fn should_spin_up_fans_as_coolant_heats_up() {
+    let mut emulator = Emulator::default();
+    let idle = emulator.stats();
+    assert_eq!(idle.fan1_rpm, 0.0);
+
+    for _ in 0..600 {
+        emulator.step(Duration::from_secs(1));
+    }
+    let warm = emulator.stats();
+    assert!(warm.coolant_temp > idle.coolant_temp + 5.0);
+    assert!(warm.coolant_out_temp < warm.coolant_temp);
+    assert!(warm.pump1_rpm > 0.0);
+    assert!(warm.fan1_rpm > 0.0);
+    // smart mode keeps the loop below its upper limit
+    assert!(warm.coolant_temp < SmartMode::default().upper_temp);
+}
+
+#[cfg(unix)]
+#[test]
+fn should_serve_over_a_pseudo_terminal() {
+    use serialport::{SerialPort, TTYPort};
+
+    let (master, slave) = TTYPort::pair().unwrap();
+    let name = slave.name().unwrap();
+    thread::spawn(move || {
+        let _slave = slave;
+        let mut transport = SerialTransport::from_port("pty", Box::new(master));
+        Emulator::default().serve(&mut transport)
+    });
+
+    let mut client = OpilioSerialDevice::new(&name).unwrap();
+    client.set_timeout(TIMEOUT);
+    assert_eq!(client.get_config().unwrap(), Config::default());
+}
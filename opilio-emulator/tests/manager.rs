@@ -0,0 +1,165 @@
+use std::{
+    env, fs, process,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use opilio_emulator::Emulator;
+use opilio_lib::{
+    error::ClientError,
+    manager::{Bus, DeviceManager, Enumerator, ALIASES_FILE_NAME},
+    serial::{OpilioDevice, PortWithSerialNumber},
+    transport::LoopbackTransport,
+    Config,
+};
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Emulated devices that can be plugged in and out by the test.
+#[derive(Clone, Default)]
+struct FakeBus {
+    attached: Arc<Mutex<Vec<(PortWithSerialNumber, Config)>>>,
+}
+
+impl FakeBus {
+    fn plug(&self, port_name: &str, serial_number: &str, sleep_after: u32) {
+        let mut config = Config::default();
+        config.general.sleep_after = sleep_after;
+        let port = PortWithSerialNumber {
+            port_name: port_name.to_string(),
+            serial_number: Some(serial_number.to_string()),
+        };
+        self.attached.lock().unwrap().push((port, config));
+    }
+
+    fn unplug(&self, serial_number: &str) {
+        self.attached
+            .lock()
+            .unwrap()
+            .retain(|(p, _)| p.serial_number.as_deref() != Some(serial_number));
+    }
+}
+
+impl Enumerator for FakeBus {
+    fn ports(&mut self) -> Result<Vec<PortWithSerialNumber>, ClientError> {
+        let attached = self.attached.lock().unwrap();
+        Ok(attached.iter().map(|(p, _)| p.clone()).collect())
+    }
+}
+
+impl Bus for FakeBus {
+    type Transport = LoopbackTransport;
+
+    fn open(
+        &mut self,
+        port: &PortWithSerialNumber,
+    ) -> Result<OpilioDevice<LoopbackTransport>, ClientError> {
+        let config = self
+            .attached
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(p, _)| p == port)
+            .map(|(_, c)| c.clone())
+            .ok_or(ClientError::NotFound)?;
+        let (host, mut device) = LoopbackTransport::pair();
+        thread::spawn(move || Emulator::new(config).serve(&mut device));
+        let mut client = OpilioDevice::with_transport(&port.port_name, host);
+        client.set_timeout(TIMEOUT);
+        Ok(client)
+    }
+}
+
+#[test]
+fn should_find_devices_by_alias_serial_number_and_port() {
+    let bus = FakeBus::default();
+    bus.plug("/dev/ttyACM0", "A1", 60);
+    bus.plug("/dev/ttyACM1", "B2", 120);
+    let mut manager = DeviceManager::with_bus(bus);
+    manager.set_alias("B2", "gpu loop");
+
+    let devices = manager.devices().unwrap();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].alias, None);
+    assert_eq!(devices[1].alias.as_deref(), Some("gpu loop"));
+    assert_eq!(devices[1].to_string(), "gpu loop (/dev/ttyACM1)");
+
+    assert_eq!(manager.get("gpu loop").unwrap().ping().unwrap(), 120);
+    assert_eq!(manager.get("A1").unwrap().ping().unwrap(), 60);
+    assert_eq!(manager.get("/dev/ttyACM1").unwrap().ping().unwrap(), 120);
+    assert!(matches!(manager.get("nope"), Err(ClientError::NotFound)));
+}
+
+#[test]
+fn should_take_the_only_device_or_the_named_one() {
+    let bus = FakeBus::default();
+    let mut manager = DeviceManager::with_bus(bus.clone());
+    assert!(matches!(manager.take(None), Err(ClientError::NotFound)));
+
+    bus.plug("/dev/ttyACM0", "A1", 60);
+    let (id, mut device) = manager.take(None).unwrap();
+    assert_eq!(id.key(), "A1");
+    assert_eq!(device.ping().unwrap(), 60);
+
+    bus.plug("/dev/ttyACM1", "B2", 120);
+    manager.set_alias("B2", "gpu loop");
+    match manager.take(None) {
+        Err(ClientError::Ambiguous(names)) => {
+            assert_eq!(names, ["A1 (/dev/ttyACM0)", "gpu loop (/dev/ttyACM1)"])
+        }
+        other => panic!("expected several devices, got {:?}", other.err()),
+    }
+    let (id, mut device) = manager.take(Some("gpu loop")).unwrap();
+    assert_eq!(id.key(), "B2");
+    assert_eq!(device.ping().unwrap(), 120);
+}
+
+#[test]
+fn should_load_aliases_from_a_file() {
+    let dir = env::temp_dir().join(format!("opilio-aliases-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(ALIASES_FILE_NAME);
+    let mut manager = DeviceManager::with_bus(FakeBus::default());
+
+    // no file, no aliases
+    manager.load_aliases(&path).unwrap();
+    assert!(manager.aliases().is_empty());
+
+    fs::write(&path, r#"{"A1": "cpu loop"}"#).unwrap();
+    manager.load_aliases(&path).unwrap();
+    assert_eq!(manager.aliases()["A1"], "cpu loop");
+
+    fs::write(&path, "[]").unwrap();
+    assert!(manager.load_aliases(&path).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn should_health_check_every_device() {
+    let bus = FakeBus::default();
+    bus.plug("/dev/ttyACM0", "A1", 60);
+    bus.plug("/dev/ttyACM1", "B2", 120);
+    let mut manager = DeviceManager::with_bus(bus.clone());
+
+    let checks = manager.health_check().unwrap();
+    let pongs: Vec<_> = checks
+        .iter()
+        .map(|(id, r)| (id.key(), *r.as_ref().unwrap()))
+        .collect();
+    assert_eq!(pongs, [("A1", 60), ("B2", 120)]);
+
+    // replugged under another name, still the same device
+    bus.unplug("A1");
+    bus.plug("/dev/ttyACM2", "A1", 60);
+    let checks = manager.health_check().unwrap();
+    assert_eq!(checks.len(), 2);
+    assert_eq!(checks[1].0.port.port_name, "/dev/ttyACM2");
+    assert_eq!(*checks[1].1.as_ref().unwrap(), 60);
+
+    bus.unplug("B2");
+    let checks = manager.health_check().unwrap();
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0].0.key(), "A1");
+}
@@ -0,0 +1,904 @@
+//! Request/response client for the OTW protocol. Every request and reply is
+//! encoded and checked here once, tokio based frontends await it directly
+//! and [`crate::serial::OpilioDevice`] drives it over a blocking
+//! [`crate::transport::Transport`].
+extern crate std;
+
+use std::{
+    boxed::Box,
+    string::String,
+    time::{Duration, Instant},
+    vec,
+};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "tokio")]
+use tokio_serial::{DataBits, SerialPortBuilderExt, SerialStream};
+
+#[cfg(feature = "tokio")]
+use crate::transport::{self, SerialTransport};
+use crate::{
+    control::Duties,
+    error::{ClientError, Error},
+    otw::{
+        self, Chunk, FrameDecoder, Reassembly, Transfer, TransferKind,
+        MAX_FRAME_SIZE, MAX_TRANSFER_SIZE,
+    },
+    serial::{
+        compatibility_warning, ConnectionState, DeviceError, RetryPolicy,
+        SERIAL_TIMEOUT_MS,
+    },
+    stream::{Samples, StreamedStats},
+    transport::AsyncTransport,
+    wire::{
+        ConfigLayout, ControlModeV2, FanSettingV2, FixedDuties, FixedOverride,
+        SmartModeV2, WireConfig,
+    },
+    Compatibility, Config, ConfigPart, ControlMode, Data, DataRef, FanSetting,
+    GeneralConfig, Id, Msg, Override, Response, SmartMode, Stats, Version, OTW,
+    PROTOCOL_VERSION,
+};
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+/// Request/response client for the OTW protocol over any
+/// [`AsyncTransport`].
+///
+/// Every request is bounded by the timeout and can be cancelled by dropping
+/// its future, a reply that arrives afterwards is recognised by its
+/// sequence number and dropped by the next request.
+pub struct AsyncOpilioDevice<T> {
+    name: String,
+    transport: T,
+    decoder: FrameDecoder,
+    timeout: Duration,
+    retry: RetryPolicy,
+    state: ConnectionState,
+    on_state_change: Option<Box<dyn FnMut(ConnectionState) + Send>>,
+    seq: u16,
+    version: Option<Version>,
+    samples: Samples,
+}
+
+/// The usual way to talk to an Opilio, over its USB serial port.
+#[cfg(feature = "tokio")]
+pub type AsyncOpilioSerialDevice = AsyncOpilioDevice<AsyncSerialTransport>;
+
+impl<T> std::fmt::Debug for AsyncOpilioDevice<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncOpilioSerial")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncOpilioDevice<AsyncSerialTransport> {
+    /// Opens the port and checks the device speaks a compatible protocol.
+    /// Failed requests are retried with the default [`RetryPolicy`], the
+    /// port is looked up again if the device is replugged. Needs to be
+    /// called from within a tokio runtime.
+    pub async fn new(port_name: &str) -> Result<Self> {
+        let transport = AsyncSerialTransport::open(port_name)?;
+        let mut device = Self::with_transport(port_name, transport);
+        device.set_retry_policy(RetryPolicy::default());
+        device.handshake().await?;
+        Ok(device)
+    }
+
+    /// Port the device is attached to right now, it may differ from
+    /// [`AsyncOpilioDevice::name`] after a reconnect.
+    pub fn port_name(&self) -> &str {
+        &self.transport.name
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.transport.serial_number.as_deref()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncOpilioDevice<StreamTransport<S>> {
+    /// Wraps an open stream, requests are not retried until a
+    /// [`RetryPolicy`] is set.
+    pub fn with_io(name: impl Into<String>, io: S) -> Self {
+        Self::with_transport(name, StreamTransport::new(io))
+    }
+}
+
+impl<T: AsyncTransport> AsyncOpilioDevice<T> {
+    /// Wraps an open link, requests are not retried until a
+    /// [`RetryPolicy`] is set.
+    pub fn with_transport(name: impl Into<String>, transport: T) -> Self {
+        Self {
+            name: name.into(),
+            transport,
+            decoder: FrameDecoder::new(),
+            timeout: Duration::from_millis(SERIAL_TIMEOUT_MS),
+            retry: RetryPolicy::NONE,
+            state: ConnectionState::Connected,
+            on_state_change: None,
+            seq: 0,
+            version: None,
+            samples: Samples::default(),
+        }
+    }
+
+    pub(crate) fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// How long to wait for the device to answer a request.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// What to do when a request fails or the link goes away.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Calls `f` whenever [`AsyncOpilioDevice::state`] changes, also while a
+    /// request is still being retried.
+    pub fn on_state_change(
+        &mut self,
+        f: impl FnMut(ConnectionState) + Send + 'static,
+    ) {
+        self.on_state_change = Some(Box::new(f));
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        if self.state == state {
+            return;
+        }
+        log::info!("{} {state}", self.name);
+        self.state = state;
+        if let Some(ref mut f) = self.on_state_change {
+            f(state);
+        }
+    }
+
+    /// Re-establishes the link, the device may have been reset so its
+    /// version is asked for again on the next
+    /// [`AsyncOpilioDevice::version`] and a stats subscription has to be
+    /// renewed.
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let result = self.transport.reconnect().await;
+        self.decoder.reset();
+        self.version = None;
+        self.samples.unsubscribe();
+        self.set_state(match result {
+            Ok(()) => ConnectionState::Connected,
+            Err(_) => ConnectionState::Disconnected,
+        });
+        result
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version reported by the device, only asked for once.
+    pub async fn version(&mut self) -> Result<Version> {
+        match self.version {
+            Some(version) => Ok(version),
+            None => self.handshake().await,
+        }
+    }
+
+    /// Tells the device which protocol revision this library speaks and
+    /// asks for its version, refusing to carry on if its revision can not
+    /// be understood.
+    pub async fn handshake(&mut self) -> Result<Version> {
+        let response = self
+            .request(Msg::GetVersion, DataRef::Protocol(&PROTOCOL_VERSION))
+            .await
+            .map_err(|e| ClientError::NoVersion(e.into()))?;
+        let version = match response.data {
+            Data::Version(v) => check_version(v)?,
+            _ => return Err(unexpected(Msg::GetVersion, &response)),
+        };
+        self.version = Some(version);
+        Ok(version)
+    }
+
+    pub async fn ping(&mut self) -> Result<u32> {
+        let response = self.request(Msg::Ping, DataRef::Empty).await?;
+        match response.data {
+            Data::Pong(p) => Ok(p),
+            _ => Err(unexpected(Msg::Ping, &response)),
+        }
+    }
+
+    pub async fn get_stats(&mut self) -> Result<Stats> {
+        let response = self.request(Msg::GetStats, DataRef::Empty).await?;
+        match response.data {
+            Data::Stats(s) => Ok(s),
+            Data::FixedStats(s) => Ok(s.into()),
+            _ => Err(unexpected(Msg::GetStats, &response)),
+        }
+    }
+
+    /// Sends `config` in the newest layout the device understands, see
+    /// [`crate::wire`]. Values are snapped to the grid first, see
+    /// [`Config::quantized`].
+    pub async fn upload_config(&mut self, config: Config) -> Result<()> {
+        let layout = self.config_layout().await?;
+        let wire = WireConfig::new(&config.quantized(), layout)?;
+        match self.command(Msg::UploadConfig, wire.data()).await {
+            // too large for a single message
+            Err(ClientError::Protocol(Error::Serialize)) => {
+                self.transfer(TransferKind::Config, &wire.to_vec()?).await
+            }
+            result => result,
+        }
+    }
+
+    pub async fn save_config(&mut self) -> Result<()> {
+        self.command(Msg::SaveConfig, DataRef::Empty).await
+    }
+
+    pub async fn get_config(&mut self) -> Result<Config> {
+        let layout = self.config_layout().await?;
+        let response = self.request(Msg::GetConfig, layout.request()).await?;
+        let Data::Transfer(transfer) = response.data else {
+            return received_config(response);
+        };
+        // too large for a single message, fetched chunk by chunk
+        let mut download = Download::new(transfer)?;
+        while let Some(received) = download.next_offset() {
+            let response = self
+                .request(Msg::GetChunk, DataRef::Received(&received))
+                .await?;
+            download.add(response)?;
+        }
+        download.config()
+    }
+
+    pub async fn reload(&mut self) -> Result<()> {
+        self.command(Msg::Reload, DataRef::Empty).await
+    }
+
+    /// Setting of the channel `id` the device is running with.
+    pub async fn get_fan_setting(&mut self, id: Id) -> Result<FanSetting> {
+        self.require(Msg::GetFanSetting).await?;
+        let response =
+            self.request(Msg::GetFanSetting, DataRef::Id(&id)).await?;
+        received(Msg::GetFanSetting, response, |data| match data {
+            Data::FanSetting(setting) => Some(setting.into()),
+            _ => None,
+        })
+    }
+
+    /// Replaces the setting of a single channel without sending the rest
+    /// of the config.
+    pub async fn upload_fan_setting(
+        &mut self,
+        setting: FanSetting,
+    ) -> Result<()> {
+        self.require(Msg::UploadFanSetting).await?;
+        let part = ConfigPart::FanSetting(setting.id);
+        let setting = FanSettingV2::from(&setting);
+        let response = self
+            .request(Msg::UploadFanSetting, DataRef::FanSetting(&setting))
+            .await?;
+        applied(Msg::UploadFanSetting, part, response)
+    }
+
+    pub async fn get_general(&mut self) -> Result<GeneralConfig> {
+        self.require(Msg::GetGeneral).await?;
+        let response = self.request(Msg::GetGeneral, DataRef::Empty).await?;
+        received(Msg::GetGeneral, response, |data| match data {
+            Data::General(general) => Some(general),
+            _ => None,
+        })
+    }
+
+    pub async fn upload_general(
+        &mut self,
+        general: GeneralConfig,
+    ) -> Result<()> {
+        self.require(Msg::UploadGeneral).await?;
+        let response = self
+            .request(Msg::UploadGeneral, DataRef::General(&general))
+            .await?;
+        applied(Msg::UploadGeneral, ConfigPart::General, response)
+    }
+
+    /// Smart mode settings, `None` while another mode is in control.
+    pub async fn get_smart_mode(&mut self) -> Result<Option<SmartMode>> {
+        self.require(Msg::GetSmartMode).await?;
+        let response = self.request(Msg::GetSmartMode, DataRef::Empty).await?;
+        received(Msg::GetSmartMode, response, |data| match data {
+            Data::SmartMode(smart_mode) => Some(smart_mode.map(Into::into)),
+            _ => None,
+        })
+    }
+
+    /// Switches to smart mode with `smart_mode`, or from it to the curves
+    /// with `None`.
+    pub async fn upload_smart_mode(
+        &mut self,
+        smart_mode: Option<SmartMode>,
+    ) -> Result<()> {
+        self.require(Msg::UploadSmartMode).await?;
+        let smart_mode = smart_mode.as_ref().map(SmartModeV2::from);
+        let response = self
+            .request(Msg::UploadSmartMode, DataRef::SmartMode(&smart_mode))
+            .await?;
+        applied(Msg::UploadSmartMode, ConfigPart::SmartMode, response)
+    }
+
+    pub async fn get_control_mode(&mut self) -> Result<ControlMode> {
+        self.require(Msg::GetControlMode).await?;
+        let response =
+            self.request(Msg::GetControlMode, DataRef::Empty).await?;
+        received(Msg::GetControlMode, response, |data| match data {
+            Data::ControlMode(mode) => Some(mode.into()),
+            _ => None,
+        })
+    }
+
+    /// Switches the mode of the whole config, see [`Msg::UploadControlMode`].
+    pub async fn upload_control_mode(
+        &mut self,
+        mode: &ControlMode,
+    ) -> Result<()> {
+        self.require(Msg::UploadControlMode).await?;
+        let mode = ControlModeV2::from(mode);
+        let response = self
+            .request(Msg::UploadControlMode, DataRef::ControlMode(&mode))
+            .await?;
+        applied(Msg::UploadControlMode, ConfigPart::ControlMode, response)
+    }
+
+    /// Sends `payload` in chunks the device acknowledges one by one, for
+    /// payloads too large for a single message. A transfer that fails
+    /// halfway is aborted.
+    pub async fn transfer(
+        &mut self,
+        kind: TransferKind,
+        payload: &[u8],
+    ) -> Result<()> {
+        self.require(Msg::BeginTransfer).await?;
+        let transfer = Transfer::new(kind, payload)?;
+        self.command(Msg::BeginTransfer, DataRef::Transfer(&transfer))
+            .await?;
+        let mut sent = self.send_chunks(payload).await;
+        if sent.is_ok() {
+            sent = self.command(Msg::CommitTransfer, DataRef::Empty).await;
+        }
+        if sent.is_err() {
+            if let Err(e) =
+                self.command(Msg::AbortTransfer, DataRef::Empty).await
+            {
+                log::warn!("failed to abort the transfer: {e}");
+            }
+        }
+        sent
+    }
+
+    async fn send_chunks(&mut self, payload: &[u8]) -> Result<()> {
+        for chunk in otw::chunks(payload) {
+            let response =
+                self.request(Msg::Chunk, DataRef::Chunk(&chunk)).await?;
+            check_received(&chunk, response)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the temperature for channels following
+    /// [`crate::TempSource::Host`], it has to be sent again within
+    /// [`crate::HOST_TEMP_TIMEOUT_S`].
+    pub async fn set_host_temp(&mut self, temp: f32) -> Result<()> {
+        self.require(Msg::HostTemp).await?;
+        self.command(Msg::HostTemp, DataRef::Temp(&temp)).await
+    }
+
+    /// Holds channel `id` at `duty_percent` for `timeout`, whatever its
+    /// curve or smart mode says. Active overrides show up in
+    /// [`Stats::overrides`].
+    pub async fn set_override(
+        &mut self,
+        id: Id,
+        duty_percent: f32,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.require(Msg::SetOverride).await?;
+        let held = FixedOverride::from(&Override {
+            id,
+            duty_percent,
+            timeout_s: timeout.as_secs().try_into().unwrap_or(u32::MAX),
+        });
+        self.command(Msg::SetOverride, DataRef::Override(&held))
+            .await
+    }
+
+    /// Hands channel `id` back to its curve before its override expires.
+    pub async fn clear_override(&mut self, id: Id) -> Result<()> {
+        self.require(Msg::ClearOverride).await?;
+        self.command(Msg::ClearOverride, DataRef::Id(&id)).await
+    }
+
+    /// Sends the duties while the config hands control to the host, see
+    /// [`crate::HostControl`]. The device goes back to its own curves if
+    /// they stop coming for longer than the watchdog.
+    pub async fn set_host_duties(&mut self, duties: Duties) -> Result<()> {
+        self.require(Msg::HostDuties).await?;
+        let duties = FixedDuties::from(&duties);
+        self.command(Msg::HostDuties, DataRef::Duties(&duties))
+            .await
+    }
+
+    /// Asks the device to push its stats every `interval` instead of
+    /// waiting for [`AsyncOpilioDevice::get_stats`].
+    pub async fn subscribe_stats(&mut self, interval: Duration) -> Result<()> {
+        self.require(Msg::SubscribeStats).await?;
+        let interval_ms = interval.as_millis().try_into().unwrap_or(u32::MAX);
+        self.command(Msg::SubscribeStats, DataRef::Interval(&interval_ms))
+            .await?;
+        self.samples.subscribe(interval);
+        Ok(())
+    }
+
+    pub async fn unsubscribe_stats(&mut self) -> Result<()> {
+        self.samples.unsubscribe();
+        self.command(Msg::Unsubscribe, DataRef::Empty).await
+    }
+
+    /// Whether samples are pushed, a reconnect ends the subscription.
+    pub fn is_subscribed(&self) -> bool {
+        self.samples.interval().is_some()
+    }
+
+    /// Waits for the next pushed sample, for up to twice the subscribed
+    /// interval.
+    pub async fn next_stats(&mut self) -> Result<StreamedStats> {
+        let deadline = Instant::now() + self.samples.patience(self.timeout)?;
+        loop {
+            if let Some(stats) = self.samples.pop() {
+                return Ok(stats);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.receive_samples(remaining).await? == 0 {
+                return Err(ClientError::Timeout(Msg::StatsSample));
+            }
+        }
+    }
+
+    /// Returns a sample that already arrived, without waiting for one.
+    pub async fn try_next_stats(&mut self) -> Result<Option<StreamedStats>> {
+        self.samples.patience(self.timeout)?;
+        if let Some(stats) = self.samples.pop() {
+            return Ok(Some(stats));
+        }
+        self.receive_samples(Duration::ZERO).await?;
+        Ok(self.samples.pop())
+    }
+
+    /// A link that went away ends the subscription, the next request
+    /// reconnects.
+    async fn receive_samples(&mut self, timeout: Duration) -> Result<usize> {
+        let mut buffer = vec![0; MAX_FRAME_SIZE];
+        let n = match self.transport.receive(&mut buffer, timeout).await {
+            Ok(n) => n,
+            Err(e) => {
+                if e.is_disconnected() {
+                    self.samples.unsubscribe();
+                    self.set_state(ConnectionState::Disconnected);
+                }
+                return Err(e);
+            }
+        };
+        self.samples.feed(
+            &mut self.decoder,
+            &buffer[..n],
+            |frame| match frame {
+                Ok(frame) => log::warn!("dropping stale {:?}", frame.msg),
+                Err(e) => log::warn!("dropping corrupt frame: {e}"),
+            },
+        );
+        Ok(n)
+    }
+
+    async fn config_layout(&mut self) -> Result<ConfigLayout> {
+        Ok(ConfigLayout::for_protocol(self.version().await?.protocol))
+    }
+
+    /// Fails with [`ClientError::Unsupported`] if the firmware predates
+    /// `msg`.
+    async fn require(&mut self, msg: Msg) -> Result<()> {
+        let version = self.version().await?;
+        if version.protocol < msg.protocol() {
+            return Err(ClientError::Unsupported { msg, version });
+        }
+        Ok(())
+    }
+
+    /// Sends a command the device acknowledges with a [`Response`], a
+    /// [`Response::Error`] is returned as a [`DeviceError`].
+    async fn command(&mut self, msg: Msg, data: DataRef<'_>) -> Result<()> {
+        let response = self.request(msg, data).await?;
+        acknowledged(msg, response)
+    }
+
+    /// Sends a request, retrying and reconnecting as allowed by the
+    /// [`RetryPolicy`].
+    async fn request(&mut self, msg: Msg, data: DataRef<'_>) -> Result<OTW> {
+        if self.state == ConnectionState::Disconnected {
+            // the device went away during an earlier request
+            self.reconnect_or_wait(1).await?;
+        }
+        let mut attempt = 0;
+        loop {
+            let err = match self.request_once(msg, data.clone()).await {
+                Ok(response) => {
+                    self.set_state(ConnectionState::Connected);
+                    return Ok(response);
+                }
+                Err(e) => e,
+            };
+            attempt += 1;
+            let Some(delay) = self.retry.retry_after(attempt, &err) else {
+                if err.is_disconnected() {
+                    self.set_state(ConnectionState::Disconnected);
+                }
+                return Err(err);
+            };
+            log::warn!("{msg:?} failed ({err}), retry {attempt}");
+            T::sleep(delay).await;
+            if err.is_disconnected() {
+                self.reconnect_or_wait(attempt).await?;
+            }
+        }
+    }
+
+    /// Keeps trying to reconnect until the retries run out.
+    async fn reconnect_or_wait(&mut self, mut attempt: u32) -> Result<()> {
+        loop {
+            self.set_state(ConnectionState::Reconnecting { attempt });
+            match self.reconnect().await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.retry.retries => return Err(e),
+                Err(e) => {
+                    log::warn!("reconnecting failed ({e}), retry {attempt}");
+                    attempt += 1;
+                    T::sleep(self.retry.delay(attempt)).await;
+                }
+            }
+        }
+    }
+
+    /// Sends one command and reads back the device's reply. Replies carrying
+    /// a different sequence number belong to an earlier request and are
+    /// dropped.
+    async fn request_once(
+        &mut self,
+        msg: Msg,
+        data: DataRef<'_>,
+    ) -> Result<OTW> {
+        self.seq = otw::next_seq(self.seq);
+        let seq = self.seq;
+        let cmd = OTW::serialised_frame(seq, msg, data)?;
+        log::debug!("sending {:?} #{} {:?}", msg, seq, cmd);
+        self.transport.send(&cmd).await?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = vec![0; MAX_FRAME_SIZE];
+        let mut pending = Pending::new(msg, seq);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let n = self.transport.receive(&mut buffer, remaining).await?;
+            if n == 0 {
+                return Err(pending.timed_out());
+            }
+            if let Some(response) =
+                pending.push(&mut self.decoder, &mut self.samples, &buffer[..n])
+            {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+/// Any tokio byte stream, e.g. a socket or one end of a
+/// [`tokio::io::duplex`]. It can not be reopened, so a lost link stays
+/// lost.
+#[cfg(feature = "tokio")]
+pub struct StreamTransport<S> {
+    io: S,
+}
+
+#[cfg(feature = "tokio")]
+impl<S> StreamTransport<S> {
+    pub fn new(io: S) -> Self {
+        Self { io }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncTransport for StreamTransport<S> {
+    async fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.io.write_all(bytes).await?;
+        Ok(())
+    }
+
+    async fn receive(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        receive(&mut self.io, buf, timeout).await
+    }
+
+    async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// USB CDC serial port driven by tokio, looked up again by its serial
+/// number when the device is replugged, see [`SerialTransport`].
+#[cfg(feature = "tokio")]
+pub struct AsyncSerialTransport {
+    name: String,
+    serial_number: Option<String>,
+    port: SerialStream,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncSerialTransport {
+    /// Needs to be called from within a tokio runtime.
+    pub fn open(port_name: &str) -> Result<Self> {
+        Ok(Self {
+            name: port_name.into(),
+            serial_number: transport::serial_number_of(port_name),
+            port: Self::open_port(port_name)?,
+        })
+    }
+
+    fn open_port(port_name: &str) -> Result<SerialStream> {
+        tokio_serial::new(port_name, SerialTransport::BAUD_RATE)
+            .data_bits(DataBits::Eight)
+            .open_native_async()
+            .map_err(|source| transport::open_error(port_name, source))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncTransport for AsyncSerialTransport {
+    async fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.port.write_all(bytes).await?;
+        Ok(())
+    }
+
+    async fn receive(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        receive(&mut self.port, buf, timeout).await
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        let port =
+            transport::find_again(&self.name, self.serial_number.as_deref())?;
+        self.port = Self::open_port(&port.port_name)?;
+        if port.port_name != self.name {
+            log::info!("Opilio moved from {} to {}", self.name, port.port_name);
+            self.name = port.port_name;
+        }
+        Ok(())
+    }
+
+    async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// Reads whatever is available, `0` when nothing arrived within `timeout`.
+#[cfg(feature = "tokio")]
+async fn receive(
+    io: &mut (impl AsyncRead + Unpin),
+    buf: &mut [u8],
+    timeout: Duration,
+) -> Result<usize> {
+    match tokio::time::timeout(timeout, io.read(buf)).await {
+        Err(_) => Ok(0),
+        Ok(Ok(0)) => Err(ClientError::Disconnected),
+        Ok(n) => Ok(n?),
+    }
+}
+
+/// A request waiting for its reply.
+struct Pending {
+    msg: Msg,
+    seq: u16,
+    stale: usize,
+    corrupt: Option<Error>,
+}
+
+impl Pending {
+    fn new(msg: Msg, seq: u16) -> Self {
+        Self {
+            msg,
+            seq,
+            stale: 0,
+            corrupt: None,
+        }
+    }
+
+    /// Feeds received bytes, returns the reply once it is complete. Replies
+    /// carrying a different sequence number are dropped, pushed samples are
+    /// queued in `samples`.
+    fn push(
+        &mut self,
+        decoder: &mut FrameDecoder,
+        samples: &mut Samples,
+        bytes: &[u8],
+    ) -> Option<OTW> {
+        let mut reply = None;
+        samples.feed(decoder, bytes, |frame| match frame {
+            Ok(response) if response.seq == self.seq => {
+                log::info!("Received {:?}", response);
+                reply = Some(response);
+            }
+            Ok(response) => {
+                log::warn!(
+                    "dropping stale {:?} #{}, waiting for #{}",
+                    response.msg,
+                    response.seq,
+                    self.seq
+                );
+                self.stale += 1;
+            }
+            Err(e) => {
+                log::warn!("dropping corrupt frame: {e}");
+                self.corrupt = Some(e);
+            }
+        });
+        reply
+    }
+
+    /// Why nothing usable came back in time.
+    fn timed_out(self) -> ClientError {
+        let Self {
+            msg,
+            seq,
+            stale,
+            corrupt,
+        } = self;
+        match corrupt {
+            Some(e) => e.into(),
+            None if stale > 0 => ClientError::StaleReplies { msg, seq, stale },
+            None => ClientError::Timeout(msg),
+        }
+    }
+}
+
+/// Refuses to carry on if the device's protocol revision can not be
+/// understood.
+fn check_version(version: Version) -> Result<Version> {
+    if version.compatibility() == Compatibility::Incompatible {
+        return Err(ClientError::Incompatible(version));
+    }
+    if let Some(warning) = compatibility_warning(&version) {
+        log::warn!("{warning}");
+    }
+    Ok(version)
+}
+
+/// Config in a reply to [`Msg::GetConfig`], in whatever layout it came.
+fn received_config(response: OTW) -> Result<Config> {
+    match response.data {
+        Data::LegacyConfig(config) => Ok(config.into()),
+        Data::Config(config) => Ok(config.into()),
+        _ => Err(unexpected(Msg::GetConfig, &response)),
+    }
+}
+
+/// Receiving end of a transfer the device began in reply to
+/// [`Msg::GetConfig`], see [`Msg::GetChunk`].
+struct Download {
+    reassembly: Reassembly<MAX_TRANSFER_SIZE>,
+    size: u32,
+    received: u32,
+}
+
+impl Download {
+    fn new(transfer: Transfer) -> Result<Self> {
+        let mut reassembly = Reassembly::new();
+        reassembly.begin(transfer)?;
+        Ok(Self {
+            reassembly,
+            size: transfer.size,
+            received: 0,
+        })
+    }
+
+    /// Bytes received so far to ask for the next chunk with, `None` once
+    /// the whole payload is in.
+    fn next_offset(&self) -> Option<u32> {
+        (self.received < self.size).then_some(self.received)
+    }
+
+    /// Adds the chunk the device answered [`Msg::GetChunk`] with.
+    fn add(&mut self, response: OTW) -> Result<()> {
+        let chunk = received(Msg::GetChunk, response, |data| match data {
+            Data::Chunk(chunk) => Some(chunk),
+            _ => None,
+        })?;
+        let received = self.reassembly.chunk(&chunk)?;
+        // nothing new would have us asking for the same chunk forever
+        if received <= self.received {
+            return Err(Error::TransferOffset.into());
+        }
+        self.received = received;
+        Ok(())
+    }
+
+    /// Checks the payload arrived intact and reads the config from it.
+    fn config(mut self) -> Result<Config> {
+        match self.reassembly.commit()? {
+            Some((TransferKind::Config, payload)) => {
+                Ok(WireConfig::from_bytes(payload)?.into())
+            }
+            None => Err(Error::NoTransfer.into()),
+        }
+    }
+}
+
+/// Reply to `msg` picked out of its data by `pick`, a [`Response::Error`]
+/// is returned as a [`DeviceError`].
+fn received<R>(
+    msg: Msg,
+    response: OTW,
+    pick: impl FnOnce(Data) -> Option<R>,
+) -> Result<R> {
+    let reply = response.msg;
+    match response.data {
+        Data::Result(Response::Error(error)) => {
+            Err(DeviceError { msg, error }.into())
+        }
+        data => pick(data).ok_or(ClientError::UnexpectedReply { msg, reply }),
+    }
+}
+
+/// Checks the device applied the `part` of the config sent with `msg`.
+fn applied(msg: Msg, part: ConfigPart, response: OTW) -> Result<()> {
+    received(msg, response, |data| match data {
+        Data::Applied(applied) if applied == part => Some(()),
+        _ => None,
+    })
+}
+
+/// Checks the device got the transfer up to the end of `chunk`.
+fn check_received(chunk: &Chunk, response: OTW) -> Result<()> {
+    let end = chunk.end();
+    received(Msg::Chunk, response, |data| match data {
+        Data::Received(received) if received == end => Some(()),
+        _ => None,
+    })
+}
+
+/// A [`Response::Error`] is returned as a [`DeviceError`].
+fn acknowledged(msg: Msg, response: OTW) -> Result<()> {
+    match response.data {
+        Data::Result(Response::Ok) => Ok(()),
+        Data::Result(Response::Error(error)) => {
+            Err(DeviceError { msg, error }.into())
+        }
+        _ => Err(unexpected(msg, &response)),
+    }
+}
+
+fn unexpected(msg: Msg, response: &OTW) -> ClientError {
+    ClientError::UnexpectedReply {
+        msg,
+        reply: response.msg,
+    }
+}
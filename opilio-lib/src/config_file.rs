@@ -0,0 +1,157 @@
+//! The JSON file frontends keep a [`Config`] in. Files carry the version of
+//! their layout, older ones are upgraded by a chain of migrations when
+//! loaded.
+extern crate std;
+
+use std::{
+    borrow::ToOwned,
+    fs, io,
+    path::{Path, PathBuf},
+    string::String,
+    vec::Vec,
+};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{error::ConfigFileError, Config};
+
+type Result<T> = std::result::Result<T, ConfigFileError>;
+
+/// Layout written by this version of the library, files without a
+/// `version` field are version `0`.
+pub const CONFIG_FILE_VERSION: u32 = 2;
+
+/// Upgrades a file from one layout version to the next.
+#[derive(Debug)]
+pub struct Migration {
+    /// Version upgraded from, to `from + 1`.
+    pub from: u32,
+    /// What changed, to tell the user.
+    pub description: &'static str,
+    apply: fn(&mut Map<String, Value>),
+}
+
+/// Every migration in order, each picks up where the one before left off.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "added general.led and general.buzzer, both on",
+        apply: add_led_and_buzzer,
+    },
+    Migration {
+        from: 1,
+        description: "replaced smart_mode with mode, curves while it was off",
+        apply: add_control_mode,
+    },
+];
+
+fn add_led_and_buzzer(file: &mut Map<String, Value>) {
+    if let Some(Value::Object(general)) = file.get_mut("general") {
+        for key in ["led", "buzzer"] {
+            general.entry(key).or_insert_with(|| "on".into());
+        }
+    }
+}
+
+fn add_control_mode(file: &mut Map<String, Value>) {
+    let mode = match file.remove("smart_mode").unwrap_or(Value::Null) {
+        Value::Null => "curves".into(),
+        smart_mode => {
+            Value::Object(Map::from_iter([("smart".into(), smart_mode)]))
+        }
+    };
+    file.insert("mode".into(), mode);
+}
+
+/// A config read from disk.
+#[derive(Debug)]
+pub struct Loaded {
+    pub config: Config,
+    /// Migrations that upgraded the file, oldest first.
+    pub migrations: Vec<&'static Migration>,
+    /// Copy of the file as it was before the migrations rewrote it.
+    pub backup: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    version: u32,
+    #[serde(flatten)]
+    config: &'a Config,
+}
+
+/// Reads the config at `path`, upgrading the file in place if it has an
+/// older layout. The original is kept next to it with a `.bak` suffix.
+pub fn load(path: &Path) -> Result<Loaded> {
+    let json = fs::read_to_string(path).map_err(io_error(path))?;
+    let (config, migrations) = from_json(&json)?;
+    let mut backup = None;
+    if !migrations.is_empty() {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".bak");
+        let bak = PathBuf::from(name);
+        fs::copy(path, &bak).map_err(io_error(&bak))?;
+        save(path, &config)?;
+        for migration in &migrations {
+            log::info!(
+                "migrated {}: {}",
+                path.display(),
+                migration.description
+            );
+        }
+        backup = Some(bak);
+    }
+    Ok(Loaded {
+        config,
+        migrations,
+        backup,
+    })
+}
+
+/// Writes `config` to `path` with the current layout version.
+pub fn save(path: &Path, config: &Config) -> Result<()> {
+    fs::write(path, to_json(config)?).map_err(io_error(path))
+}
+
+/// Parses a config file of any known layout, along with the migrations it
+/// took to get there. Values are snapped to the grid they are uploaded
+/// with, see [`Config::quantized`].
+pub fn from_json(json: &str) -> Result<(Config, Vec<&'static Migration>)> {
+    let mut file = match serde_json::from_str(json)? {
+        Value::Object(file) => file,
+        _ => return Err(ConfigFileError::Layout("expected an object")),
+    };
+    let version = match file.remove("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| v.try_into().ok())
+            .ok_or(ConfigFileError::Layout("version is not a number"))?,
+    };
+    if version > CONFIG_FILE_VERSION {
+        return Err(ConfigFileError::TooNew(version));
+    }
+    let migrations: Vec<_> =
+        MIGRATIONS.iter().filter(|m| m.from >= version).collect();
+    for migration in &migrations {
+        (migration.apply)(&mut file);
+    }
+    let config: Config = serde_json::from_value(Value::Object(file))?;
+    Ok((config.quantized(), migrations))
+}
+
+pub fn to_json(config: &Config) -> Result<String> {
+    let file = Versioned {
+        version: CONFIG_FILE_VERSION,
+        config,
+    };
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> ConfigFileError + '_ {
+    move |source| ConfigFileError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
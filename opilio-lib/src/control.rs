@@ -0,0 +1,238 @@
+//! Turns curves into duties over time, shared by the firmware and the host
+//! so simulations behave like the device.
+use core::time::Duration;
+
+use crate::{
+    error::Error, smart_duty_percent, ChannelMode, Config, ControlMode,
+    FanSetting, Id, PidMode, Result, Stats, MAX_DUTY_PERCENT,
+};
+
+/// Duty in percent for every channel, by [`crate::Id::index`].
+pub type Duties = [f32; 4];
+
+/// Keeps what a channel did last, to apply the hysteresis and ramp limits
+/// of its [`FanSetting`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DutyController {
+    /// Temperature the curve was last looked up at.
+    temp: Option<f32>,
+    /// Duty in percent last returned.
+    duty: Option<f32>,
+}
+
+impl DutyController {
+    /// Duty for `temp`, `dt` after the previous update. The first update
+    /// applies the curve as is.
+    pub fn update(
+        &mut self,
+        setting: &FanSetting,
+        temp: f32,
+        dt: Duration,
+        max_duty_value: u16,
+    ) -> u16 {
+        let duty = self.duty_percent(setting, temp, dt);
+        (max_duty_value as f32 / 100.0 * duty) as u16
+    }
+
+    /// [`DutyController::update`] in percent.
+    pub fn duty_percent(
+        &mut self,
+        setting: &FanSetting,
+        temp: f32,
+        dt: Duration,
+    ) -> f32 {
+        // rising temperatures are followed straight away, falling ones only
+        // once they dropped by more than the hysteresis
+        let temp = match self.temp {
+            Some(held) => {
+                held.max(temp).min(temp + setting.hysteresis_c.max(0.0))
+            }
+            None => temp,
+        };
+        self.temp = Some(temp);
+
+        let target = setting.interpolation.duty(&setting.curve, temp);
+        let duty = match self.duty {
+            Some(duty) => {
+                let up = limit(setting.ramp_up_pct_per_s, dt);
+                let down = limit(setting.ramp_down_pct_per_s, dt);
+                target.max(duty - down).min(duty + up)
+            }
+            None => target,
+        };
+        self.duty = Some(duty);
+        duty
+    }
+}
+
+/// Works out the duties of every channel from a [`Config`], so the
+/// firmware, the emulator and the host all pick between the
+/// [`ControlMode`], the channel modes and duties sent by the host the same
+/// way.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Controller {
+    /// One per channel, by [`crate::Id::index`].
+    channels: [DutyController; 4],
+    /// Whether smart mode has the fans spinning.
+    fans_running: bool,
+    pid: Pid,
+    /// Duties of the mode or the curves, channels without a setting or a
+    /// duty from the mode keep the last one they had.
+    duties: Duties,
+    /// Last [`crate::Msg::HostDuties`] and how long ago they came.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    host: Option<(Duties, Duration)>,
+}
+
+impl Controller {
+    /// Duties for `stats`, `dt` after the previous update. Duties from the
+    /// host win for as long as the watchdog of [`Config::host_control`]
+    /// allows.
+    pub fn update(
+        &mut self,
+        config: &Config,
+        stats: &Stats,
+        host_temp: Option<f32>,
+        dt: Duration,
+    ) -> Duties {
+        // worked out even while the host is in control, so hysteresis and
+        // ramps carry on from where the loop is if it goes quiet
+        let fan_duty = match config.mode {
+            ControlMode::Smart(ref smart_mode) => {
+                let fan_duty = smart_duty_percent(
+                    stats.coolant_temp,
+                    stats.ambient_temp,
+                    smart_mode.trigger_above_ambient,
+                    smart_mode.upper_temp,
+                    self.fans_running,
+                );
+                self.fans_running = fan_duty > 0.0;
+                Some(fan_duty)
+            }
+            ControlMode::Curves => None,
+            ControlMode::Fixed(ref fixed_mode) => Some(fixed_mode.fan_duty),
+            ControlMode::Pid(ref pid_mode) => {
+                let delta = stats.coolant_temp - stats.ambient_or_default();
+                Some(self.pid.update(pid_mode, delta, dt))
+            }
+        };
+        for id in Id::ALL {
+            let index = id.index();
+            let setting = config.get(id);
+            let by_mode = match id {
+                Id::P1 => config.mode.pump_duty(),
+                _ => fan_duty,
+            };
+            let mode = setting.map_or(ChannelMode::Follow, |s| s.mode);
+            match (mode, by_mode, setting) {
+                (ChannelMode::Fixed(duty), ..) => self.duties[index] = duty,
+                (ChannelMode::Follow, Some(duty), _) => {
+                    self.duties[index] = duty
+                }
+                (_, _, Some(setting)) => {
+                    let temp = setting.temp(stats, host_temp);
+                    self.duties[index] =
+                        self.channels[index].duty_percent(setting, temp, dt);
+                }
+                // no curve to fall back to
+                _ => {}
+            }
+        }
+
+        if let Some((duties, age)) = self.host.as_mut() {
+            *age = age.saturating_add(dt);
+            match config.host_control {
+                Some(host_control) if *age <= host_control.watchdog() => {
+                    return *duties;
+                }
+                // the host went quiet, or the config took control back
+                _ => self.host = None,
+            }
+        }
+        self.duties
+    }
+
+    /// Takes duties sent by the host, used from the next
+    /// [`Controller::update`] on.
+    pub fn set_host_duties(
+        &mut self,
+        config: &Config,
+        duties: Duties,
+    ) -> Result<()> {
+        if config.host_control.is_none() {
+            return Err(Error::NoHostControl);
+        }
+        if !duties.iter().all(|d| (0.0..=MAX_DUTY_PERCENT).contains(d)) {
+            return Err(Error::InvalidDuty);
+        }
+        self.host = Some((duties, Duration::ZERO));
+        Ok(())
+    }
+
+    /// Whether the duties last returned came from the host.
+    pub fn is_host_driven(&self) -> bool {
+        self.host.is_some()
+    }
+}
+
+/// PID loop holding coolant at the delta above ambient a [`PidMode`]
+/// targets, more duty cools it down.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pid {
+    /// Integral term in percent.
+    integral: f32,
+    /// Delta of the previous update.
+    last: Option<f32>,
+    /// Smoothed rate of change in °C per second.
+    rate: f32,
+}
+
+impl Pid {
+    /// Duty in percent for coolant `delta` °C above ambient, `dt` after the
+    /// previous update.
+    pub fn update(&mut self, mode: &PidMode, delta: f32, dt: Duration) -> f32 {
+        let dt_s = dt.as_secs_f32();
+        let error = delta - mode.target_delta;
+        let proportional = mode.kp * error;
+
+        // derivative on the delta rather than the error, so changing the
+        // target does not kick the fans
+        if let Some(last) = self.last.filter(|_| dt_s > 0.0) {
+            let rate = (delta - last) / dt_s;
+            let alpha = dt_s / (mode.derivative_filter_s.max(0.0) + dt_s);
+            self.rate += alpha * (rate - self.rate);
+        }
+        if dt_s > 0.0 || self.last.is_none() {
+            self.last = Some(delta);
+        }
+        let derivative = mode.kd * self.rate;
+
+        // anti-windup: the integral stops growing while the output is held
+        // at a limit, so it has nothing to unwind after running flat out,
+        // and stays within the duties the loop can settle at
+        let step = mode.ki * error * dt_s;
+        let output = proportional + self.integral + derivative;
+        let held = (output >= mode.max_duty && step > 0.0)
+            || (output <= mode.min_duty && step < 0.0);
+        if !held {
+            self.integral += step;
+        }
+        self.integral = self.integral.min(mode.max_duty).max(mode.min_duty);
+
+        (proportional + self.integral + derivative)
+            .min(mode.max_duty)
+            .max(mode.min_duty)
+    }
+}
+
+/// How far the duty may move in `dt`, a rate of `0` lets it jump.
+fn limit(pct_per_s: f32, dt: Duration) -> f32 {
+    if pct_per_s > 0.0 {
+        pct_per_s * dt.as_secs_f32()
+    } else {
+        f32::INFINITY
+    }
+}
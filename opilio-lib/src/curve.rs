@@ -0,0 +1,94 @@
+//! Temperature to duty curves, shared by the firmware and the host so both
+//! compute the same duties.
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::TempDuty;
+
+pub const MIN_CURVE_POINTS: usize = 2;
+pub const MAX_CURVE_POINTS: usize = 8;
+
+/// Points ordered by temperature.
+pub type Curve = Vec<TempDuty, MAX_CURVE_POINTS>;
+
+/// How the duty is worked out between two points of a [`Curve`]. Below the
+/// first point the first duty applies, above the last one the last duty.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Interpolation {
+    /// Duty of the closest point at or below the temperature.
+    Step,
+    #[default]
+    Linear,
+    /// Smooth curve through the points that never overshoots them, so a
+    /// curve that only rises never dips in between.
+    MonotoneCubic,
+}
+
+impl Interpolation {
+    /// Duty in percent at `temp`, `0.0` for an empty curve. A `NaN`, e.g.
+    /// from an open thermistor, gets the last duty so the fans fail hot.
+    pub fn duty(&self, curve: &[TempDuty], temp: f32) -> f32 {
+        let (first, last) = match (curve.first(), curve.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return 0.0,
+        };
+        if temp.is_nan() {
+            return last.1;
+        }
+        if temp <= first.0 {
+            return first.1;
+        }
+        if temp >= last.0 {
+            return last.1;
+        }
+        // temp lies strictly inside the curve so there is a next point
+        let k = curve.iter().rposition(|p| p.0 <= temp).unwrap_or(0);
+        let (lower, upper) = (curve[k], curve[k + 1]);
+        match self {
+            Self::Step => lower.1,
+            Self::Linear => {
+                (upper.1 - lower.1) * (temp - lower.0) / (upper.0 - lower.0)
+                    + lower.1
+            }
+            Self::MonotoneCubic => {
+                let h = upper.0 - lower.0;
+                let t = (temp - lower.0) / h;
+                let (t2, t3) = (t * t, t * t * t);
+                (2.0 * t3 - 3.0 * t2 + 1.0) * lower.1
+                    + (t3 - 2.0 * t2 + t) * h * tangent(curve, k)
+                    + (-2.0 * t3 + 3.0 * t2) * upper.1
+                    + (t3 - t2) * h * tangent(curve, k + 1)
+            }
+        }
+    }
+}
+
+/// Slope between point `k` and the next one.
+fn slope(curve: &[TempDuty], k: usize) -> f32 {
+    (curve[k + 1].1 - curve[k].1) / (curve[k + 1].0 - curve[k].0)
+}
+
+/// Slope of the monotone cubic at point `k`, the weighted harmonic mean of
+/// the neighbouring slopes as in Fritsch and Butland. Flat where the curve
+/// turns, so it never overshoots and needs no square root.
+fn tangent(curve: &[TempDuty], k: usize) -> f32 {
+    if k == 0 {
+        return slope(curve, 0);
+    }
+    if k == curve.len() - 1 {
+        return slope(curve, k - 1);
+    }
+    let (before, after) = (slope(curve, k - 1), slope(curve, k));
+    if before * after <= 0.0 {
+        return 0.0;
+    }
+    let h_before = curve[k].0 - curve[k - 1].0;
+    let h_after = curve[k + 1].0 - curve[k].0;
+    let w_before = 2.0 * h_after + h_before;
+    let w_after = h_after + 2.0 * h_before;
+    (w_before + w_after) / (w_before / before + w_after / after)
+}
@@ -13,6 +13,78 @@ pub enum Error {
     InvalidMsgDataPair,
     Unknown,
     TempRead,
+    /// Frame is not valid COBS.
+    CorruptFrame,
+    /// Frame checksum does not match its contents.
+    Crc,
+    /// Frame is longer than [`crate::otw::MAX_FRAME_SIZE`].
+    FrameTooLong,
+    /// Config was rejected by [`crate::Config::is_valid`].
+    InvalidConfig,
+    /// Stats can not be pushed that often, see
+    /// [`crate::MIN_STATS_INTERVAL_MS`].
+    InvalidInterval,
+    /// Config can not be put in a layout the other end understands, see
+    /// [`crate::wire`].
+    ConfigLayout,
+    /// Config has no setting for the channel asked for.
+    UnknownChannel,
+    /// Chunk or commit without a transfer begun, or after it was aborted.
+    NoTransfer,
+    /// Transfer is larger than the receiver can take, or a chunk runs past
+    /// its announced size.
+    TransferTooLarge,
+    /// Chunk does not continue where the last one ended.
+    TransferOffset,
+    /// Commit before every chunk was received.
+    TransferIncomplete,
+    /// Received payload does not match the checksum of the transfer.
+    TransferCrc,
+    /// Override duty is not between 0 and 100 %, or it lasts no time.
+    InvalidOverride,
+    /// Duties sent while the config does not hand control to the host.
+    NoHostControl,
+    /// Duty sent by the host is not between 0 and 100 %.
+    InvalidDuty,
+}
+
+impl Error {
+    /// Human readable description, suitable to show to a user.
+    pub const fn message(&self) -> &'static str {
+        match self {
+            Self::Deserialize => "failed to decode a message",
+            Self::FlashErase => "failed to erase the settings flash",
+            Self::FlashRead => "failed to read settings from flash",
+            Self::FlashWrite => "failed to write settings to flash",
+            Self::SerialRead => "failed to read from the USB serial port",
+            Self::SerialWrite => "failed to write to the USB serial port",
+            Self::Serialize => "failed to encode a message",
+            Self::InvalidMsgDataPair => {
+                "message carries the wrong kind of data"
+            }
+            Self::Unknown => "unknown error",
+            Self::TempRead => "failed to read a temperature sensor",
+            Self::CorruptFrame => "received a corrupt frame",
+            Self::Crc => "received a frame with a bad checksum",
+            Self::FrameTooLong => "received a frame that is too long",
+            Self::InvalidConfig => "config is not valid",
+            Self::InvalidInterval => "stats interval is too short",
+            Self::ConfigLayout => {
+                "config uses settings the other end does not support"
+            }
+            Self::UnknownChannel => "config has no setting for that channel",
+            Self::NoTransfer => "no transfer in progress",
+            Self::TransferTooLarge => "transfer is too large",
+            Self::TransferOffset => "chunk does not continue the transfer",
+            Self::TransferIncomplete => "transfer is missing chunks",
+            Self::TransferCrc => "transfer does not match its checksum",
+            Self::InvalidOverride => {
+                "override needs a duty of 0 to 100 % and a timeout"
+            }
+            Self::NoHostControl => "config does not hand control to the host",
+            Self::InvalidDuty => "duty must be between 0 and 100 %",
+        }
+    }
 }
 
 impl From<postcard::Error> for Error {
@@ -36,16 +108,162 @@ impl From<postcard::Error> for Error {
     }
 }
 
+#[cfg(feature = "std")]
+pub use std_impls::{ClientError, ConfigFileError};
+
 #[cfg(feature = "std")]
 mod std_impls {
     extern crate std;
-    use std::{error::Error, fmt::Display};
+    use std::{
+        boxed::Box, fmt::Display, io, path::PathBuf, string::String, vec::Vec,
+    };
+
+    use crate::{
+        config_file::CONFIG_FILE_VERSION, serial::DeviceError, Msg, Version,
+    };
 
     impl Display for super::Error {
         fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-            write!(f, "{:?}", self)
+            f.write_str(self.message())
         }
     }
 
-    impl Error for super::Error {}
+    impl std::error::Error for super::Error {}
+
+    /// Everything that can go wrong talking to a device from the host.
+    #[derive(Debug, thiserror::Error)]
+    pub enum ClientError {
+        /// No Opilio is plugged in.
+        #[error("No Opilio device found")]
+        NotFound,
+        /// Several devices are plugged in and none was named.
+        #[error(
+            "Found {} Opilio devices, name the one to use: {}",
+            .0.len(),
+            .0.join(", ")
+        )]
+        Ambiguous(Vec<String>),
+        /// The port exists but may not be opened by this user, on linux
+        /// they usually need to be in the `dialout` group.
+        #[error(
+            "Permission denied opening {port}, make sure your user is in \
+             the dialout group"
+        )]
+        PermissionDenied { port: String },
+        /// The port could not be opened for any other reason.
+        #[error("Failed to connect to {port}, ({source})")]
+        Connect {
+            port: String,
+            source: serialport::Error,
+        },
+        #[error(transparent)]
+        Serial(#[from] serialport::Error),
+        #[error(transparent)]
+        Io(#[from] io::Error),
+        /// The other end of the link went away.
+        #[error("Connection to the device was closed")]
+        Disconnected,
+        /// Nothing came back before the timeout.
+        #[error("Timed out waiting for a reply to {0:?}")]
+        Timeout(Msg),
+        /// Only replies to earlier requests came back before the timeout.
+        #[error(
+            "No reply to {msg:?} #{seq}, dropped {stale} replies with a \
+             mismatched sequence number"
+        )]
+        StaleReplies { msg: Msg, seq: u16, stale: usize },
+        /// A frame could not be encoded or decoded.
+        #[error(transparent)]
+        Protocol(#[from] super::Error),
+        /// The device answered with a message that makes no sense for the
+        /// request.
+        #[error("Unexpected reply {reply:?} to {msg:?}")]
+        UnexpectedReply { msg: Msg, reply: Msg },
+        #[error(transparent)]
+        Device(#[from] DeviceError),
+        /// Asking for the version failed, the firmware most likely predates
+        /// [`Msg::GetVersion`].
+        #[error(
+            "Opilio did not report its version, the firmware may be too old \
+             for this software ({0})"
+        )]
+        NoVersion(Box<ClientError>),
+        #[error(
+            "Opilio firmware {} speaks protocol revision {}, which this \
+             software can not talk to, please update the firmware",
+            .0.firmware,
+            .0.protocol
+        )]
+        Incompatible(Version),
+        /// The firmware predates `msg`.
+        #[error(
+            "Opilio firmware {} does not support {msg:?}, please update the \
+             firmware",
+            .version.firmware
+        )]
+        Unsupported { msg: Msg, version: Version },
+        /// Stats were read from the stream without subscribing first, or
+        /// the subscription ended with a reconnect.
+        #[error("Not subscribed to stats")]
+        NotSubscribed,
+    }
+
+    /// Everything that can go wrong reading or writing a config file, see
+    /// [`crate::config_file`].
+    #[derive(Debug, thiserror::Error)]
+    pub enum ConfigFileError {
+        #[error("Failed to access {}, ({source})", .path.display())]
+        Io { path: PathBuf, source: io::Error },
+        #[error("Config file is not valid, ({0})")]
+        Json(#[from] serde_json::Error),
+        /// The file is JSON but not laid out like a config.
+        #[error("Config file is not valid, {0}")]
+        Layout(&'static str),
+        /// Written by a newer version of this software.
+        #[error(
+            "Config file version {0} is newer than the supported \
+             {CONFIG_FILE_VERSION}, please update this software"
+        )]
+        TooNew(u32),
+    }
+
+    impl ClientError {
+        /// Link to the device is gone, reconnecting may help.
+        pub fn is_disconnected(&self) -> bool {
+            match self {
+                Self::NotFound | Self::Disconnected => true,
+                Self::Serial(e) | Self::Connect { source: e, .. } => matches!(
+                    e.kind(),
+                    serialport::ErrorKind::NoDevice
+                        | serialport::ErrorKind::Io(io::ErrorKind::NotFound)
+                        | serialport::ErrorKind::Io(io::ErrorKind::BrokenPipe)
+                ),
+                // a USB serial port that fails for any reason other than a
+                // timeout is gone, most of the time with EIO
+                Self::Io(e) => !matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::Interrupted
+                ),
+                _ => false,
+            }
+        }
+
+        /// The request got lost or mangled on the way, sending it again may
+        /// succeed.
+        pub fn is_transient(&self) -> bool {
+            matches!(
+                self,
+                Self::Timeout(_)
+                    | Self::StaleReplies { .. }
+                    | Self::Protocol(
+                        super::Error::CorruptFrame
+                            | super::Error::Crc
+                            | super::Error::FrameTooLong
+                            | super::Error::Deserialize
+                    )
+            )
+        }
+    }
 }
@@ -0,0 +1,218 @@
+//! Notices devices coming and going, so frontends do not have to poll the
+//! USB bus themselves.
+extern crate std;
+
+use std::{
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+    vec::Vec,
+};
+
+use crate::{
+    manager::{Enumerator, UsbBus},
+    serial::PortWithSerialNumber,
+};
+
+/// How often the bus is enumerated when there is no udev to wake us up.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HotplugEvent {
+    Attached(PortWithSerialNumber),
+    Detached(PortWithSerialNumber),
+}
+
+/// Turns successive enumerations into events.
+#[derive(Debug, Default)]
+pub struct PortDiff {
+    known: Vec<PortWithSerialNumber>,
+}
+
+impl PortDiff {
+    /// Remembers `ports` and returns what changed since the last update. A
+    /// device that moved to another port is detached from the old one first.
+    pub fn update(
+        &mut self,
+        ports: Vec<PortWithSerialNumber>,
+    ) -> Vec<HotplugEvent> {
+        let mut events: Vec<_> = self
+            .known
+            .iter()
+            .filter(|p| !ports.contains(p))
+            .cloned()
+            .map(HotplugEvent::Detached)
+            .collect();
+        events.extend(
+            ports
+                .iter()
+                .filter(|p| !self.known.contains(p))
+                .cloned()
+                .map(HotplugEvent::Attached),
+        );
+        self.known = ports;
+        events
+    }
+
+    pub fn ports(&self) -> &[PortWithSerialNumber] {
+        &self.known
+    }
+}
+
+/// Enumerates devices on a background thread and reports the differences.
+/// The thread stops when the watcher is dropped.
+pub struct HotplugWatcher {
+    events: Receiver<HotplugEvent>,
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HotplugWatcher {
+    /// Watches Opilio devices on USB. On linux the bus is only enumerated
+    /// when udev reports a change, elsewhere every [`POLL_INTERVAL`].
+    pub fn usb() -> Self {
+        Self::spawn(move |stop, tx| {
+            #[cfg(all(target_os = "linux", feature = "udev"))]
+            match udev::Waker::new() {
+                Ok(mut waker) => {
+                    return watch(UsbBus::default(), tx, || waker.wait(&stop))
+                }
+                Err(e) => log::warn!("udev is not available ({e}), polling"),
+            }
+            watch(UsbBus::default(), tx, || sleep(&stop, POLL_INTERVAL))
+        })
+    }
+
+    /// Enumerates with `enumerator` every `interval`.
+    pub fn polling(
+        enumerator: impl Enumerator + Send + 'static,
+        interval: Duration,
+    ) -> Self {
+        Self::spawn(move |stop, tx| {
+            watch(enumerator, tx, || sleep(&stop, interval))
+        })
+    }
+
+    fn spawn(
+        run: impl FnOnce(Receiver<()>, Sender<HotplugEvent>) + Send + 'static,
+    ) -> Self {
+        let (tx, events) = channel();
+        let (stop, stop_rx) = channel();
+        let handle = thread::Builder::new()
+            .name("opilio-hotplug".into())
+            .spawn(move || run(stop_rx, tx))
+            .expect("failed to spawn the hotplug thread");
+        Self {
+            events,
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// Events in the order they happened, the first ones report the devices
+    /// that were already attached.
+    pub fn events(&self) -> &Receiver<HotplugEvent> {
+        &self.events
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        // hanging up wakes the thread
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+/// Enumerates every time `wait` returns `true` until it returns `false` or
+/// nobody listens anymore.
+fn watch(
+    mut enumerator: impl Enumerator,
+    tx: Sender<HotplugEvent>,
+    mut wait: impl FnMut() -> bool,
+) {
+    let mut diff = PortDiff::default();
+    loop {
+        match enumerator.ports() {
+            Ok(ports) => {
+                for event in diff.update(ports) {
+                    log::debug!("{event:?}");
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => log::warn!("failed to enumerate devices ({e})"),
+        }
+        if !wait() {
+            return;
+        }
+    }
+}
+
+/// Returns `false` once the watcher is dropped.
+fn sleep(stop: &Receiver<()>, interval: Duration) -> bool {
+    matches!(stop.recv_timeout(interval), Err(RecvTimeoutError::Timeout))
+}
+
+#[cfg(all(target_os = "linux", feature = "udev"))]
+mod udev {
+    extern crate std;
+    use std::{
+        os::unix::io::AsRawFd,
+        sync::mpsc::{Receiver, TryRecvError},
+    };
+
+    /// How long to block on the udev socket before checking whether the
+    /// watcher was dropped.
+    const WAKEUP_MS: i32 = 250;
+
+    /// Blocks until the kernel reports a tty being added or removed.
+    pub struct Waker {
+        socket: libudev::MonitorSocket,
+    }
+
+    impl Waker {
+        pub fn new() -> libudev::Result<Self> {
+            let context = libudev::Context::new()?;
+            let mut monitor = libudev::Monitor::new(&context)?;
+            monitor.match_subsystem("tty")?;
+            Ok(Self {
+                socket: monitor.listen()?,
+            })
+        }
+
+        /// Returns `false` once the watcher is dropped.
+        pub fn wait(&mut self, stop: &Receiver<()>) -> bool {
+            loop {
+                if !matches!(stop.try_recv(), Err(TryRecvError::Empty)) {
+                    return false;
+                }
+                let mut fd = libc::pollfd {
+                    fd: self.socket.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                // SAFETY: `fd` is a single valid pollfd for the duration of
+                // the call.
+                let ready = unsafe { libc::poll(&mut fd, 1, WAKEUP_MS) };
+                if ready > 0 {
+                    let mut changed = false;
+                    while let Some(event) = self.socket.receive_event() {
+                        log::debug!(
+                            "udev {} {:?}",
+                            event.event_type(),
+                            event.syspath()
+                        );
+                        changed = true;
+                    }
+                    if changed {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+}
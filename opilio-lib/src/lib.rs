@@ -1,11 +1,18 @@
 #![no_std]
 
+pub use curve::{Curve, Interpolation};
 use error::Error;
 use fixed::types::extra::U4;
 use heapless::Vec;
 pub use otw::OTW;
+use otw::{Chunk, Transfer};
 use postcard::{from_bytes, to_vec};
 use serde::{Deserialize, Serialize};
+pub use validate::{Severity, ValidationIssue};
+use wire::{
+    ConfigLayout, ConfigV1, ControlModeV2, FanSettingV2, FixedDuties,
+    FixedOverride, FixedSample, FixedStats, SmartModeV2, WireConfig,
+};
 
 pub type Fixed = fixed::FixedI32<U4>;
 
@@ -16,16 +23,51 @@ pub const MAX_TEMP: f32 = 50.0;
 
 pub const CONFIG_SIZE: usize = 18;
 pub const STATS_DATA_SIZE: usize = 20;
+/// Largest message, sized for the buffers of the MCU. Configs that do not
+/// fit go as a chunked transfer, see [`otw::Reassembly`].
 pub const MAX_SERIAL_DATA_SIZE: usize = 256;
 pub const SWITCH_TEMP_BUFFER: f32 = 1.0;
+/// Assumed ambient temperature while the ambient thermistor is unplugged.
+pub const DEFAULT_AMBIENT_TEMP: f32 = 22.0;
+/// Seconds a temperature sent with [`Msg::HostTemp`] is used for, channels
+/// following [`TempSource::Host`] run at the top of their curve once it
+/// expires, see [`FanSetting::temp`].
+pub const HOST_TEMP_TIMEOUT_S: u32 = 10;
 
 // requested from https:://pid.codes
 // https://github.com/pidcodes/pidcodes.github.com/pull/751
 pub const VID: u16 = 0x1209;
 pub const PID: u16 = 0x2442;
 
+/// Revision of the OTW protocol spoken by this library, bumped for every
+/// protocol change including new messages.
+pub const PROTOCOL_VERSION: u16 = 4;
+/// Oldest revision sharing the framing and envelope of [`PROTOCOL_VERSION`],
+/// anything older cannot be talked to at all.
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+/// Shortest interval the device pushes stats at, see [`Msg::SubscribeStats`].
+pub const MIN_STATS_INTERVAL_MS: u32 = 100;
+
+#[cfg(feature = "std")]
+pub mod async_client;
+#[cfg(feature = "std")]
+pub mod config_file;
+pub mod control;
+pub mod curve;
 pub mod error;
+#[cfg(feature = "std")]
+pub mod hotplug;
+#[cfg(feature = "std")]
+pub mod manager;
 pub mod otw;
+#[cfg(feature = "std")]
+pub mod serial;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod transport;
+pub mod validate;
+pub mod wire;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -43,28 +85,206 @@ pub enum Msg {
     Result = 8,
     UploadConfig = 9,
     Reload = 10,
+    GetVersion = 11,
+    Version = 12,
+    /// Asks the device to push a [`Msg::StatsSample`] every interval.
+    SubscribeStats = 13,
+    Unsubscribe = 14,
+    /// Pushed by the device while subscribed, not a reply to any request.
+    StatsSample = 15,
+    /// Temperature for channels following [`TempSource::Host`].
+    HostTemp = 16,
+    /// Asks for the setting of a single channel, answered with
+    /// [`Msg::FanSetting`].
+    GetFanSetting = 17,
+    FanSetting = 18,
+    /// Replaces the setting of a single channel, or adds it if the config
+    /// has none, answered with [`Msg::Applied`].
+    UploadFanSetting = 19,
+    GetGeneral = 20,
+    General = 21,
+    UploadGeneral = 22,
+    GetSmartMode = 23,
+    SmartMode = 24,
+    /// Switches smart mode off when sent without one.
+    UploadSmartMode = 25,
+    /// Acknowledges a part of the config uploaded on its own, errors are
+    /// still answered with [`Msg::Result`].
+    Applied = 26,
+    /// Starts a transfer of a payload too large for a single message, see
+    /// [`otw::Reassembly`].
+    BeginTransfer = 27,
+    /// Answered with [`Msg::Received`].
+    Chunk = 28,
+    /// Bytes of the transfer received so far.
+    Received = 29,
+    /// Hands the received payload over once it is complete and intact.
+    CommitTransfer = 30,
+    AbortTransfer = 31,
+    /// Holds a channel at a fixed duty until it expires or is cleared,
+    /// replacing any override already on the channel.
+    SetOverride = 32,
+    /// Hands a channel back to its curve.
+    ClearOverride = 33,
+    /// Duties for every channel while the config hands control to the
+    /// host, see [`HostControl`].
+    HostDuties = 34,
+    GetControlMode = 35,
+    ControlMode = 36,
+    /// Switches the mode of the whole config, channels keep their own
+    /// [`FanSetting::mode`].
+    UploadControlMode = 37,
+    /// Asks for the next chunk of a transfer the device began in reply to
+    /// [`Msg::GetConfig`], with the bytes received so far. Answered with
+    /// [`Msg::Chunk`].
+    GetChunk = 38,
+}
+
+impl Msg {
+    /// Protocol revision that introduced the message.
+    pub fn protocol(&self) -> u16 {
+        match self {
+            Self::Ping
+            | Self::Pong
+            | Self::GetConfig
+            | Self::SaveConfig
+            | Self::GetStats
+            | Self::Stats
+            | Self::Config
+            | Self::Result
+            | Self::UploadConfig
+            | Self::Reload
+            | Self::GetVersion
+            | Self::Version => MIN_PROTOCOL_VERSION,
+            Self::SubscribeStats | Self::Unsubscribe | Self::StatsSample => 3,
+            _ => wire::ENVELOPE_PROTOCOL,
+        }
+    }
 }
 
 #[derive(Serialize, Clone)]
 pub enum DataRef<'a> {
-    Config(&'a Config),
+    /// Config of firmware before [`wire::ENVELOPE_PROTOCOL`].
+    LegacyConfig(&'a ConfigV1),
     Stats(&'a Stats),
     Result(&'a Response),
     Pong(&'a u32),
     Empty,
+    Version(&'a Version),
+    /// Milliseconds between two pushed samples.
+    Interval(&'a u32),
+    Sample(&'a Sample),
+    Temp(&'a f32),
+    /// Config layout asked for with [`Msg::GetConfig`].
+    Layout(&'a ConfigLayout),
+    Config(&'a WireConfig),
+    /// Protocol revision of the host, sent with [`Msg::GetVersion`].
+    Protocol(&'a u16),
+    FixedStats(&'a FixedStats),
+    FixedSample(&'a FixedSample),
+    /// Channel asked for with [`Msg::GetFanSetting`].
+    Id(&'a Id),
+    FanSetting(&'a FanSettingV2),
+    General(&'a GeneralConfig),
+    SmartMode(&'a Option<SmartModeV2>),
+    Applied(&'a ConfigPart),
+    Transfer(&'a Transfer),
+    Chunk(&'a Chunk),
+    Received(&'a u32),
+    Override(&'a FixedOverride),
+    Duties(&'a FixedDuties),
+    ControlMode(&'a ControlModeV2),
 }
 
+// no allocator on the firmware to box the config with
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Data {
-    Config(Config),
+    LegacyConfig(ConfigV1),
     Stats(Stats),
     Result(Response),
     Pong(u32),
     Empty,
+    Version(Version),
+    Interval(u32),
+    Sample(Sample),
+    Temp(f32),
+    Layout(ConfigLayout),
+    Config(WireConfig),
+    Protocol(u16),
+    FixedStats(FixedStats),
+    FixedSample(FixedSample),
+    Id(Id),
+    FanSetting(FanSettingV2),
+    General(GeneralConfig),
+    SmartMode(Option<SmartModeV2>),
+    Applied(ConfigPart),
+    Transfer(Transfer),
+    Chunk(Chunk),
+    Received(u32),
+    Override(FixedOverride),
+    Duties(FixedDuties),
+    ControlMode(ControlModeV2),
 }
 
-#[derive(Copy, Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Copy, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SemVer {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl core::fmt::Display for SemVer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// What the device reports about itself in reply to [`Msg::GetVersion`].
+#[derive(Copy, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    pub firmware: SemVer,
+    pub protocol: u16,
+    pub hardware: u8,
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Device speaks the same protocol revision as this library.
+    Full,
+    /// Revisions differ but share the wire format, messages added after the
+    /// older of the two are not available.
+    Degraded,
+    /// Wire formats differ, talking to the device is not possible.
+    Incompatible,
+}
+
+impl Version {
+    pub fn compatibility(&self) -> Compatibility {
+        if self.protocol < MIN_PROTOCOL_VERSION {
+            Compatibility::Incompatible
+        } else if self.protocol != PROTOCOL_VERSION {
+            Compatibility::Degraded
+        } else {
+            Compatibility::Full
+        }
+    }
+}
+
+impl core::fmt::Display for Version {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} (protocol {}, hw rev {})",
+            self.firmware, self.protocol, self.hardware
+        )
+    }
+}
+
+#[derive(Copy, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum Id {
@@ -74,7 +294,36 @@ pub enum Id {
     F3 = 4,
 }
 
-#[derive(Copy, Debug, Clone, Deserialize, Serialize, PartialEq)]
+impl Id {
+    pub const ALL: [Self; 4] = [Self::P1, Self::F1, Self::F2, Self::F3];
+
+    /// Position of the channel in [`Id::ALL`], and in arrays of all four
+    /// such as [`Stats::overrides`].
+    pub fn index(&self) -> usize {
+        *self as usize - 1
+    }
+}
+
+/// Duty a channel is held at instead of following its curve, see
+/// [`Msg::SetOverride`].
+#[derive(Copy, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Override {
+    pub id: Id,
+    pub duty_percent: f32,
+    /// Seconds it is held for, in [`Stats::overrides`] the seconds left.
+    pub timeout_s: u32,
+}
+
+impl Override {
+    /// Whether the device takes it, see [`Error::InvalidOverride`].
+    pub fn is_valid(&self) -> bool {
+        (0.0..=MAX_DUTY_PERCENT).contains(&self.duty_percent)
+            && self.timeout_s > 0
+    }
+}
+
+#[derive(Copy, Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Stats {
     pub pump1_rpm: f32,
@@ -84,64 +333,154 @@ pub struct Stats {
     pub coolant_temp: f32,
     pub ambient_temp: f32,
     pub coolant_out_temp: f32,
+    /// Overrides in force by [`Id::index`], always empty from firmware
+    /// before [`wire::ENVELOPE_PROTOCOL`].
+    #[serde(skip)]
+    pub overrides: [Option<Override>; 4],
 }
 
+/// Stats pushed by the device while subscribed.
 #[derive(Copy, Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sample {
+    /// Counts up from 0 with every sample since subscribing, lets the host
+    /// notice samples that got lost on the way.
+    pub index: u32,
+    pub stats: Stats,
+}
+
+impl Stats {
+    /// Ambient temperature, or [`DEFAULT_AMBIENT_TEMP`] if the thermistor
+    /// is unplugged.
+    pub fn ambient_or_default(&self) -> f32 {
+        ambient_or_default(self.ambient_temp)
+    }
+}
+
+fn ambient_or_default(ambient_temp: f32) -> f32 {
+    if ambient_temp < -20.0 {
+        DEFAULT_AMBIENT_TEMP
+    } else {
+        ambient_temp
+    }
+}
+
+/// Temperature a curve is driven by.
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TempSource {
+    /// Coolant flowing into the radiator.
+    #[default]
+    CoolantIn,
+    CoolantOut,
+    Ambient,
+    /// Hotter of the two coolant sensors.
+    MaxCoolant,
+    /// How far the coolant is above ambient, suits radiator fans that can
+    /// not cool below room temperature anyway.
+    CoolantDelta,
+    /// Sent by the host with [`Msg::HostTemp`], e.g. a CPU temperature.
+    Host,
+}
+
+impl TempSource {
+    /// Picks the temperature out of `stats`, `None` for
+    /// [`TempSource::Host`] without a host temperature.
+    pub fn temp(&self, stats: &Stats, host_temp: Option<f32>) -> Option<f32> {
+        Some(match self {
+            Self::CoolantIn => stats.coolant_temp,
+            Self::CoolantOut => stats.coolant_out_temp,
+            Self::Ambient => stats.ambient_or_default(),
+            Self::MaxCoolant => stats.coolant_temp.max(stats.coolant_out_temp),
+            Self::CoolantDelta => {
+                stats.coolant_temp - stats.ambient_or_default()
+            }
+            Self::Host => host_temp?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FanSetting {
     pub id: Id,
-    pub curve: [(f32, f32); 4],
+    /// Between [`curve::MIN_CURVE_POINTS`] and [`curve::MAX_CURVE_POINTS`]
+    /// points.
+    pub curve: Curve,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub source: TempSource,
+    /// How far in °C the temperature has to drop before the duty follows
+    /// it down, keeps fans from hunting around a curve point.
+    #[serde(default)]
+    pub hysteresis_c: f32,
+    /// Fastest the duty may rise in percent per second, `0` for no limit.
+    #[serde(default)]
+    pub ramp_up_pct_per_s: f32,
+    /// Fastest the duty may fall in percent per second, `0` for no limit.
+    #[serde(default)]
+    pub ramp_down_pct_per_s: f32,
+    /// Lets the channel fall back to its curve or a fixed duty while the
+    /// rest follow [`Config::mode`].
+    #[serde(default)]
+    pub mode: ChannelMode,
 }
 
 pub type TempDuty = (f32, f32);
 
 impl FanSetting {
     pub fn new(id: Id) -> Self {
+        let points: &[TempDuty] = if id == Id::P1 {
+            &[(25.0, 50.0), (30.0, 60.0), (35.0, 80.0), (40.0, 100.0)]
+        } else {
+            &[(25.0, 0.0), (30.0, 30.0), (35.0, 50.0), (40.0, 100.0)]
+        };
         Self {
             id,
-            curve: if id == Id::P1 {
-                [(25.0, 50.0), (30.0, 60.0), (35.0, 80.0), (40.0, 100.0)]
-            } else {
-                [(25.0, 0.0), (30.0, 30.0), (35.0, 50.0), (40.0, 100.0)]
-            },
+            curve: Vec::from_slice(points).unwrap_or_default(),
+            interpolation: Interpolation::default(),
+            source: TempSource::default(),
+            hysteresis_c: 0.0,
+            ramp_up_pct_per_s: 0.0,
+            ramp_down_pct_per_s: 0.0,
+            mode: ChannelMode::Follow,
         }
     }
 
+    /// Duty straight off the curve, see [`control::DutyController`] for one
+    /// that applies the hysteresis and ramp limits.
     pub fn get_duty(&self, temp: f32, max_duty_value: u16) -> u16 {
-        let calculate =
-            |(min_temp, min_duty): TempDuty, (max_temp, max_duty): TempDuty| {
-                ((max_duty - min_duty) * (temp - min_temp)
-                    / (max_temp - min_temp))
-                    + min_duty
-            };
-
-        let duty_percent = if temp < self.curve[1].0 {
-            calculate(self.curve[0], self.curve[1])
-        } else if temp < self.curve[2].0 {
-            calculate(self.curve[1], self.curve[2])
-        } else if temp < self.curve[3].0 {
-            calculate(self.curve[2], self.curve[3])
-        }
-        // return max duty
-        else {
-            self.curve[3].1
-        };
+        let duty_percent = self.interpolation.duty(&self.curve, temp);
         (max_duty_value as f32 / 100.0 * duty_percent) as u16
     }
 
-    pub fn is_fan(&self) -> bool {
-        !matches!(self.id, Id::P1)
+    /// Temperature this channel follows, see [`FanSetting::source`].
+    /// Without a host temperature it is the last point of the curve, so the
+    /// channel runs flat out rather than follow a temperature its curve was
+    /// not written for, e.g. the coolant on a CPU curve.
+    pub fn temp(&self, stats: &Stats, host_temp: Option<f32>) -> f32 {
+        self.source.temp(stats, host_temp).unwrap_or_else(|| {
+            self.curve.last().map_or(MAX_TEMP, |&(temp, _)| temp)
+        })
     }
 
-    pub fn is_valid(&self) -> bool {
-        let mut previous = self.curve[0];
-        for current in &self.curve[1..] {
-            if current.0 <= previous.0 || current.1 <= previous.1 {
-                return false;
-            }
-            previous = *current;
-        }
-        true
+    /// Duty for the temperature this channel follows, see
+    /// [`FanSetting::temp`].
+    pub fn get_duty_from(
+        &self,
+        stats: &Stats,
+        host_temp: Option<f32>,
+        max_duty_value: u16,
+    ) -> u16 {
+        self.get_duty(self.temp(stats, host_temp), max_duty_value)
+    }
+
+    pub fn is_fan(&self) -> bool {
+        !matches!(self.id, Id::P1)
     }
 }
 
@@ -152,6 +491,16 @@ pub enum Response {
     Error(Error),
 }
 
+/// Part of the config the device applied, see [`Msg::Applied`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigPart {
+    FanSetting(Id),
+    General,
+    SmartMode,
+    ControlMode,
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct SmartMode {
@@ -170,6 +519,119 @@ impl Default for SmartMode {
     }
 }
 
+/// Runs every fan at one duty and the pump at another.
+#[derive(Copy, Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedMode {
+    pub fan_duty: f32,
+    pub pump_duty: f32,
+}
+
+impl Default for FixedMode {
+    fn default() -> Self {
+        Self {
+            fan_duty: 50.0,
+            pump_duty: 95.0,
+        }
+    }
+}
+
+/// Holds the coolant at a set delta above ambient with a PID loop driving
+/// the fans, see [`control::Pid`]. The pump runs at a constant duty.
+#[derive(Copy, Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PidMode {
+    /// How far in °C the coolant is held above ambient.
+    pub target_delta: f32,
+    /// Percent of duty per °C above the target.
+    pub kp: f32,
+    /// Percent of duty per °C and second above the target.
+    pub ki: f32,
+    /// Percent of duty per °C per second the coolant heats up at.
+    pub kd: f32,
+    /// Fan duty in percent the loop does not go below.
+    pub min_duty: f32,
+    /// Fan duty in percent the loop does not go above.
+    pub max_duty: f32,
+    /// Time constant in seconds the rate of change is smoothed over before
+    /// `kd` applies, sensor noise would have the fans jitter otherwise.
+    /// `0` turns the smoothing off.
+    pub derivative_filter_s: f32,
+    pub pump_duty: f32,
+}
+
+impl Default for PidMode {
+    fn default() -> Self {
+        Self {
+            target_delta: 8.0,
+            kp: 8.0,
+            ki: 0.1,
+            kd: 30.0,
+            min_duty: 20.0,
+            max_duty: 100.0,
+            derivative_filter_s: 5.0,
+            pump_duty: 95.0,
+        }
+    }
+}
+
+/// How the device works out duties, see [`control::Controller`]. Variants
+/// are never reordered, a new mode goes at the end.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlMode {
+    /// Fans speed up as the coolant warms past ambient.
+    Smart(SmartMode),
+    /// Every channel follows its curve in [`Config::settings`].
+    Curves,
+    Fixed(FixedMode),
+    Pid(PidMode),
+}
+
+impl Default for ControlMode {
+    fn default() -> Self {
+        Self::Smart(SmartMode::default())
+    }
+}
+
+impl ControlMode {
+    /// Name of the mode as written in the config file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Smart(_) => "smart",
+            Self::Curves => "curves",
+            Self::Fixed(_) => "fixed",
+            Self::Pid(_) => "pid",
+        }
+    }
+
+    /// Duty in percent the mode runs the pump at, curves leave it to the
+    /// pump's own.
+    pub fn pump_duty(&self) -> Option<f32> {
+        match self {
+            Self::Smart(smart_mode) => Some(smart_mode.pump_duty),
+            Self::Curves => None,
+            Self::Fixed(fixed_mode) => Some(fixed_mode.pump_duty),
+            Self::Pid(pid_mode) => Some(pid_mode.pump_duty),
+        }
+    }
+}
+
+/// What a single channel does, see [`FanSetting::mode`].
+#[derive(Copy, Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelMode {
+    /// Whatever [`Config::mode`] says.
+    #[default]
+    Follow,
+    /// Its own curve, whatever the mode.
+    Curve,
+    /// A constant duty in percent, whatever the mode.
+    Fixed(f32),
+}
+
 #[derive(Copy, Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
 #[serde(rename_all = "lowercase")]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -203,12 +665,36 @@ impl Default for GeneralConfig {
     }
 }
 
+/// Hands the duties to the host, which sends them with
+/// [`Msg::HostDuties`]. The device falls back to smart mode or the curves
+/// once the host has been quiet for longer than the watchdog, and follows
+/// the host again with the next duties it sends.
+#[derive(Copy, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HostControl {
+    pub watchdog_s: u32,
+}
+
+impl Default for HostControl {
+    fn default() -> Self {
+        Self { watchdog_s: 5 }
+    }
+}
+
+impl HostControl {
+    pub fn watchdog(&self) -> core::time::Duration {
+        core::time::Duration::from_secs(self.watchdog_s.into())
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Config {
     pub general: GeneralConfig,
-    pub smart_mode: Option<SmartMode>,
+    pub mode: ControlMode,
     pub settings: Vec<FanSetting, 4>,
+    #[serde(default)]
+    pub host_control: Option<HostControl>,
 }
 
 impl Default for Config {
@@ -221,24 +707,14 @@ impl Default for Config {
 
         Self {
             general: GeneralConfig::default(),
-            smart_mode: Some(SmartMode::default()),
+            mode: ControlMode::default(),
             settings,
+            host_control: None,
         }
     }
 }
 
 impl Config {
-    pub fn is_valid(&self) -> bool {
-        if self.general.sleep_after < 5 {
-            return false;
-        }
-        // running in smart mode only requires pump to be at a decent speed.
-        if let Some(ref smart_mode) = self.smart_mode {
-            return smart_mode.pump_duty >= 40.0;
-        }
-        self.settings.iter().all(|c| c.is_valid())
-    }
-
     pub fn set(&mut self, config: FanSetting) {
         for c in self.settings.iter_mut() {
             if c.id == config.id {
@@ -251,9 +727,11 @@ impl Config {
     }
 
     pub fn get(&self, fan_id: Id) -> Option<&FanSetting> {
-        self.settings.iter().find(|&&c| c.id == fan_id)
+        self.settings.iter().find(|c| c.id == fan_id)
     }
 
+    /// Fails with [`Error::Serialize`] for configs larger than a message,
+    /// [`WireConfig::to_vec`] takes any.
     pub fn to_vec(&self) -> Result<Vec<u8, MAX_SERIAL_DATA_SIZE>> {
         to_vec(&self).map_err(Error::from)
     }
@@ -271,27 +749,35 @@ pub fn get_smart_duty(
     max_duty_value: u16,
     is_running: bool,
 ) -> u16 {
-    let ambient_temp = if ambient_temp < -20.0 {
-        // sane default if thermistor is unplugged
-        22.0
-    } else {
-        ambient_temp
-    };
+    let duty_percent =
+        smart_duty_percent(temp, ambient_temp, min_delta, max_temp, is_running);
+    (max_duty_value as f32 / 100.0 * duty_percent) as u16
+}
+
+/// [`get_smart_duty`] in percent.
+pub fn smart_duty_percent(
+    temp: f32,
+    ambient_temp: f32,
+    min_delta: f32,
+    max_temp: f32,
+    is_running: bool,
+) -> f32 {
+    let ambient_temp = ambient_or_default(ambient_temp);
     let trigger_temp = ambient_temp + min_delta;
 
     // if we are 1C below the minimum trigger delta turn off
     if is_running && temp <= trigger_temp - SWITCH_TEMP_BUFFER {
-        return 0;
+        return 0.0;
     }
 
     // if not running and temp delta isn't reached keep off
     if !is_running && temp <= trigger_temp {
-        return 0;
+        return 0.0;
     }
 
     // if we reached the max temp run at full speed.
     if temp >= max_temp {
-        return max_duty_value;
+        return MAX_DUTY_PERCENT;
     }
 
     let calculate = |(min_temp, min_duty): TempDuty,
@@ -300,218 +786,5 @@ pub fn get_smart_duty(
             + min_duty
     };
 
-    let duty_percent = calculate((trigger_temp, 20.0), (max_temp, 100.0));
-
-    (max_duty_value as f32 / 100.0 * duty_percent) as u16
-}
-
-#[cfg(feature = "std")]
-pub mod serial {
-    extern crate std;
-
-    use std::{
-        boxed::Box,
-        io::{Read, Write},
-        string::{String, ToString},
-        time::Duration,
-        vec,
-        vec::Vec,
-    };
-
-    use anyhow::{anyhow, bail, Ok, Result};
-    use log::info;
-    use serialport::{ClearBuffer, DataBits, SerialPort, SerialPortType};
-
-    use super::{Config, Data, DataRef, Msg, Stats, MAX_SERIAL_DATA_SIZE, OTW};
-
-    const SERIAL_TIMEOUT_MS: u64 = 20;
-
-    pub struct OpilioSerialDevice {
-        name: String,
-        port: Box<dyn SerialPort>,
-    }
-
-    #[derive(Clone, Debug, PartialEq, Eq)]
-    pub struct PortWithSerialNumber {
-        pub port_name: String,
-        pub serial_number: Option<String>,
-    }
-
-    impl std::fmt::Display for PortWithSerialNumber {
-        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "{}", self.port_name)
-        }
-    }
-
-    impl std::fmt::Debug for OpilioSerialDevice {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            f.debug_struct("OpilioSerial")
-                .field("name", &self.name)
-                .finish()
-        }
-    }
-
-    impl OpilioSerialDevice {
-        pub fn new(port_name: &str) -> Result<Self> {
-            let port = Self::open_port(port_name)?;
-            Ok(Self {
-                port,
-                name: port_name.to_string(),
-            })
-        }
-
-        pub fn find_ports(
-            vid: u16,
-            pid: u16,
-        ) -> Result<Vec<PortWithSerialNumber>, anyhow::Error> {
-            let ports: Vec<_> = serialport::available_ports()?
-                .into_iter()
-                .filter_map(|info| {
-                    if let SerialPortType::UsbPort(port) = info.port_type {
-                        if port.vid == vid && port.pid == pid {
-                            Some(PortWithSerialNumber {
-                                port_name: info.port_name,
-                                serial_number: port.serial_number,
-                            })
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            Ok(ports)
-        }
-
-        pub fn version(&self) -> Result<String> {
-            Ok("0.1.0".to_string())
-        }
-
-        pub fn ping(&mut self) -> Result<u32> {
-            self.clear_buffers()?;
-            let cmd = OTW::serialised_vec(Msg::Ping, DataRef::Empty)?;
-            self.port.write_all(&cmd)?;
-
-            let mut buffer = vec![0; MAX_SERIAL_DATA_SIZE];
-
-            if self.port.read(buffer.as_mut_slice())? == 0 {
-                bail!("Failed to read any bytes from the port")
-            }
-
-            let response = OTW::from_bytes(&buffer)?;
-            info!("Received {:?}", response);
-            match response.data {
-                Data::Pong(p) => Ok(p),
-                _ => bail!("Failed to get data"),
-            }
-        }
-
-        pub fn get_stats(&mut self) -> Result<Stats> {
-            self.clear_buffers()?;
-
-            let cmd = OTW::serialised_vec(Msg::GetStats, DataRef::Empty)?;
-            self.port.write_all(&cmd)?;
-
-            let mut buffer = vec![0; MAX_SERIAL_DATA_SIZE];
-
-            if self.port.read(buffer.as_mut_slice())? == 0 {
-                bail!("Failed to read any bytes from the port")
-            }
-
-            let response = OTW::from_bytes(&buffer)?;
-            info!("Received {:?}", response);
-            match response.data {
-                Data::Stats(s) => Ok(s),
-                _ => bail!("Failed to get data"),
-            }
-        }
-
-        pub fn upload_config(&mut self, config: Config) -> Result<()> {
-            self.clear_buffers()?;
-            let cmd = OTW::serialised_vec(
-                Msg::UploadConfig,
-                DataRef::Config(&config),
-            )?;
-
-            log::info!("sending all bytes {:?}", cmd);
-            self.port.write_all(&cmd)?;
-
-            let mut buffer = vec![0; MAX_SERIAL_DATA_SIZE];
-
-            if self.port.read(buffer.as_mut_slice())? == 0 {
-                bail!("Failed to read any bytes from the port")
-            }
-
-            Ok(())
-        }
-
-        pub fn save_config(&mut self) -> Result<()> {
-            self.clear_buffers()?;
-            let cmd = OTW::serialised_vec(Msg::SaveConfig, DataRef::Empty)?;
-            log::info!("saving config {:?}", cmd);
-            self.port.write_all(&cmd)?;
-
-            let mut buffer = vec![0; MAX_SERIAL_DATA_SIZE];
-
-            if self.port.read(buffer.as_mut_slice())? == 0 {
-                bail!("Failed to read any bytes from the port")
-            }
-            Ok(())
-        }
-
-        pub fn get_config(&mut self) -> Result<Config> {
-            self.clear_buffers()?;
-            let cmd = OTW::serialised_vec(Msg::GetConfig, DataRef::Empty)?;
-            self.port.write_all(&cmd)?;
-
-            let mut buffer = vec![0; MAX_SERIAL_DATA_SIZE];
-
-            if self.port.read(buffer.as_mut_slice())? == 0 {
-                bail!("Failed to read any bytes from the port")
-            }
-            let response = OTW::from_bytes(&buffer)?;
-            match response.data {
-                Data::Config(s) => Ok(s),
-                _ => bail!("Failed to get data"),
-            }
-        }
-
-        pub fn reload(&mut self) -> Result<()> {
-            self.clear_buffers()?;
-            let cmd = OTW::serialised_vec(Msg::Reload, DataRef::Empty)?;
-            self.port.write_all(&cmd)?;
-
-            log::info!("resetting opilio {:?}", cmd);
-            self.port.write_all(&cmd)?;
-
-            let mut buffer = vec![0; MAX_SERIAL_DATA_SIZE];
-
-            if self.port.read(buffer.as_mut_slice())? == 0 {
-                bail!("Failed to read any bytes from the port")
-            }
-            Ok(())
-        }
-
-        fn clear_buffers(&mut self) -> Result<()> {
-            if let Err(e) = self.port.clear(ClearBuffer::All) {
-                log::error!("Error clearing buffers: {:?}: {}", e.kind(), e);
-                self.port = Self::open_port(&self.name)?;
-            };
-            Ok(())
-        }
-
-        fn open_port(
-            port_name: &str,
-        ) -> Result<Box<dyn SerialPort>, anyhow::Error> {
-            let port = serialport::new(port_name, 115_200)
-                .timeout(Duration::from_millis(SERIAL_TIMEOUT_MS))
-                .data_bits(DataBits::Eight)
-                .open()
-                .map_err(|e| {
-                    anyhow!("Failed to connect to {port_name}, ({e})")
-                })?;
-            Ok(port)
-        }
-    }
+    calculate((trigger_temp, 20.0), (max_temp, 100.0))
 }
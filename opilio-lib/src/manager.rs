@@ -0,0 +1,260 @@
+//! Keeps track of every attached Opilio, not just the first one found.
+extern crate std;
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fs, io,
+    path::Path,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    error::{ClientError, ConfigFileError},
+    serial::{OpilioDevice, OpilioSerialDevice, PortWithSerialNumber},
+    transport::{SerialTransport, Transport},
+    PID, VID,
+};
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+/// Where frontends keep aliases, next to their config file.
+pub const ALIASES_FILE_NAME: &str = "devices.json";
+
+/// Lists the devices attached right now.
+pub trait Enumerator {
+    fn ports(&mut self) -> Result<Vec<PortWithSerialNumber>>;
+}
+
+/// Where devices are found and how they are opened.
+pub trait Bus: Enumerator {
+    type Transport: Transport;
+
+    fn open(
+        &mut self,
+        port: &PortWithSerialNumber,
+    ) -> Result<OpilioDevice<Self::Transport>>;
+}
+
+/// Opilio devices on USB, matched by vendor and product id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsbBus {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+impl Default for UsbBus {
+    fn default() -> Self {
+        Self { vid: VID, pid: PID }
+    }
+}
+
+impl Enumerator for UsbBus {
+    fn ports(&mut self) -> Result<Vec<PortWithSerialNumber>> {
+        OpilioSerialDevice::find_ports(self.vid, self.pid)
+    }
+}
+
+impl Bus for UsbBus {
+    type Transport = SerialTransport;
+
+    fn open(
+        &mut self,
+        port: &PortWithSerialNumber,
+    ) -> Result<OpilioSerialDevice> {
+        OpilioSerialDevice::new(&port.port_name)
+    }
+}
+
+/// An attached device and the name the user gave it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceId {
+    pub port: PortWithSerialNumber,
+    pub alias: Option<String>,
+}
+
+impl DeviceId {
+    /// Stays the same across replugs, see [`PortWithSerialNumber::key`].
+    pub fn key(&self) -> &str {
+        self.port.key()
+    }
+}
+
+impl std::fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.alias {
+            Some(ref alias) => write!(f, "{alias} ({})", self.port.port_name),
+            None => write!(f, "{} ({})", self.key(), self.port.port_name),
+        }
+    }
+}
+
+/// Opens devices on demand and keeps them open, keyed by serial number.
+pub struct DeviceManager<B: Bus = UsbBus> {
+    bus: B,
+    /// Friendly names by serial number.
+    aliases: BTreeMap<String, String>,
+    devices: BTreeMap<String, OpilioDevice<B::Transport>>,
+}
+
+impl DeviceManager {
+    pub fn new() -> Self {
+        Self::with_bus(UsbBus::default())
+    }
+}
+
+impl Default for DeviceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Bus> DeviceManager<B> {
+    pub fn with_bus(bus: B) -> Self {
+        Self {
+            bus,
+            aliases: BTreeMap::new(),
+            devices: BTreeMap::new(),
+        }
+    }
+
+    /// Names the device with `serial_number`, the alias can be used instead
+    /// of the serial number or port name in [`DeviceManager::get`].
+    pub fn set_alias(
+        &mut self,
+        serial_number: impl Into<String>,
+        alias: impl Into<String>,
+    ) {
+        self.aliases.insert(serial_number.into(), alias.into());
+    }
+
+    /// Names devices from `path`, a JSON object of aliases keyed by serial
+    /// number. A missing file names none.
+    pub fn load_aliases(
+        &mut self,
+        path: &Path,
+    ) -> std::result::Result<(), ConfigFileError> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(source) => {
+                return Err(ConfigFileError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        let aliases: BTreeMap<String, String> = serde_json::from_str(&json)?;
+        self.aliases.extend(aliases);
+        Ok(())
+    }
+
+    pub fn aliases(&self) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
+    /// Devices attached right now, whether they are open or not.
+    pub fn devices(&mut self) -> Result<Vec<DeviceId>> {
+        let ports = self.bus.ports()?;
+        Ok(ports.into_iter().map(|port| self.id(port)).collect())
+    }
+
+    /// Opens the device called `name`, which is matched against aliases,
+    /// serial numbers and port names in that order.
+    pub fn get(
+        &mut self,
+        name: &str,
+    ) -> Result<&mut OpilioDevice<B::Transport>> {
+        let id = self.find(Some(name))?;
+        self.open(&id.port)
+    }
+
+    /// Opens the device called `name` like [`DeviceManager::get`], or the
+    /// only one attached when there is no name, and hands it over to the
+    /// caller.
+    pub fn take(
+        &mut self,
+        name: Option<&str>,
+    ) -> Result<(DeviceId, OpilioDevice<B::Transport>)> {
+        let id = self.find(name)?;
+        let device = match self.devices.remove(id.key()) {
+            Some(device) => device,
+            None => self.bus.open(&id.port)?,
+        };
+        Ok((id, device))
+    }
+
+    /// Devices opened so far, by serial number or port name where there is
+    /// none.
+    pub fn open_devices(
+        &mut self,
+    ) -> impl Iterator<Item = (&str, &mut OpilioDevice<B::Transport>)> {
+        self.devices
+            .iter_mut()
+            .map(|(key, device)| (key.as_str(), device))
+    }
+
+    /// Opens any newly attached device, forgets the ones that went away and
+    /// pings the rest. Returns what each device answered.
+    pub fn health_check(&mut self) -> Result<Vec<(DeviceId, Result<u32>)>> {
+        let ports = self.bus.ports()?;
+        self.devices
+            .retain(|key, _| ports.iter().any(|p| p.key() == key.as_str()));
+
+        let mut checks = Vec::with_capacity(ports.len());
+        for port in ports {
+            let result = self.open(&port).and_then(|device| device.ping());
+            if let Err(ref e) = result {
+                log::warn!("{} failed its health check ({e})", port);
+                // opened again on the next check
+                self.devices.remove(port.key());
+            }
+            checks.push((self.id(port), result));
+        }
+        Ok(checks)
+    }
+
+    fn open(
+        &mut self,
+        port: &PortWithSerialNumber,
+    ) -> Result<&mut OpilioDevice<B::Transport>> {
+        match self.devices.entry(port.key().to_string()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(self.bus.open(port)?)),
+        }
+    }
+
+    fn find(&mut self, name: Option<&str>) -> Result<DeviceId> {
+        let mut devices = self.devices()?;
+        let Some(name) = name else {
+            return match devices.len() {
+                0 => Err(ClientError::NotFound),
+                1 => Ok(devices.remove(0)),
+                _ => Err(ClientError::Ambiguous(
+                    devices.iter().map(ToString::to_string).collect(),
+                )),
+            };
+        };
+        let position = devices
+            .iter()
+            .position(|id| id.alias.as_deref() == Some(name))
+            .or_else(|| {
+                devices.iter().position(|id| {
+                    id.port.serial_number.as_deref() == Some(name)
+                })
+            })
+            .or_else(|| devices.iter().position(|id| id.port.port_name == name))
+            .ok_or(ClientError::NotFound)?;
+        Ok(devices.swap_remove(position))
+    }
+
+    /// Names `port` by its alias, if it has one.
+    pub fn id(&self, port: PortWithSerialNumber) -> DeviceId {
+        let alias = port
+            .serial_number
+            .as_ref()
+            .and_then(|serial_number| self.aliases.get(serial_number))
+            .cloned();
+        DeviceId { port, alias }
+    }
+}
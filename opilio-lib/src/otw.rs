@@ -1,24 +1,80 @@
+use crc::{Crc, CRC_16_USB};
 use heapless::Vec;
-use postcard::{from_bytes, to_vec};
-use serde::Serialize;
+use postcard::{from_bytes, take_from_bytes, to_vec};
+use serde::{Deserialize, Serialize};
 
-use crate::{error::Error, Data, DataRef, Msg, Result, MAX_SERIAL_DATA_SIZE};
+use crate::{
+    error::Error, Data, DataRef, Msg, Response, Result, MAX_SERIAL_DATA_SIZE,
+};
+
+/// Checksum appended to every frame before COBS encoding.
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_USB);
+pub const CRC_SIZE: usize = 2;
+/// Marks the end of a frame, COBS guarantees it never appears inside one.
+pub const FRAME_DELIMITER: u8 = 0;
+/// Largest encoded frame, including the COBS overhead and the delimiter.
+pub const MAX_FRAME_SIZE: usize = MAX_SERIAL_DATA_SIZE
+    + CRC_SIZE
+    + (MAX_SERIAL_DATA_SIZE + CRC_SIZE) / 254
+    + 2;
+
+/// Bytes of a [`Msg::Chunk`] message besides the chunk itself: the seq,
+/// command, data variant, offset and length varints at their longest.
+/// Commands and variants stay below 128 and take a single byte, chunk
+/// lengths below 16384 take two.
+const CHUNK_OVERHEAD: usize = 3 + 1 + 1 + 5 + 2;
+/// Most bytes carried by a single [`Chunk`], whatever is left of
+/// [`MAX_SERIAL_DATA_SIZE`] by the rest of the message.
+pub const MAX_CHUNK_SIZE: usize = MAX_SERIAL_DATA_SIZE - CHUNK_OVERHEAD;
+/// Largest payload kept in memory by either end of a chunked transfer, see
+/// [`Reassembly`]. Transfers themselves may be up to `u32::MAX` bytes.
+pub const MAX_TRANSFER_SIZE: usize = 2048;
+
+// Indices of the data variants that share a command, they have to match
+// the order of `DataRef`, which the tests check.
+
+/// Index of [`DataRef::LegacyConfig`], tells a bare config from a
+/// [`crate::wire::WireConfig`].
+pub const LEGACY_CONFIG_VARIANT: u8 = 0;
+/// Index of [`DataRef::Stats`], tells it from [`DataRef::FixedStats`].
+pub const STATS_VARIANT: u8 = 1;
+/// Index of [`DataRef::Sample`], tells it from [`DataRef::FixedSample`].
+pub const SAMPLE_VARIANT: u8 = 7;
+
+/// Sequence number of messages that answer no request in particular,
+/// replies to frames the device could not read and pushed samples. Hosts
+/// never number a request with it.
+pub const UNCORRELATED_SEQ: u16 = 0;
+
+/// Sequence number of the request following `seq`, skips
+/// [`UNCORRELATED_SEQ`] when it wraps around.
+pub fn next_seq(seq: u16) -> u16 {
+    match seq.wrapping_add(1) {
+        UNCORRELATED_SEQ => UNCORRELATED_SEQ + 1,
+        next => next,
+    }
+}
 
 /// Over The Wire protocol
 #[derive(Debug, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct OTW {
+    /// Chosen by the host for each request, the device echoes it back in
+    /// the reply so late replies can be told apart.
+    pub seq: u16,
     pub msg: Msg,
     pub data: Data,
 }
 
 impl OTW {
     pub fn serialised_vec(
+        seq: u16,
         msg: Msg,
         data: DataRef,
     ) -> Result<Vec<u8, MAX_SERIAL_DATA_SIZE>> {
         #[derive(Serialize)]
         struct OtwSerial<'a> {
+            seq: u16,
             msg: Msg,
             data: DataRef<'a>,
         }
@@ -26,53 +82,370 @@ impl OTW {
         if match msg {
             Msg::GetStats
             | Msg::SaveConfig
-            | Msg::GetConfig
             | Msg::Reload
-            | Msg::Ping => {
+            | Msg::Ping
+            | Msg::GetGeneral
+            | Msg::GetSmartMode
+            | Msg::GetControlMode
+            | Msg::CommitTransfer
+            | Msg::AbortTransfer
+            | Msg::Unsubscribe => {
                 matches!(data, DataRef::Empty)
             }
-            Msg::Config => {
-                matches!(data, DataRef::Config(_))
+            Msg::GetVersion => {
+                matches!(data, DataRef::Empty | DataRef::Protocol(_))
+            }
+            Msg::GetConfig => {
+                matches!(data, DataRef::Empty | DataRef::Layout(_))
             }
-            Msg::UploadConfig => {
-                matches!(data, DataRef::Config(_))
+            Msg::Config | Msg::UploadConfig => {
+                matches!(data, DataRef::LegacyConfig(_) | DataRef::Config(_))
             }
             Msg::Result => matches!(data, DataRef::Result(_)),
-            Msg::Stats => matches!(data, DataRef::Stats(_)),
+            Msg::Stats => {
+                matches!(data, DataRef::Stats(_) | DataRef::FixedStats(_))
+            }
             Msg::Pong => matches!(data, DataRef::Pong(_)),
+            Msg::Version => matches!(data, DataRef::Version(_)),
+            Msg::SubscribeStats => matches!(data, DataRef::Interval(_)),
+            Msg::StatsSample => {
+                matches!(data, DataRef::Sample(_) | DataRef::FixedSample(_))
+            }
+            Msg::HostTemp => matches!(data, DataRef::Temp(_)),
+            Msg::GetFanSetting | Msg::ClearOverride => {
+                matches!(data, DataRef::Id(_))
+            }
+            Msg::SetOverride => matches!(data, DataRef::Override(_)),
+            Msg::HostDuties => matches!(data, DataRef::Duties(_)),
+            Msg::FanSetting | Msg::UploadFanSetting => {
+                matches!(data, DataRef::FanSetting(_))
+            }
+            Msg::General | Msg::UploadGeneral => {
+                matches!(data, DataRef::General(_))
+            }
+            Msg::SmartMode | Msg::UploadSmartMode => {
+                matches!(data, DataRef::SmartMode(_))
+            }
+            Msg::ControlMode | Msg::UploadControlMode => {
+                matches!(data, DataRef::ControlMode(_))
+            }
+            Msg::Applied => matches!(data, DataRef::Applied(_)),
+            Msg::BeginTransfer => matches!(data, DataRef::Transfer(_)),
+            Msg::Chunk => matches!(data, DataRef::Chunk(_)),
+            Msg::Received | Msg::GetChunk => {
+                matches!(data, DataRef::Received(_))
+            }
         } {
-            let s = OtwSerial { msg, data };
+            let s = OtwSerial { seq, msg, data };
             to_vec(&s).map_err(Error::from)
         } else {
             Err(Error::InvalidMsgDataPair)
         }
     }
 
-    // returns Result<
-    pub fn serialised_ok() -> &'static [u8; 3] {
-        &[6_u8, 4, 0]
+    /// Same as [`OTW::serialised_vec`] wrapped in a frame, ready to be
+    /// written to the wire.
+    pub fn serialised_frame(
+        seq: u16,
+        msg: Msg,
+        data: DataRef,
+    ) -> Result<Vec<u8, MAX_FRAME_SIZE>> {
+        encode_frame(&Self::serialised_vec(seq, msg, data)?)
+    }
+
+    /// Framed [`Response::Ok`] answering the request numbered `seq`.
+    pub fn serialised_ok(seq: u16) -> Result<Vec<u8, MAX_FRAME_SIZE>> {
+        Self::serialised_frame(seq, Msg::Result, DataRef::Result(&Response::Ok))
     }
 
     pub fn from_bytes(slice: &[u8]) -> Result<Self> {
-        if slice.len() < 2 {
-            return Err(Error::Deserialize);
-        }
-        let command = from_bytes(&slice[0..2])?;
+        let (seq, rest) = take_from_bytes(slice)?;
+        let (command, rest) = take_from_bytes(rest)?;
+        // the data variant is implied by the command, except for configs
+        // and stats which come in several forms
+        let (variant, payload) =
+            rest.split_first().ok_or(Error::Deserialize)?;
 
         let data = match command {
+            Msg::Config | Msg::UploadConfig
+                if *variant == LEGACY_CONFIG_VARIANT =>
+            {
+                Data::LegacyConfig(from_bytes(payload)?)
+            }
             Msg::Config | Msg::UploadConfig => {
-                Data::Config(from_bytes(&slice[2..])?)
+                Data::Config(from_bytes(payload)?)
+            }
+            // hosts before the config envelope ask with no data
+            Msg::GetConfig if payload.is_empty() => Data::Empty,
+            Msg::GetConfig => Data::Layout(from_bytes(payload)?),
+            // hosts before the envelope ask with no data
+            Msg::GetVersion if payload.is_empty() => Data::Empty,
+            Msg::GetVersion => Data::Protocol(from_bytes(payload)?),
+            Msg::Stats if *variant == STATS_VARIANT => {
+                Data::Stats(from_bytes(payload)?)
+            }
+            Msg::Stats => Data::FixedStats(from_bytes(payload)?),
+            Msg::Result => Data::Result(from_bytes(payload)?),
+            Msg::Pong => Data::Pong(from_bytes(payload)?),
+            Msg::Version => Data::Version(from_bytes(payload)?),
+            Msg::SubscribeStats => Data::Interval(from_bytes(payload)?),
+            Msg::StatsSample if *variant == SAMPLE_VARIANT => {
+                Data::Sample(from_bytes(payload)?)
+            }
+            Msg::StatsSample => Data::FixedSample(from_bytes(payload)?),
+            Msg::HostTemp => Data::Temp(from_bytes(payload)?),
+            Msg::GetFanSetting | Msg::ClearOverride => {
+                Data::Id(from_bytes(payload)?)
+            }
+            Msg::SetOverride => Data::Override(from_bytes(payload)?),
+            Msg::HostDuties => Data::Duties(from_bytes(payload)?),
+            Msg::FanSetting | Msg::UploadFanSetting => {
+                Data::FanSetting(from_bytes(payload)?)
+            }
+            Msg::General | Msg::UploadGeneral => {
+                Data::General(from_bytes(payload)?)
+            }
+            Msg::SmartMode | Msg::UploadSmartMode => {
+                Data::SmartMode(from_bytes(payload)?)
+            }
+            Msg::ControlMode | Msg::UploadControlMode => {
+                Data::ControlMode(from_bytes(payload)?)
+            }
+            Msg::Applied => Data::Applied(from_bytes(payload)?),
+            Msg::BeginTransfer => Data::Transfer(from_bytes(payload)?),
+            Msg::Chunk => Data::Chunk(from_bytes(payload)?),
+            Msg::Received | Msg::GetChunk => {
+                Data::Received(from_bytes(payload)?)
             }
-            Msg::Stats => Data::Stats(from_bytes(&slice[2..])?),
-            Msg::Result => Data::Result(from_bytes(&slice[2..])?),
-            Msg::Pong => Data::Pong(from_bytes(&slice[2..])?),
 
             Msg::Ping
-            | Msg::GetConfig
             | Msg::GetStats
             | Msg::SaveConfig
-            | Msg::Reload => Data::Empty,
+            | Msg::Reload
+            | Msg::GetGeneral
+            | Msg::GetSmartMode
+            | Msg::GetControlMode
+            | Msg::CommitTransfer
+            | Msg::AbortTransfer
+            | Msg::Unsubscribe => Data::Empty,
+        };
+        Ok(Self {
+            seq,
+            msg: command,
+            data,
+        })
+    }
+}
+
+/// Appends a checksum to `payload` and COBS encodes it, terminated by
+/// [`FRAME_DELIMITER`].
+pub fn encode_frame(payload: &[u8]) -> Result<Vec<u8, MAX_FRAME_SIZE>> {
+    let mut raw: Vec<u8, { MAX_SERIAL_DATA_SIZE + CRC_SIZE }> =
+        Vec::from_slice(payload).map_err(|_| Error::Serialize)?;
+    raw.extend_from_slice(&CRC.checksum(payload).to_le_bytes())
+        .map_err(|_| Error::Serialize)?;
+
+    let mut frame: Vec<u8, MAX_FRAME_SIZE> = Vec::new();
+    frame
+        .resize_default(MAX_FRAME_SIZE)
+        .map_err(|_| Error::Serialize)?;
+    let len = cobs::encode(&raw, &mut frame);
+    frame.truncate(len);
+    frame.push(FRAME_DELIMITER).map_err(|_| Error::Serialize)?;
+    Ok(frame)
+}
+
+/// Decodes a single frame in place (without its delimiter) and returns the
+/// checked payload.
+pub fn decode_frame(frame: &mut [u8]) -> Result<&[u8]> {
+    let len = cobs::decode_in_place(frame).map_err(|_| Error::CorruptFrame)?;
+    if len < CRC_SIZE {
+        return Err(Error::CorruptFrame);
+    }
+    let (payload, crc) = frame[..len].split_at(len - CRC_SIZE);
+    if CRC.checksum(payload).to_le_bytes() != crc {
+        return Err(Error::Crc);
+    }
+    Ok(payload)
+}
+
+/// Reassembles frames from a byte stream, no matter how it was split up by
+/// the reads.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8, MAX_FRAME_SIZE>,
+    overflowed: bool,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Feeds one byte, returns the message once its frame is complete.
+    pub fn push(&mut self, byte: u8) -> Option<Result<OTW>> {
+        if byte != FRAME_DELIMITER {
+            if self.buffer.push(byte).is_err() {
+                self.overflowed = true;
+            }
+            return None;
+        }
+        // back to back delimiters, nothing to decode
+        if self.buffer.is_empty() && !self.overflowed {
+            return None;
+        }
+        let result = if self.overflowed {
+            Err(Error::FrameTooLong)
+        } else {
+            decode_frame(&mut self.buffer).and_then(OTW::from_bytes)
         };
-        Ok(Self { msg: command, data })
+        self.reset();
+        Some(result)
+    }
+
+    /// Drops any partially received frame.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.overflowed = false;
+    }
+}
+
+/// What a chunked transfer carries, tells the receiver what to do with it
+/// once committed.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransferKind {
+    /// A [`crate::wire::WireConfig`], applied like [`Msg::UploadConfig`].
+    Config,
+}
+
+/// Announces a chunked transfer, see [`Msg::BeginTransfer`].
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Transfer {
+    pub kind: TransferKind,
+    /// Length of the whole payload in bytes.
+    pub size: u32,
+    /// Checksum of the whole payload, checked on commit.
+    pub crc: u16,
+}
+
+impl Transfer {
+    pub fn new(kind: TransferKind, payload: &[u8]) -> Result<Self> {
+        Ok(Self {
+            kind,
+            size: payload
+                .len()
+                .try_into()
+                .map_err(|_| Error::TransferTooLarge)?,
+            crc: CRC.checksum(payload),
+        })
+    }
+}
+
+/// Part of a transfer starting `offset` bytes into its payload.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Chunk {
+    pub offset: u32,
+    pub bytes: Vec<u8, MAX_CHUNK_SIZE>,
+}
+
+impl Chunk {
+    /// Offset of the byte following the chunk.
+    pub fn end(&self) -> u32 {
+        self.offset.saturating_add(self.bytes.len() as u32)
+    }
+}
+
+/// Splits `payload` into the chunks of a transfer, in order.
+pub fn chunks(payload: &[u8]) -> impl Iterator<Item = Chunk> + '_ {
+    payload
+        .chunks(MAX_CHUNK_SIZE)
+        .zip((0..).step_by(MAX_CHUNK_SIZE))
+        .map(|(bytes, offset)| Chunk {
+            offset,
+            bytes: bytes.iter().copied().collect(),
+        })
+}
+
+/// Receiving end of a chunked transfer, takes payloads of up to `N` bytes.
+#[derive(Debug, Default)]
+pub struct Reassembly<const N: usize> {
+    transfer: Option<Transfer>,
+    /// Whether `transfer` was handed over, a commit sent again because its
+    /// ack got lost must not apply it twice.
+    committed: bool,
+    buffer: Vec<u8, N>,
+}
+
+impl<const N: usize> Reassembly<N> {
+    pub const fn new() -> Self {
+        Self {
+            transfer: None,
+            committed: false,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Starts receiving `transfer`, dropping any transfer in progress.
+    pub fn begin(&mut self, transfer: Transfer) -> Result<()> {
+        self.abort();
+        if transfer.size as usize > N {
+            return Err(Error::TransferTooLarge);
+        }
+        self.transfer = Some(transfer);
+        Ok(())
+    }
+
+    /// Adds `chunk` and returns the bytes received so far. Chunks have to
+    /// come in order, one received already is acked again as its ack may
+    /// have been lost.
+    pub fn chunk(&mut self, chunk: &Chunk) -> Result<u32> {
+        let transfer = match self.transfer {
+            Some(transfer) if !self.committed => transfer,
+            _ => return Err(Error::NoTransfer),
+        };
+        let received = self.buffer.len() as u32;
+        if chunk.end() <= received {
+            return Ok(received);
+        }
+        if chunk.offset != received {
+            return Err(Error::TransferOffset);
+        }
+        if chunk.end() > transfer.size {
+            return Err(Error::TransferTooLarge);
+        }
+        self.buffer
+            .extend_from_slice(&chunk.bytes)
+            .map_err(|_| Error::TransferTooLarge)?;
+        Ok(self.buffer.len() as u32)
+    }
+
+    /// Checks the payload is complete and intact and hands it over, `None`
+    /// if it was handed over already.
+    pub fn commit(&mut self) -> Result<Option<(TransferKind, &[u8])>> {
+        let transfer = self.transfer.ok_or(Error::NoTransfer)?;
+        if self.committed {
+            return Ok(None);
+        }
+        if self.buffer.len() != transfer.size as usize {
+            return Err(Error::TransferIncomplete);
+        }
+        if CRC.checksum(&self.buffer) != transfer.crc {
+            return Err(Error::TransferCrc);
+        }
+        self.committed = true;
+        Ok(Some((transfer.kind, &self.buffer)))
+    }
+
+    /// Drops the transfer and whatever was received of it.
+    pub fn abort(&mut self) {
+        self.transfer = None;
+        self.committed = false;
+        self.buffer.clear();
     }
 }
@@ -0,0 +1,444 @@
+extern crate std;
+
+use std::{
+    format,
+    future::Future,
+    pin::pin,
+    string::String,
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+    vec,
+    vec::Vec,
+};
+
+use serialport::SerialPortType;
+
+use super::{
+    error::{ClientError, Error},
+    Compatibility, Config, ControlMode, FanSetting, GeneralConfig, Id, Msg,
+    SmartMode, Stats, Version, PROTOCOL_VERSION,
+};
+use crate::{
+    async_client::AsyncOpilioDevice,
+    control::Duties,
+    otw::TransferKind,
+    stream::{StatsStream, StreamedStats},
+    transport::{AsyncTransport, SerialTransport, Transport},
+};
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+pub(crate) const SERIAL_TIMEOUT_MS: u64 = 20;
+
+/// When set, [`OpilioSerialDevice::find_ports`] reports this port instead of
+/// enumerating USB devices, e.g. the PTY of `opilio-emulator`.
+pub const PORT_OVERRIDE_ENV: &str = "OPILIO_PORT";
+
+/// How often and how patiently a failed request is sent again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one before giving up.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every following one.
+    pub backoff: Duration,
+    /// Upper bound for the wait between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Every failure is returned straight away.
+    pub const NONE: Self = Self {
+        retries: 0,
+        backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+    };
+
+    /// Wait before retry number `attempt`, starting at 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_backoff)
+    }
+
+    /// Wait before retry number `attempt` of a request that failed with
+    /// `err`, `None` once it has to be given up.
+    pub(crate) fn retry_after(
+        &self,
+        attempt: u32,
+        err: &ClientError,
+    ) -> Option<Duration> {
+        let retry = err.is_transient() || err.is_disconnected();
+        (retry && attempt <= self.retries).then(|| self.delay(attempt))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// What the client last saw of the link to the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The link went away and is being re-established.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// Retries ran out, the next request tries to reconnect again.
+    Disconnected,
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Self::Connected)
+    }
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Connected => f.write_str("connected"),
+            Self::Reconnecting { attempt } => {
+                write!(f, "reconnecting (attempt {attempt})")
+            }
+            Self::Disconnected => f.write_str("disconnected"),
+        }
+    }
+}
+
+/// Blocking request/response client for the OTW protocol over any
+/// [`Transport`]. Every method waits for its counterpart of
+/// [`AsyncOpilioDevice`], see there for what it does.
+pub struct OpilioDevice<T> {
+    device: AsyncOpilioDevice<Blocking<T>>,
+}
+
+/// The usual way to talk to an Opilio, over its USB serial port.
+pub type OpilioSerialDevice = OpilioDevice<SerialTransport>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortWithSerialNumber {
+    pub port_name: String,
+    pub serial_number: Option<String>,
+}
+
+impl PortWithSerialNumber {
+    /// Identifies the device across replugs, devices that do not report a
+    /// serial number are identified by their port name instead.
+    pub fn key(&self) -> &str {
+        self.serial_number.as_deref().unwrap_or(&self.port_name)
+    }
+}
+
+impl std::fmt::Display for PortWithSerialNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.port_name)
+    }
+}
+
+/// The device understood a command but failed to carry it out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeviceError {
+    /// The command that failed.
+    pub msg: Msg,
+    /// What went wrong, as reported by the device.
+    pub error: Error,
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let action = match self.msg {
+            Msg::UploadConfig => "apply the config",
+            Msg::SaveConfig => "save the config",
+            Msg::Reload => "reload the saved config",
+            Msg::GetConfig => "send its config",
+            Msg::GetStats => "send its stats",
+            _ => "handle the request",
+        };
+        write!(f, "Opilio could not {action}, {}", self.error)
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+impl<T> std::fmt::Debug for OpilioDevice<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.device.fmt(f)
+    }
+}
+
+impl OpilioDevice<SerialTransport> {
+    /// Opens the port and checks the device speaks a compatible protocol.
+    /// Failed requests are retried with the default [`RetryPolicy`], the
+    /// port is looked up again if the device is replugged.
+    pub fn new(port_name: &str) -> Result<Self> {
+        let transport = SerialTransport::open(port_name)?;
+        let mut device = Self::with_transport(port_name, transport);
+        device.set_retry_policy(RetryPolicy::default());
+        device.handshake()?;
+        Ok(device)
+    }
+
+    /// Port the device is attached to right now, it may differ from
+    /// [`OpilioDevice::name`] after a reconnect.
+    pub fn port_name(&self) -> &str {
+        self.device.transport().0.name()
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.device.transport().0.serial_number()
+    }
+
+    pub fn find_ports(vid: u16, pid: u16) -> Result<Vec<PortWithSerialNumber>> {
+        if let Some(port_name) = std::env::var_os(PORT_OVERRIDE_ENV) {
+            return Ok(vec![PortWithSerialNumber {
+                port_name: port_name.to_string_lossy().into_owned(),
+                serial_number: None,
+            }]);
+        }
+        let ports: Vec<_> = serialport::available_ports()?
+            .into_iter()
+            .filter_map(|info| {
+                if let SerialPortType::UsbPort(port) = info.port_type {
+                    if port.vid == vid && port.pid == pid {
+                        Some(PortWithSerialNumber {
+                            port_name: info.port_name,
+                            serial_number: port.serial_number,
+                        })
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Ok(ports)
+    }
+}
+
+impl<T: Transport> OpilioDevice<T> {
+    /// Wraps an open link, requests are not retried until a
+    /// [`RetryPolicy`] is set.
+    pub fn with_transport(name: impl Into<String>, transport: T) -> Self {
+        Self {
+            device: AsyncOpilioDevice::with_transport(
+                name,
+                Blocking(transport),
+            ),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.device.set_timeout(timeout)
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.device.set_retry_policy(retry)
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.device.state()
+    }
+
+    pub fn on_state_change(
+        &mut self,
+        f: impl FnMut(ConnectionState) + Send + 'static,
+    ) {
+        self.device.on_state_change(f)
+    }
+
+    pub fn reconnect(&mut self) -> Result<()> {
+        block_on(self.device.reconnect())
+    }
+
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn version(&mut self) -> Result<Version> {
+        block_on(self.device.version())
+    }
+
+    pub fn handshake(&mut self) -> Result<Version> {
+        block_on(self.device.handshake())
+    }
+
+    pub fn ping(&mut self) -> Result<u32> {
+        block_on(self.device.ping())
+    }
+
+    pub fn get_stats(&mut self) -> Result<Stats> {
+        block_on(self.device.get_stats())
+    }
+
+    pub fn upload_config(&mut self, config: Config) -> Result<()> {
+        block_on(self.device.upload_config(config))
+    }
+
+    pub fn save_config(&mut self) -> Result<()> {
+        block_on(self.device.save_config())
+    }
+
+    pub fn get_config(&mut self) -> Result<Config> {
+        block_on(self.device.get_config())
+    }
+
+    pub fn reload(&mut self) -> Result<()> {
+        block_on(self.device.reload())
+    }
+
+    pub fn get_fan_setting(&mut self, id: Id) -> Result<FanSetting> {
+        block_on(self.device.get_fan_setting(id))
+    }
+
+    pub fn upload_fan_setting(&mut self, setting: FanSetting) -> Result<()> {
+        block_on(self.device.upload_fan_setting(setting))
+    }
+
+    pub fn get_general(&mut self) -> Result<GeneralConfig> {
+        block_on(self.device.get_general())
+    }
+
+    pub fn upload_general(&mut self, general: GeneralConfig) -> Result<()> {
+        block_on(self.device.upload_general(general))
+    }
+
+    pub fn get_smart_mode(&mut self) -> Result<Option<SmartMode>> {
+        block_on(self.device.get_smart_mode())
+    }
+
+    pub fn upload_smart_mode(
+        &mut self,
+        smart_mode: Option<SmartMode>,
+    ) -> Result<()> {
+        block_on(self.device.upload_smart_mode(smart_mode))
+    }
+
+    pub fn get_control_mode(&mut self) -> Result<ControlMode> {
+        block_on(self.device.get_control_mode())
+    }
+
+    pub fn upload_control_mode(&mut self, mode: &ControlMode) -> Result<()> {
+        block_on(self.device.upload_control_mode(mode))
+    }
+
+    pub fn transfer(
+        &mut self,
+        kind: TransferKind,
+        payload: &[u8],
+    ) -> Result<()> {
+        block_on(self.device.transfer(kind, payload))
+    }
+
+    pub fn set_host_temp(&mut self, temp: f32) -> Result<()> {
+        block_on(self.device.set_host_temp(temp))
+    }
+
+    pub fn set_override(
+        &mut self,
+        id: Id,
+        duty_percent: f32,
+        timeout: Duration,
+    ) -> Result<()> {
+        block_on(self.device.set_override(id, duty_percent, timeout))
+    }
+
+    pub fn clear_override(&mut self, id: Id) -> Result<()> {
+        block_on(self.device.clear_override(id))
+    }
+
+    pub fn set_host_duties(&mut self, duties: Duties) -> Result<()> {
+        block_on(self.device.set_host_duties(duties))
+    }
+
+    pub fn subscribe_stats(&mut self, interval: Duration) -> Result<()> {
+        block_on(self.device.subscribe_stats(interval))
+    }
+
+    pub fn unsubscribe_stats(&mut self) -> Result<()> {
+        block_on(self.device.unsubscribe_stats())
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.device.is_subscribed()
+    }
+
+    pub fn next_stats(&mut self) -> Result<StreamedStats> {
+        block_on(self.device.next_stats())
+    }
+
+    pub fn try_next_stats(&mut self) -> Result<Option<StreamedStats>> {
+        block_on(self.device.try_next_stats())
+    }
+
+    /// Iterates over the pushed samples, see [`OpilioDevice::next_stats`].
+    pub fn stats_stream(&mut self) -> StatsStream<'_, T> {
+        StatsStream { device: self }
+    }
+}
+
+/// A blocking [`Transport`] seen as an [`AsyncTransport`], its futures are
+/// ready as soon as they are polled.
+struct Blocking<T>(T);
+
+impl<T: Transport> AsyncTransport for Blocking<T> {
+    async fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.0.send(bytes)
+    }
+
+    async fn receive(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        self.0.receive(buf, timeout)
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.0.reconnect()
+    }
+
+    async fn sleep(duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// Runs a request of [`AsyncOpilioDevice`] over a [`Blocking`] transport,
+/// which never leaves it waiting to be woken.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("blocking transports are always ready"),
+    }
+}
+
+/// What to tell the user about a device that can be talked to but speaks
+/// another protocol revision, `None` if nothing is missing.
+pub fn compatibility_warning(version: &Version) -> Option<String> {
+    if version.compatibility() != Compatibility::Degraded {
+        return None;
+    }
+    let outdated = if version.protocol < PROTOCOL_VERSION {
+        "the firmware"
+    } else {
+        "this software"
+    };
+    Some(format!(
+        "Opilio firmware {} speaks protocol revision {} and this software \
+         revision {}, some features are not available until {outdated} is \
+         updated",
+        version.firmware, version.protocol, PROTOCOL_VERSION
+    ))
+}
@@ -0,0 +1,125 @@
+//! Stats pushed by a device subscribed with [`crate::Msg::SubscribeStats`].
+extern crate std;
+
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{
+    error::ClientError, otw::FrameDecoder, serial::OpilioDevice,
+    transport::Transport, Data, Sample, Stats, OTW,
+};
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+/// Samples kept while nobody reads them, older ones are dropped and show up
+/// as missed.
+const MAX_QUEUED: usize = 64;
+
+/// A sample read from the stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StreamedStats {
+    pub stats: Stats,
+    /// Position in the stream, counted by the device since subscribing.
+    pub index: u32,
+    /// Samples lost between this one and the one before, e.g. to a corrupt
+    /// frame or a reader that fell behind.
+    pub missed: u32,
+}
+
+/// Samples received but not read yet.
+#[derive(Debug, Default)]
+pub(crate) struct Samples {
+    interval: Option<Duration>,
+    queue: VecDeque<Sample>,
+    /// Index the next sample should carry.
+    next: u32,
+}
+
+impl Samples {
+    pub(crate) fn subscribe(&mut self, interval: Duration) {
+        self.queue.clear();
+        self.interval = Some(interval);
+        self.next = 0;
+    }
+
+    pub(crate) fn unsubscribe(&mut self) {
+        self.queue.clear();
+        self.interval = None;
+    }
+
+    pub(crate) fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// How long to wait for the next sample before giving up.
+    pub(crate) fn patience(&self, timeout: Duration) -> Result<Duration> {
+        let interval = self.interval.ok_or(ClientError::NotSubscribed)?;
+        Ok(interval * 2 + timeout)
+    }
+
+    /// Decodes `bytes`, queueing samples and handing every other frame to
+    /// `other`.
+    pub(crate) fn feed(
+        &mut self,
+        decoder: &mut FrameDecoder,
+        bytes: &[u8],
+        mut other: impl FnMut(crate::Result<OTW>),
+    ) {
+        for byte in bytes {
+            match decoder.push(*byte) {
+                None => {}
+                Some(Ok(OTW {
+                    data: Data::Sample(sample),
+                    ..
+                })) => self.push(sample),
+                Some(Ok(OTW {
+                    data: Data::FixedSample(sample),
+                    ..
+                })) => self.push(sample.into()),
+                Some(frame) => other(frame),
+            }
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        if self.interval.is_none() {
+            log::debug!("dropping sample #{}, not subscribed", sample.index);
+            return;
+        }
+        if self.queue.len() == MAX_QUEUED {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(sample);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<StreamedStats> {
+        let sample = self.queue.pop_front()?;
+        // an index going backwards means the device started over
+        let missed = sample.index.saturating_sub(self.next);
+        if missed > 0 {
+            log::warn!("missed {missed} samples before #{}", sample.index);
+        }
+        self.next = sample.index.wrapping_add(1);
+        Some(StreamedStats {
+            stats: sample.stats,
+            index: sample.index,
+            missed,
+        })
+    }
+}
+
+/// Blocks for every sample in turn, see [`OpilioDevice::stats_stream`].
+/// Ends once the subscription does.
+pub struct StatsStream<'a, T> {
+    pub(crate) device: &'a mut OpilioDevice<T>,
+}
+
+impl<'a, T: Transport> Iterator for StatsStream<'a, T> {
+    type Item = Result<StreamedStats>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.device.next_stats() {
+            Err(ClientError::NotSubscribed) => None,
+            result => Some(result),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Byte level links the OTW client can talk over.
+extern crate std;
+
+use std::{
+    boxed::Box,
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    string::{String, ToString},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    time::Duration,
+    vec::Vec,
+};
+
+use serialport::{ClearBuffer, DataBits, SerialPort};
+
+use crate::{
+    error::ClientError,
+    serial::{OpilioSerialDevice, PortWithSerialNumber},
+    PID, VID,
+};
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+/// A bidirectional byte stream to an Opilio device.
+pub trait Transport {
+    /// Writes all of `bytes` to the link.
+    fn send(&mut self, bytes: &[u8]) -> Result<()>;
+
+    /// Reads whatever is available into `buf`, waiting up to `timeout` for
+    /// the first byte. Returns `0` when nothing arrived in time.
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize>;
+
+    /// Discards any received bytes that have not been read yet.
+    fn clear(&mut self) -> Result<()>;
+
+    /// Re-establishes a link that went away, e.g. after the device was
+    /// replugged. Links that can not be re-established keep failing.
+    fn reconnect(&mut self) -> Result<()> {
+        Err(ClientError::Disconnected)
+    }
+}
+
+/// A byte stream to an Opilio device that is waited on instead of blocked
+/// on, see [`crate::async_client::AsyncOpilioDevice`].
+// the futures are `Send` whenever the transport is, which is all a
+// frontend spawning requests needs
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport {
+    /// Writes all of `bytes` to the link.
+    async fn send(&mut self, bytes: &[u8]) -> Result<()>;
+
+    /// Reads whatever is available into `buf`, waiting up to `timeout` for
+    /// the first byte. Returns `0` when nothing arrived in time.
+    async fn receive(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize>;
+
+    /// Re-establishes a link that went away, see [`Transport::reconnect`].
+    async fn reconnect(&mut self) -> Result<()> {
+        Err(ClientError::Disconnected)
+    }
+
+    /// Waits before a request is retried.
+    async fn sleep(duration: Duration);
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        (**self).send(bytes)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        (**self).receive(buf, timeout)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        (**self).clear()
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        (**self).reconnect()
+    }
+}
+
+fn timed_out(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// USB CDC serial port, the way a real Opilio is attached.
+pub struct SerialTransport {
+    name: String,
+    /// USB serial number of the device, used to find it again when it comes
+    /// back under a different port name.
+    serial_number: Option<String>,
+    port: Box<dyn SerialPort>,
+}
+
+impl SerialTransport {
+    pub const BAUD_RATE: u32 = 115_200;
+
+    pub fn open(port_name: &str) -> Result<Self> {
+        Ok(Self {
+            name: port_name.to_string(),
+            serial_number: serial_number_of(port_name),
+            port: Self::open_port(port_name)?,
+        })
+    }
+
+    /// Wraps an already open port, e.g. one end of a pseudo terminal.
+    pub fn from_port(port_name: &str, port: Box<dyn SerialPort>) -> Self {
+        Self {
+            name: port_name.to_string(),
+            serial_number: None,
+            port,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    fn open_port(port_name: &str) -> Result<Box<dyn SerialPort>> {
+        serialport::new(port_name, Self::BAUD_RATE)
+            .data_bits(DataBits::Eight)
+            .open()
+            .map_err(|source| open_error(port_name, source))
+    }
+}
+
+/// USB serial number of the Opilio at `port_name`, if it reports one.
+pub(crate) fn serial_number_of(port_name: &str) -> Option<String> {
+    OpilioSerialDevice::find_ports(VID, PID)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|p| p.port_name == port_name)
+        .and_then(|p| p.serial_number)
+}
+
+/// Looks a replugged device up again by its serial number, falling back to
+/// the old port name for devices that do not report one.
+pub(crate) fn find_again(
+    port_name: &str,
+    serial_number: Option<&str>,
+) -> Result<PortWithSerialNumber> {
+    OpilioSerialDevice::find_ports(VID, PID)?
+        .into_iter()
+        .find(|p| match serial_number {
+            Some(serial_number) => {
+                p.serial_number.as_deref() == Some(serial_number)
+            }
+            None => p.port_name == port_name,
+        })
+        .ok_or(ClientError::NotFound)
+}
+
+pub(crate) fn open_error(
+    port_name: &str,
+    source: serialport::Error,
+) -> ClientError {
+    match source.kind() {
+        serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) => {
+            ClientError::PermissionDenied {
+                port: port_name.to_string(),
+            }
+        }
+        _ => ClientError::Connect {
+            port: port_name.to_string(),
+            source,
+        },
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.port.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.port.set_timeout(timeout)?;
+        match self.port.read(buf) {
+            Ok(n) => Ok(n),
+            Err(e) if timed_out(&e) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        if let Err(e) = self.port.clear(ClearBuffer::All) {
+            log::error!("Error clearing buffers: {:?}: {}", e.kind(), e);
+            self.reconnect()?;
+        };
+        Ok(())
+    }
+
+    /// Looks the device up again, see [`find_again`].
+    fn reconnect(&mut self) -> Result<()> {
+        let port = find_again(&self.name, self.serial_number.as_deref())?;
+        self.port = Self::open_port(&port.port_name)?;
+        if port.port_name != self.name {
+            log::info!("Opilio moved from {} to {}", self.name, port.port_name);
+            self.name = port.port_name;
+        }
+        Ok(())
+    }
+}
+
+/// Device exposed over TCP, e.g. a serial-to-network bridge or emulator.
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self { stream })
+    }
+
+    pub fn from_stream(stream: TcpStream) -> Self {
+        Self { stream }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.stream.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        // a zero read timeout is rejected by the OS, poll for 1ms instead
+        self.stream
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        match self.stream.read(buf) {
+            Ok(0) => Err(ClientError::Disconnected),
+            Ok(n) => Ok(n),
+            Err(e) if timed_out(&e) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        let mut buf = [0; 64];
+        self.stream.set_nonblocking(true)?;
+        let res = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err(ClientError::Disconnected),
+                Ok(_) => continue,
+                Err(e) if timed_out(&e) => break Ok(()),
+                Err(e) => break Err(e.into()),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        res
+    }
+}
+
+/// In-memory link, one end talks to the other. Useful for tests and for
+/// running a software device in the same process.
+pub struct LoopbackTransport {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    pending: VecDeque<u8>,
+}
+
+impl LoopbackTransport {
+    /// Creates two connected ends, bytes sent on one are received by the
+    /// other.
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        (
+            Self {
+                tx: a_tx,
+                rx: a_rx,
+                pending: VecDeque::new(),
+            },
+            Self {
+                tx: b_tx,
+                rx: b_rx,
+                pending: VecDeque::new(),
+            },
+        )
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.tx
+            .send(bytes.to_vec())
+            .map_err(|_| ClientError::Disconnected)
+    }
+
+    fn receive(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        if self.pending.is_empty() {
+            match self.rx.recv_timeout(timeout) {
+                Ok(bytes) => self.pending.extend(bytes),
+                Err(RecvTimeoutError::Timeout) => return Ok(0),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(ClientError::Disconnected)
+                }
+            }
+        }
+        // pick up anything else that already arrived
+        while let Ok(bytes) = self.rx.try_recv() {
+            self.pending.extend(bytes);
+        }
+        let n = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.pending.clear();
+        loop {
+            match self.rx.try_recv() {
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(ClientError::Disconnected)
+                }
+            }
+        }
+    }
+}
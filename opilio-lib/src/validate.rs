@@ -0,0 +1,352 @@
+//! Checks a [`Config`] and reports every problem with it, rather than a bare
+//! yes or no, so frontends can point at what to fix before uploading.
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::fmt;
+
+use crate::{
+    curve::MIN_CURVE_POINTS, ChannelMode, Config, ControlMode, FanSetting,
+    FixedMode, Id, PidMode, SmartMode, TempSource, MAX_DUTY_PERCENT, MAX_TEMP,
+    MIN_DUTY_PERCENT, MIN_TEMP,
+};
+
+/// Issues kept in a [`Report`] without `std`. Once it is full an error takes
+/// the place of a warning, further warnings are dropped.
+pub const MAX_ISSUES: usize = 32;
+
+/// Shortest `general.sleep_after` in seconds the device accepts.
+pub const MIN_SLEEP_AFTER: u32 = 5;
+
+/// Lowest pump duty in percent a [`ControlMode`] may run the pump with.
+pub const MIN_SMART_PUMP_DUTY: f32 = 40.0;
+
+/// Issues found by [`Config::validate`], in the order of the config.
+#[cfg(feature = "std")]
+pub type Report = std::vec::Vec<ValidationIssue>;
+/// Issues found by [`Config::validate`], in the order of the config, at
+/// most [`MAX_ISSUES`] of them.
+#[cfg(not(feature = "std"))]
+pub type Report = heapless::Vec<ValidationIssue, MAX_ISSUES>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Severity {
+    /// The device refuses the config.
+    Error,
+    /// The device takes the config but it probably does not do what was
+    /// meant.
+    Warning,
+}
+
+/// Where in the config an issue is, shown as its JSON path, e.g.
+/// `settings.F1.curve[2]`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Field {
+    SleepAfter,
+    TriggerAboveAmbient,
+    UpperTemp,
+    PumpDuty,
+    /// Setting of one channel as a whole.
+    Setting(Id),
+    Curve(Id),
+    /// A single point of a curve.
+    Point(Id, usize),
+    Hysteresis(Id),
+    RampUp(Id),
+    RampDown(Id),
+    /// [`FanSetting::mode`] of a channel.
+    ChannelMode(Id),
+    Watchdog,
+    /// PID mode as a whole.
+    PidMode,
+    TargetDelta,
+    MinDuty,
+    MaxDuty,
+    DerivativeFilter,
+    PidPumpDuty,
+    FanDuty,
+    FixedPumpDuty,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SleepAfter => write!(f, "general.sleep_after"),
+            Self::TriggerAboveAmbient => {
+                write!(f, "mode.smart.trigger_above_ambient")
+            }
+            Self::UpperTemp => write!(f, "mode.smart.upper_temp"),
+            Self::PumpDuty => write!(f, "mode.smart.pump_duty"),
+            Self::Setting(id) => write!(f, "settings.{id:?}"),
+            Self::Curve(id) => write!(f, "settings.{id:?}.curve"),
+            Self::Point(id, k) => write!(f, "settings.{id:?}.curve[{k}]"),
+            Self::Hysteresis(id) => write!(f, "settings.{id:?}.hysteresis_c"),
+            Self::RampUp(id) => write!(f, "settings.{id:?}.ramp_up_pct_per_s"),
+            Self::RampDown(id) => {
+                write!(f, "settings.{id:?}.ramp_down_pct_per_s")
+            }
+            Self::ChannelMode(id) => write!(f, "settings.{id:?}.mode"),
+            Self::Watchdog => write!(f, "host_control.watchdog_s"),
+            Self::PidMode => write!(f, "mode.pid"),
+            Self::TargetDelta => write!(f, "mode.pid.target_delta"),
+            Self::MinDuty => write!(f, "mode.pid.min_duty"),
+            Self::MaxDuty => write!(f, "mode.pid.max_duty"),
+            Self::DerivativeFilter => {
+                write!(f, "mode.pid.derivative_filter_s")
+            }
+            Self::PidPumpDuty => write!(f, "mode.pid.pump_duty"),
+            Self::FanDuty => write!(f, "mode.fixed.fan_duty"),
+            Self::FixedPumpDuty => write!(f, "mode.fixed.pump_duty"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ValidationIssue {
+    pub path: Field,
+    pub severity: Severity,
+    pub message: &'static str,
+}
+
+impl ValidationIssue {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Hands every issue found to a sink, which keeps a [`Report`] or only
+/// looks for errors.
+struct Checker<'a>(&'a mut dyn FnMut(ValidationIssue));
+
+impl Checker<'_> {
+    fn error(&mut self, path: Field, message: &'static str) {
+        self.push(path, Severity::Error, message);
+    }
+
+    fn warn(&mut self, path: Field, message: &'static str) {
+        self.push(path, Severity::Warning, message);
+    }
+
+    fn push(&mut self, path: Field, severity: Severity, message: &'static str) {
+        let issue = ValidationIssue {
+            path,
+            severity,
+            message,
+        };
+        (self.0)(issue);
+    }
+}
+
+/// Runs `check` and keeps what it finds.
+fn report(check: impl FnOnce(&mut Checker)) -> Report {
+    let mut report = Report::new();
+    check(&mut Checker(&mut |issue| keep(&mut report, issue)));
+    report
+}
+
+#[cfg(feature = "std")]
+fn keep(report: &mut Report, issue: ValidationIssue) {
+    report.push(issue);
+}
+
+/// Keeps errors ahead of warnings once the report is full, so a report
+/// with room left for no error still tells whether the config is valid.
+#[cfg(not(feature = "std"))]
+fn keep(report: &mut Report, issue: ValidationIssue) {
+    if report.is_full() && issue.is_error() {
+        if let Some(k) = report.iter().rposition(|kept| !kept.is_error()) {
+            report.remove(k);
+        }
+    }
+    report.push(issue).ok();
+}
+
+/// Whether `check` finds no errors, warnings are fine. Nothing is kept, so
+/// no error is missed however many warnings come first.
+fn passes(check: impl FnOnce(&mut Checker)) -> bool {
+    let mut valid = true;
+    check(&mut Checker(&mut |issue: ValidationIssue| {
+        valid &= !issue.is_error()
+    }));
+    valid
+}
+
+impl Config {
+    /// Checks the whole config, curves included even while the mode does
+    /// not use them, so switching to curves later does not bring up a
+    /// broken one.
+    pub fn validate(&self) -> Report {
+        report(|check| self.check(check))
+    }
+
+    /// Whether [`Config::validate`] finds no errors, warnings are fine.
+    pub fn is_valid(&self) -> bool {
+        passes(|check| self.check(check))
+    }
+
+    fn check(&self, check: &mut Checker) {
+        if self.general.sleep_after < MIN_SLEEP_AFTER {
+            check.error(Field::SleepAfter, "must be at least 5 seconds");
+        }
+        match self.mode {
+            ControlMode::Smart(ref smart_mode) => smart_mode.check(check),
+            ControlMode::Curves => {}
+            ControlMode::Fixed(ref fixed_mode) => fixed_mode.check(check),
+            ControlMode::Pid(ref pid_mode) => pid_mode.check(check),
+        }
+        if matches!(self.host_control, Some(h) if h.watchdog_s == 0) {
+            check.error(Field::Watchdog, "must be at least 1 second");
+        }
+
+        for id in [Id::P1, Id::F1, Id::F2, Id::F3] {
+            match self.settings.iter().filter(|s| s.id == id).count() {
+                0 => check.warn(
+                    Field::Setting(id),
+                    "missing, the channel has no curve to follow",
+                ),
+                1 => {}
+                _ => check.error(Field::Setting(id), "set more than once"),
+            }
+        }
+        for setting in &self.settings {
+            setting.check(check);
+        }
+    }
+}
+
+impl FanSetting {
+    /// Issues with this channel alone, see [`Config::validate`].
+    pub fn validate(&self) -> Report {
+        report(|check| self.check(check))
+    }
+
+    /// Whether [`FanSetting::validate`] finds no errors, warnings are fine.
+    pub fn is_valid(&self) -> bool {
+        passes(|check| self.check(check))
+    }
+
+    fn check(&self, check: &mut Checker) {
+        let id = self.id;
+        if self.curve.len() < MIN_CURVE_POINTS {
+            check.error(Field::Curve(id), "needs at least 2 points");
+        }
+        // temperatures curves are usually driven by, others are up to the
+        // user
+        let expected = match self.source {
+            TempSource::CoolantDelta => Some(0.0..=MAX_TEMP - MIN_TEMP),
+            TempSource::Host => None,
+            _ => Some(MIN_TEMP..=MAX_TEMP),
+        };
+        for (k, &(temp, duty)) in self.curve.iter().enumerate() {
+            let point = Field::Point(id, k);
+            if !(0.0..=MAX_DUTY_PERCENT).contains(&duty) {
+                check.error(point, "duty must be between 0 and 100 %");
+            } else if duty < MIN_DUTY_PERCENT && (duty > 0.0 || !self.is_fan())
+            {
+                check.warn(point, "duty below 10 % may not spin at all");
+            }
+            if matches!(&expected, Some(range) if !range.contains(&temp)) {
+                check.warn(point, "temperature is outside what is expected");
+            }
+            if let Some(&(prev_temp, prev_duty)) =
+                k.checked_sub(1).and_then(|prev| self.curve.get(prev))
+            {
+                if temp <= prev_temp || temp.is_nan() {
+                    check.error(
+                        point,
+                        "temperature must rise from the last point",
+                    );
+                }
+                // flat segments are fine, e.g. to hold a duty over a range
+                if duty < prev_duty {
+                    check.error(point, "duty must not drop as it gets warmer");
+                }
+            }
+        }
+        if negative(self.hysteresis_c) {
+            check.error(Field::Hysteresis(id), "must not be negative");
+        }
+        if negative(self.ramp_up_pct_per_s) {
+            check.error(Field::RampUp(id), "must not be negative");
+        }
+        if negative(self.ramp_down_pct_per_s) {
+            check.error(Field::RampDown(id), "must not be negative");
+        }
+        if let ChannelMode::Fixed(duty) = self.mode {
+            if !(0.0..=MAX_DUTY_PERCENT).contains(&duty) {
+                check.error(
+                    Field::ChannelMode(id),
+                    "must be between 0 and 100 %",
+                );
+            }
+        }
+    }
+}
+
+impl SmartMode {
+    fn check(&self, check: &mut Checker) {
+        if negative(self.trigger_above_ambient) {
+            check.error(Field::TriggerAboveAmbient, "must not be negative");
+        }
+        if !(MIN_TEMP..=MAX_TEMP).contains(&self.upper_temp) {
+            check.error(Field::UpperTemp, "must be between 15 and 50 °C");
+        }
+        if !(MIN_SMART_PUMP_DUTY..=MAX_DUTY_PERCENT).contains(&self.pump_duty) {
+            check.error(Field::PumpDuty, "must be between 40 and 100 %");
+        }
+    }
+}
+
+impl FixedMode {
+    fn check(&self, check: &mut Checker) {
+        if !(0.0..=MAX_DUTY_PERCENT).contains(&self.fan_duty) {
+            check.error(Field::FanDuty, "must be between 0 and 100 %");
+        } else if self.fan_duty > 0.0 && self.fan_duty < MIN_DUTY_PERCENT {
+            check.warn(Field::FanDuty, "duty below 10 % may not spin at all");
+        }
+        if !(MIN_SMART_PUMP_DUTY..=MAX_DUTY_PERCENT).contains(&self.pump_duty) {
+            check.error(Field::FixedPumpDuty, "must be between 40 and 100 %");
+        }
+    }
+}
+
+impl PidMode {
+    fn check(&self, check: &mut Checker) {
+        if !(self.target_delta > 0.0
+            && self.target_delta <= MAX_TEMP - MIN_TEMP)
+        {
+            check.error(Field::TargetDelta, "must be between 0 and 35 °C");
+        }
+        if negative(self.kp) || negative(self.ki) || negative(self.kd) {
+            check.error(Field::PidMode, "gains must not be negative");
+        }
+        if !(0.0..=MAX_DUTY_PERCENT).contains(&self.min_duty) {
+            check.error(Field::MinDuty, "must be between 0 and 100 %");
+        }
+        if !(0.0..=MAX_DUTY_PERCENT).contains(&self.max_duty) {
+            check.error(Field::MaxDuty, "must be between 0 and 100 %");
+        } else if self.max_duty < self.min_duty {
+            check.error(Field::MaxDuty, "must not be below min_duty");
+        }
+        if negative(self.derivative_filter_s) {
+            check.error(Field::DerivativeFilter, "must not be negative");
+        }
+        if !(MIN_SMART_PUMP_DUTY..=MAX_DUTY_PERCENT).contains(&self.pump_duty) {
+            check.error(Field::PidPumpDuty, "must be between 40 and 100 %");
+        }
+    }
+}
+
+/// Also catches `NaN`, which no comparison does.
+fn negative(value: f32) -> bool {
+    value < 0.0 || value.is_nan()
+}
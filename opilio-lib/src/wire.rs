@@ -0,0 +1,611 @@
+//! Config and stats as they travel over the wire. Postcard is positional
+//! and leaves no room to grow a struct, so every layout the config ever had
+//! is kept here and converted to and from [`Config`], both ends settle on
+//! the newest one they understand.
+//!
+//! Since [`ENVELOPE_PROTOCOL`] temperatures and duties are sent as
+//! [`Fixed`], which takes fewer bytes than `f32`. Every value on the 1/16
+//! grid converts to and from `f32` without loss, [`Config::quantized`]
+//! snaps a config to it before it is uploaded, so the device holds the very
+//! same numbers as the host and both work out identical duties.
+use heapless::Vec;
+use postcard::{from_bytes, to_vec};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    control::Duties,
+    curve::{MAX_CURVE_POINTS, MIN_CURVE_POINTS},
+    error::Error,
+    otw::MAX_TRANSFER_SIZE,
+    ChannelMode, Config, ControlMode, Curve, DataRef, FanSetting, Fixed,
+    FixedMode, GeneralConfig, HostControl, Id, Interpolation, Override,
+    PidMode, Result, Sample, SmartMode, Stats, TempDuty, TempSource,
+};
+
+/// First protocol revision that wraps configs in a [`WireConfig`] and sends
+/// temperatures and duties as [`Fixed`], older firmware only knows a bare
+/// [`ConfigV1`] and [`Stats`] in `f32`.
+pub const ENVELOPE_PROTOCOL: u16 = 4;
+
+/// Nearest [`Fixed`] to `value`, `NaN` becomes `0`.
+pub fn to_fixed(value: f32) -> Fixed {
+    if value.is_nan() {
+        Fixed::ZERO
+    } else {
+        Fixed::saturating_from_num(value)
+    }
+}
+
+/// Exact for anything below a million, plenty for temperatures, duties and
+/// speeds.
+pub fn to_f32(value: Fixed) -> f32 {
+    value.to_num()
+}
+
+/// `value` as the other end sees it once sent as [`Fixed`].
+pub fn quantize(value: f32) -> f32 {
+    to_f32(to_fixed(value))
+}
+
+#[derive(
+    Copy, Clone, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigLayout {
+    /// Four point linear curves, always sent bare.
+    V1,
+    /// Curves of 2 to 8 points with their interpolation, source, hysteresis
+    /// and ramp limits, an explicit [`Config::mode`] and a
+    /// [`FanSetting::mode`] for every channel, in fixed point.
+    V2,
+}
+
+impl ConfigLayout {
+    /// Newest layout this build understands.
+    pub const CURRENT: Self = Self::V2;
+
+    /// Newest layout understood by both this build and a device speaking
+    /// `protocol`.
+    pub fn for_protocol(protocol: u16) -> Self {
+        if protocol < ENVELOPE_PROTOCOL {
+            Self::V1
+        } else {
+            Self::CURRENT
+        }
+    }
+
+    /// Data of a [`crate::Msg::GetConfig`] asking for this layout, firmware
+    /// before [`ENVELOPE_PROTOCOL`] takes no data and answers with a
+    /// [`ConfigV1`].
+    pub fn request(&self) -> DataRef<'_> {
+        match self {
+            Self::V1 => DataRef::Empty,
+            layout => DataRef::Layout(layout),
+        }
+    }
+}
+
+/// Config tagged with its layout. Variants are never reordered or removed,
+/// a new layout goes at the end.
+// no allocator on the firmware to box the config with
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WireConfig {
+    V1(ConfigV1),
+    V2(ConfigV2),
+}
+
+impl WireConfig {
+    /// Converts `config` to `layout`, fails with [`Error::ConfigLayout`]
+    /// rather than leave out settings the layout has no room for.
+    pub fn new(config: &Config, layout: ConfigLayout) -> Result<Self> {
+        Ok(match layout {
+            ConfigLayout::V1 => Self::V1(ConfigV1::try_from(config)?),
+            ConfigLayout::V2 => Self::V2(config.into()),
+        })
+    }
+
+    pub fn layout(&self) -> ConfigLayout {
+        match self {
+            Self::V1(_) => ConfigLayout::V1,
+            Self::V2(_) => ConfigLayout::V2,
+        }
+    }
+
+    /// Payload of a [`crate::otw::TransferKind::Config`] transfer, for
+    /// configs too large for a single message.
+    pub fn to_vec(&self) -> Result<Vec<u8, MAX_TRANSFER_SIZE>> {
+        to_vec(self).map_err(Error::from)
+    }
+
+    pub fn from_bytes(slice: &[u8]) -> Result<Self> {
+        from_bytes(slice).map_err(Error::from)
+    }
+
+    /// Data to send it with, a [`ConfigV1`] goes bare so firmware before
+    /// [`ENVELOPE_PROTOCOL`] can read it.
+    pub fn data(&self) -> DataRef<'_> {
+        match self {
+            Self::V1(config) => DataRef::LegacyConfig(config),
+            wire => DataRef::Config(wire),
+        }
+    }
+}
+
+impl From<WireConfig> for Config {
+    fn from(wire: WireConfig) -> Self {
+        match wire {
+            WireConfig::V1(config) => config.into(),
+            WireConfig::V2(config) => config.into(),
+        }
+    }
+}
+
+impl Config {
+    /// `self` with every temperature and duty snapped to the [`Fixed`]
+    /// grid, as a device reads it back once uploaded.
+    pub fn quantized(&self) -> Self {
+        WireConfig::V2(self.into()).into()
+    }
+}
+
+/// Config of firmware before [`ENVELOPE_PROTOCOL`].
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigV1 {
+    pub general: GeneralConfig,
+    pub smart_mode: Option<SmartMode>,
+    pub settings: Vec<FanSettingV1, 4>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FanSettingV1 {
+    pub id: Id,
+    pub curve: [TempDuty; 4],
+}
+
+impl From<ConfigV1> for Config {
+    fn from(config: ConfigV1) -> Self {
+        Self {
+            general: config.general,
+            // the curves were in control whenever smart mode was off
+            mode: config
+                .smart_mode
+                .map_or(ControlMode::Curves, ControlMode::Smart),
+            settings: config.settings.into_iter().map(Into::into).collect(),
+            host_control: None,
+        }
+    }
+}
+
+impl From<FanSettingV1> for FanSetting {
+    fn from(setting: FanSettingV1) -> Self {
+        Self {
+            curve: Curve::from_slice(&setting.curve).unwrap_or_default(),
+            ..Self::new(setting.id)
+        }
+    }
+}
+
+impl TryFrom<&Config> for ConfigV1 {
+    type Error = Error;
+
+    /// Only smart mode or the curves fit, with every channel following
+    /// the mode and no host control.
+    fn try_from(config: &Config) -> Result<Self> {
+        let smart_mode = match config.mode {
+            ControlMode::Smart(ref smart_mode) => Some(smart_mode.clone()),
+            ControlMode::Curves => None,
+            _ => return Err(Error::ConfigLayout),
+        };
+        if config.host_control.is_some() {
+            return Err(Error::ConfigLayout);
+        }
+        let mut settings = Vec::new();
+        for setting in &config.settings {
+            settings.push(setting.try_into()?).ok();
+        }
+        Ok(Self {
+            general: config.general.clone(),
+            smart_mode,
+            settings,
+        })
+    }
+}
+
+impl TryFrom<&FanSetting> for FanSettingV1 {
+    type Error = Error;
+
+    /// Only plain linear curves of up to four points fit.
+    fn try_from(setting: &FanSetting) -> Result<Self> {
+        let plain = setting.interpolation == Interpolation::Linear
+            && setting.source == TempSource::CoolantIn
+            && setting.hysteresis_c == 0.0
+            && setting.ramp_up_pct_per_s == 0.0
+            && setting.ramp_down_pct_per_s == 0.0
+            && setting.mode == ChannelMode::Follow;
+        if !plain || setting.curve.len() < MIN_CURVE_POINTS {
+            return Err(Error::ConfigLayout);
+        }
+        let mut curve: Vec<TempDuty, 4> =
+            Vec::from_slice(&setting.curve).map_err(|_| Error::ConfigLayout)?;
+        // splitting the widest segment in half leaves a linear curve as is
+        while curve.len() < 4 {
+            let width = |k: usize| curve[k + 1].0 - curve[k].0;
+            let k = (0..curve.len() - 1)
+                .max_by(|&a, &b| width(a).total_cmp(&width(b)))
+                .unwrap_or(0);
+            let (lower, upper) = (curve[k], curve[k + 1]);
+            let middle = ((lower.0 + upper.0) / 2.0, (lower.1 + upper.1) / 2.0);
+            curve.insert(k + 1, middle).ok();
+        }
+        Ok(Self {
+            id: setting.id,
+            curve: [curve[0], curve[1], curve[2], curve[3]],
+        })
+    }
+}
+
+/// Config with temperatures and duties in fixed point, an explicit
+/// [`ControlMode`] and a mode for every channel.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigV2 {
+    pub general: GeneralConfig,
+    pub mode: ControlModeV2,
+    pub settings: Vec<FanSettingV2, 4>,
+    pub host_control: Option<HostControl>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FanSettingV2 {
+    pub id: Id,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub curve: Vec<(Fixed, Fixed), MAX_CURVE_POINTS>,
+    pub interpolation: Interpolation,
+    pub source: TempSource,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub hysteresis_c: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub ramp_up_pct_per_s: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub ramp_down_pct_per_s: Fixed,
+    pub mode: ChannelModeV2,
+}
+
+/// [`ChannelMode`] in fixed point, variants are never reordered.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelModeV2 {
+    Follow,
+    Curve,
+    Fixed(#[cfg_attr(feature = "defmt", defmt(Debug2Format))] Fixed),
+}
+
+/// [`ControlMode`] in fixed point, variants are never reordered.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlModeV2 {
+    Smart(SmartModeV2),
+    Curves,
+    Fixed(FixedModeV2),
+    Pid(PidModeV2),
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmartModeV2 {
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub trigger_above_ambient: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub upper_temp: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub pump_duty: Fixed,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedModeV2 {
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub fan_duty: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub pump_duty: Fixed,
+}
+
+/// [`PidMode`] with its temperatures and duties in fixed point. The gains
+/// stay `f32`, 1/16 is far too coarse for them.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PidModeV2 {
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub target_delta: Fixed,
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub min_duty: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub max_duty: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub derivative_filter_s: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub pump_duty: Fixed,
+}
+
+impl From<&Config> for ConfigV2 {
+    fn from(config: &Config) -> Self {
+        Self {
+            general: config.general.clone(),
+            mode: (&config.mode).into(),
+            settings: config.settings.iter().map(Into::into).collect(),
+            host_control: config.host_control,
+        }
+    }
+}
+
+impl From<ConfigV2> for Config {
+    fn from(config: ConfigV2) -> Self {
+        Self {
+            general: config.general,
+            mode: config.mode.into(),
+            settings: config.settings.into_iter().map(Into::into).collect(),
+            host_control: config.host_control,
+        }
+    }
+}
+
+impl From<&FanSetting> for FanSettingV2 {
+    fn from(setting: &FanSetting) -> Self {
+        Self {
+            id: setting.id,
+            curve: setting
+                .curve
+                .iter()
+                .map(|&(temp, duty)| (to_fixed(temp), to_fixed(duty)))
+                .collect(),
+            interpolation: setting.interpolation,
+            source: setting.source,
+            hysteresis_c: to_fixed(setting.hysteresis_c),
+            ramp_up_pct_per_s: to_fixed(setting.ramp_up_pct_per_s),
+            ramp_down_pct_per_s: to_fixed(setting.ramp_down_pct_per_s),
+            mode: match setting.mode {
+                ChannelMode::Follow => ChannelModeV2::Follow,
+                ChannelMode::Curve => ChannelModeV2::Curve,
+                ChannelMode::Fixed(duty) => {
+                    ChannelModeV2::Fixed(to_fixed(duty))
+                }
+            },
+        }
+    }
+}
+
+impl From<FanSettingV2> for FanSetting {
+    fn from(setting: FanSettingV2) -> Self {
+        Self {
+            id: setting.id,
+            curve: setting
+                .curve
+                .iter()
+                .map(|&(temp, duty)| (to_f32(temp), to_f32(duty)))
+                .collect(),
+            interpolation: setting.interpolation,
+            source: setting.source,
+            hysteresis_c: to_f32(setting.hysteresis_c),
+            ramp_up_pct_per_s: to_f32(setting.ramp_up_pct_per_s),
+            ramp_down_pct_per_s: to_f32(setting.ramp_down_pct_per_s),
+            mode: match setting.mode {
+                ChannelModeV2::Follow => ChannelMode::Follow,
+                ChannelModeV2::Curve => ChannelMode::Curve,
+                ChannelModeV2::Fixed(duty) => ChannelMode::Fixed(to_f32(duty)),
+            },
+        }
+    }
+}
+
+impl From<&ControlMode> for ControlModeV2 {
+    fn from(mode: &ControlMode) -> Self {
+        match mode {
+            ControlMode::Smart(smart_mode) => Self::Smart(smart_mode.into()),
+            ControlMode::Curves => Self::Curves,
+            ControlMode::Fixed(fixed_mode) => Self::Fixed(FixedModeV2 {
+                fan_duty: to_fixed(fixed_mode.fan_duty),
+                pump_duty: to_fixed(fixed_mode.pump_duty),
+            }),
+            ControlMode::Pid(pid_mode) => Self::Pid(pid_mode.into()),
+        }
+    }
+}
+
+impl From<ControlModeV2> for ControlMode {
+    fn from(mode: ControlModeV2) -> Self {
+        match mode {
+            ControlModeV2::Smart(smart_mode) => Self::Smart(smart_mode.into()),
+            ControlModeV2::Curves => Self::Curves,
+            ControlModeV2::Fixed(fixed_mode) => Self::Fixed(FixedMode {
+                fan_duty: to_f32(fixed_mode.fan_duty),
+                pump_duty: to_f32(fixed_mode.pump_duty),
+            }),
+            ControlModeV2::Pid(pid_mode) => Self::Pid(pid_mode.into()),
+        }
+    }
+}
+
+impl From<&SmartMode> for SmartModeV2 {
+    fn from(smart_mode: &SmartMode) -> Self {
+        Self {
+            trigger_above_ambient: to_fixed(smart_mode.trigger_above_ambient),
+            upper_temp: to_fixed(smart_mode.upper_temp),
+            pump_duty: to_fixed(smart_mode.pump_duty),
+        }
+    }
+}
+
+impl From<SmartModeV2> for SmartMode {
+    fn from(smart_mode: SmartModeV2) -> Self {
+        Self {
+            trigger_above_ambient: to_f32(smart_mode.trigger_above_ambient),
+            upper_temp: to_f32(smart_mode.upper_temp),
+            pump_duty: to_f32(smart_mode.pump_duty),
+        }
+    }
+}
+
+impl From<&PidMode> for PidModeV2 {
+    fn from(mode: &PidMode) -> Self {
+        Self {
+            target_delta: to_fixed(mode.target_delta),
+            kp: mode.kp,
+            ki: mode.ki,
+            kd: mode.kd,
+            min_duty: to_fixed(mode.min_duty),
+            max_duty: to_fixed(mode.max_duty),
+            derivative_filter_s: to_fixed(mode.derivative_filter_s),
+            pump_duty: to_fixed(mode.pump_duty),
+        }
+    }
+}
+
+impl From<PidModeV2> for PidMode {
+    fn from(mode: PidModeV2) -> Self {
+        Self {
+            target_delta: to_f32(mode.target_delta),
+            kp: mode.kp,
+            ki: mode.ki,
+            kd: mode.kd,
+            min_duty: to_f32(mode.min_duty),
+            max_duty: to_f32(mode.max_duty),
+            derivative_filter_s: to_f32(mode.derivative_filter_s),
+            pump_duty: to_f32(mode.pump_duty),
+        }
+    }
+}
+
+/// [`Stats`] in fixed point along with the overrides in force, sent to hosts
+/// that announced at least [`ENVELOPE_PROTOCOL`].
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedStats {
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub pump1_rpm: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub fan1_rpm: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub fan2_rpm: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub fan3_rpm: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub coolant_temp: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub ambient_temp: Fixed,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub coolant_out_temp: Fixed,
+    pub overrides: [Option<FixedOverride>; 4],
+}
+
+/// [`Sample`] in fixed point.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedSample {
+    pub index: u32,
+    pub stats: FixedStats,
+}
+
+impl From<&Stats> for FixedStats {
+    fn from(stats: &Stats) -> Self {
+        Self {
+            pump1_rpm: to_fixed(stats.pump1_rpm),
+            fan1_rpm: to_fixed(stats.fan1_rpm),
+            fan2_rpm: to_fixed(stats.fan2_rpm),
+            fan3_rpm: to_fixed(stats.fan3_rpm),
+            coolant_temp: to_fixed(stats.coolant_temp),
+            ambient_temp: to_fixed(stats.ambient_temp),
+            coolant_out_temp: to_fixed(stats.coolant_out_temp),
+            overrides: stats.overrides.map(|o| o.as_ref().map(Into::into)),
+        }
+    }
+}
+
+impl From<FixedStats> for Stats {
+    fn from(stats: FixedStats) -> Self {
+        Self {
+            pump1_rpm: to_f32(stats.pump1_rpm),
+            fan1_rpm: to_f32(stats.fan1_rpm),
+            fan2_rpm: to_f32(stats.fan2_rpm),
+            fan3_rpm: to_f32(stats.fan3_rpm),
+            coolant_temp: to_f32(stats.coolant_temp),
+            ambient_temp: to_f32(stats.ambient_temp),
+            coolant_out_temp: to_f32(stats.coolant_out_temp),
+            overrides: stats.overrides.map(|o| o.map(Into::into)),
+        }
+    }
+}
+
+impl From<&Sample> for FixedSample {
+    fn from(sample: &Sample) -> Self {
+        Self {
+            index: sample.index,
+            stats: (&sample.stats).into(),
+        }
+    }
+}
+
+impl From<FixedSample> for Sample {
+    fn from(sample: FixedSample) -> Self {
+        Self {
+            index: sample.index,
+            stats: sample.stats.into(),
+        }
+    }
+}
+
+/// [`Override`] in fixed point.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedOverride {
+    pub id: Id,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub duty_percent: Fixed,
+    pub timeout_s: u32,
+}
+
+impl From<&Override> for FixedOverride {
+    fn from(o: &Override) -> Self {
+        Self {
+            id: o.id,
+            duty_percent: to_fixed(o.duty_percent),
+            timeout_s: o.timeout_s,
+        }
+    }
+}
+
+impl From<FixedOverride> for Override {
+    fn from(o: FixedOverride) -> Self {
+        Self {
+            id: o.id,
+            duty_percent: to_f32(o.duty_percent),
+            timeout_s: o.timeout_s,
+        }
+    }
+}
+
+/// [`Duties`] in fixed point, sent with [`crate::Msg::HostDuties`].
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedDuties(
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))] pub [Fixed; 4],
+);
+
+impl From<&Duties> for FixedDuties {
+    fn from(duties: &Duties) -> Self {
+        Self(duties.map(to_fixed))
+    }
+}
+
+impl From<FixedDuties> for Duties {
+    fn from(duties: FixedDuties) -> Self {
+        duties.0.map(to_f32)
+    }
+}
@@ -0,0 +1,179 @@
+#![cfg(feature = "tokio")]
+
+use std::{
+    net::TcpListener,
+    sync::mpsc::{channel, Receiver},
+    thread,
+    time::Duration,
+};
+
+use common::{read_request, serve, stats, version};
+use opilio_lib::{
+    async_client::{AsyncOpilioDevice, StreamTransport},
+    error::ClientError,
+    serial::{ConnectionState, RetryPolicy},
+    transport::{AsyncTransport, TcpTransport, Transport},
+    *,
+};
+use tokio::net::TcpStream;
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Client on a tokio socket, and the blocking device end of it.
+async fn connect(
+) -> (AsyncOpilioDevice<StreamTransport<TcpStream>>, TcpTransport) {
+    let (host, device) = socket().await;
+    let mut client = AsyncOpilioDevice::with_io("tcp", host);
+    client.set_timeout(TIMEOUT);
+    (client, device)
+}
+
+/// Both ends of a fresh socket.
+async fn socket() -> (TcpStream, TcpTransport) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (device, _) = listener.accept().unwrap();
+    (host, TcpTransport::from_stream(device))
+}
+
+#[tokio::test]
+async fn should_talk_over_an_async_stream() {
+    let (mut client, device) = connect().await;
+    // the upload picks its layout from the version it already has
+    let server = thread::spawn(move || serve(device, 6));
+
+    assert_eq!(client.handshake().await.unwrap(), version(PROTOCOL_VERSION));
+    assert_eq!(client.ping().await.unwrap(), 42);
+    assert_eq!(client.get_stats().await.unwrap(), stats());
+    assert_eq!(client.get_config().await.unwrap(), Config::default());
+    client.upload_config(Config::default()).await.unwrap();
+    client.save_config().await.unwrap();
+
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn should_time_out_without_a_device() {
+    let (mut client, _device) = connect().await;
+    assert!(matches!(
+        client.ping().await,
+        Err(ClientError::Timeout(Msg::Ping))
+    ));
+}
+
+#[tokio::test]
+async fn should_report_a_closed_link() {
+    let (mut client, device) = connect().await;
+    drop(device);
+    assert!(client.ping().await.unwrap_err().is_disconnected());
+}
+
+#[tokio::test]
+async fn should_recover_from_a_cancelled_request() {
+    let (mut client, mut device) = connect().await;
+
+    // give up on the first ping before the device answers
+    let cancelled =
+        tokio::time::timeout(Duration::from_millis(10), client.ping()).await;
+    assert!(cancelled.is_err());
+    let late = read_request(&mut device);
+    let reply =
+        OTW::serialised_frame(late.seq, Msg::Pong, DataRef::Pong(&1)).unwrap();
+    device.send(&reply).unwrap();
+
+    // the late reply is dropped and the next one matched
+    let server = thread::spawn(move || serve(device, 1));
+    assert_eq!(client.ping().await.unwrap(), 42);
+    server.join().unwrap();
+}
+
+#[tokio::test]
+async fn should_retry_a_lost_reply() {
+    let (mut client, mut device) = connect().await;
+    client.set_timeout(Duration::from_millis(50));
+    client.set_retry_policy(RetryPolicy {
+        retries: 1,
+        backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(1),
+    });
+
+    let server = thread::spawn(move || {
+        // the first ping goes unanswered
+        read_request(&mut device);
+        serve(device, 1)
+    });
+    assert_eq!(client.ping().await.unwrap(), 42);
+    server.join().unwrap();
+}
+
+/// Socket that is replaced by the next one handed out by the test whenever
+/// the client reconnects, like a device coming back after a replug.
+struct Replugged {
+    link: StreamTransport<TcpStream>,
+    next: Receiver<TcpStream>,
+}
+
+impl AsyncTransport for Replugged {
+    async fn send(
+        &mut self,
+        bytes: &[u8],
+    ) -> std::result::Result<(), ClientError> {
+        self.link.send(bytes).await
+    }
+
+    async fn receive(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> std::result::Result<usize, ClientError> {
+        self.link.receive(buf, timeout).await
+    }
+
+    async fn reconnect(&mut self) -> std::result::Result<(), ClientError> {
+        let io = self.next.try_recv().map_err(|_| ClientError::NotFound)?;
+        self.link = StreamTransport::new(io);
+        Ok(())
+    }
+
+    async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+#[tokio::test]
+async fn should_reconnect_after_a_replug() {
+    let (host, device) = socket().await;
+    let (plug, next) = channel();
+    let link = StreamTransport::new(host);
+    let mut client =
+        AsyncOpilioDevice::with_transport("replug", Replugged { link, next });
+    client.set_timeout(TIMEOUT);
+    client.set_retry_policy(RetryPolicy {
+        retries: 2,
+        backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(5),
+    });
+
+    // subscribed, then unplugged once the version and subscription are in
+    let server = thread::spawn(move || serve(device, 2));
+    client
+        .subscribe_stats(Duration::from_millis(500))
+        .await
+        .unwrap();
+    server.join().unwrap();
+    let err = client.ping().await.unwrap_err();
+    assert!(err.is_disconnected(), "{err:?}");
+    assert_eq!(client.state(), ConnectionState::Disconnected);
+    assert!(!client.is_subscribed());
+
+    let (host, device) = socket().await;
+    plug.send(host).unwrap();
+    let server = thread::spawn(move || serve(device, 1));
+    assert_eq!(client.ping().await.unwrap(), 42);
+    assert_eq!(client.state(), ConnectionState::Connected);
+    server.join().unwrap();
+}
@@ -0,0 +1,389 @@
+#![cfg(feature = "std")]
+
+use std::{
+    net::TcpListener,
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use common::{read_request, serve, stats, version};
+use opilio_lib::{
+    error::ClientError,
+    serial::{
+        compatibility_warning, ConnectionState, DeviceError, OpilioDevice,
+        RetryPolicy,
+    },
+    transport::{LoopbackTransport, TcpTransport, Transport},
+    *,
+};
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[test]
+fn should_talk_over_loopback() {
+    let (host, device) = LoopbackTransport::pair();
+    let handle = thread::spawn(move || serve(device, 4));
+
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    client.set_timeout(TIMEOUT);
+    assert_eq!(client.version().unwrap(), version(PROTOCOL_VERSION));
+    // cached after the first query
+    assert_eq!(client.version().unwrap(), version(PROTOCOL_VERSION));
+    assert_eq!(client.ping().unwrap(), 42);
+    assert_eq!(client.get_stats().unwrap(), stats());
+    assert_eq!(client.get_config().unwrap(), Config::default());
+
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_talk_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        // the upload asks for the version first, to pick the config layout
+        serve(TcpTransport::from_stream(stream), 3)
+    });
+
+    let mut client = OpilioDevice::with_transport(
+        "tcp",
+        TcpTransport::connect(addr).unwrap(),
+    );
+    client.set_timeout(TIMEOUT);
+    assert_eq!(client.ping().unwrap(), 42);
+    client.upload_config(Config::default()).unwrap();
+
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_time_out_without_a_device() {
+    let (host, _device) = LoopbackTransport::pair();
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    assert!(matches!(
+        client.ping().unwrap_err(),
+        ClientError::Timeout(Msg::Ping)
+    ));
+}
+
+#[test]
+fn should_report_a_corrupt_reply() {
+    let (host, mut device) = LoopbackTransport::pair();
+    let handle = thread::spawn(move || {
+        let request = read_request(&mut device);
+        let mut reply =
+            OTW::serialised_frame(request.seq, Msg::Pong, DataRef::Pong(&42))
+                .unwrap();
+        reply[2] ^= 0x10;
+        device.send(&reply).unwrap();
+        // keep the link up until the client gives up
+        device
+    });
+
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    client.set_timeout(TIMEOUT);
+    let err = client.ping().unwrap_err();
+    assert!(
+        matches!(err, ClientError::Protocol(error::Error::Crc)),
+        "{err:?}"
+    );
+    assert!(err.is_transient());
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_drop_stale_replies() {
+    let (host, mut device) = LoopbackTransport::pair();
+    let handle = thread::spawn(move || {
+        let request = read_request(&mut device);
+        // a late reply to an earlier GetStats arrives first
+        let stale = OTW::serialised_frame(
+            request.seq.wrapping_sub(1),
+            Msg::Stats,
+            DataRef::Stats(&stats()),
+        )
+        .unwrap();
+        device.send(&stale).unwrap();
+        let reply = OTW::serialised_frame(
+            request.seq,
+            Msg::Result,
+            DataRef::Result(&Response::Ok),
+        )
+        .unwrap();
+        device.send(&reply).unwrap();
+
+        // only ever answers with the wrong sequence number
+        let request = read_request(&mut device);
+        let stale = OTW::serialised_frame(
+            request.seq.wrapping_add(7),
+            Msg::Result,
+            DataRef::Result(&Response::Ok),
+        )
+        .unwrap();
+        device.send(&stale).unwrap();
+        device
+    });
+
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    client.set_timeout(TIMEOUT);
+    client.save_config().unwrap();
+    let err = client.save_config().unwrap_err();
+    assert!(err.to_string().contains("sequence"), "{err}");
+    assert!(matches!(
+        err,
+        ClientError::StaleReplies {
+            msg: Msg::SaveConfig,
+            stale: 1,
+            ..
+        }
+    ));
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_refuse_incompatible_protocol() {
+    let (host, mut device) = LoopbackTransport::pair();
+    let handle = thread::spawn(move || {
+        let request = read_request(&mut device);
+        assert_eq!(request.msg, Msg::GetVersion);
+        let reply = OTW::serialised_frame(
+            request.seq,
+            Msg::Version,
+            DataRef::Version(&version(MIN_PROTOCOL_VERSION - 1)),
+        )
+        .unwrap();
+        device.send(&reply).unwrap();
+        device
+    });
+
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    client.set_timeout(TIMEOUT);
+    let err = client.handshake().unwrap_err();
+    assert!(err.to_string().contains("update the firmware"), "{err}");
+    assert!(
+        matches!(err, ClientError::Incompatible(v) if v.protocol == MIN_PROTOCOL_VERSION - 1)
+    );
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_warn_about_a_different_protocol() {
+    assert_eq!(compatibility_warning(&version(PROTOCOL_VERSION)), None);
+    assert_eq!(
+        compatibility_warning(&version(MIN_PROTOCOL_VERSION - 1)),
+        None
+    );
+
+    let older = compatibility_warning(&version(MIN_PROTOCOL_VERSION)).unwrap();
+    assert!(older.contains("until the firmware is updated"), "{older}");
+    let newer = compatibility_warning(&version(PROTOCOL_VERSION + 1)).unwrap();
+    assert!(newer.contains("until this software is updated"), "{newer}");
+}
+
+#[test]
+fn should_not_subscribe_to_stats_on_old_firmware() {
+    let (host, mut device) = LoopbackTransport::pair();
+    let handle = thread::spawn(move || {
+        let request = read_request(&mut device);
+        let reply = OTW::serialised_frame(
+            request.seq,
+            Msg::Version,
+            DataRef::Version(&version(2)),
+        )
+        .unwrap();
+        device.send(&reply).unwrap();
+        device
+    });
+
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    client.set_timeout(TIMEOUT);
+    let err = client
+        .subscribe_stats(Duration::from_millis(500))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Unsupported {
+            msg: Msg::SubscribeStats,
+            ..
+        }
+    ));
+    assert!(!client.is_subscribed());
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_send_old_firmware_a_bare_config() {
+    let (host, mut device) = LoopbackTransport::pair();
+    let handle = thread::spawn(move || {
+        let request = read_request(&mut device);
+        let reply = OTW::serialised_frame(
+            request.seq,
+            Msg::Version,
+            DataRef::Version(&version(2)),
+        )
+        .unwrap();
+        device.send(&reply).unwrap();
+
+        let request = read_request(&mut device);
+        assert!(matches!(request.data, Data::LegacyConfig(_)));
+        device
+            .send(&OTW::serialised_ok(request.seq).unwrap())
+            .unwrap();
+        device
+    });
+
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    client.set_timeout(TIMEOUT);
+    client.upload_config(Config::default()).unwrap();
+
+    // settings the old layout has no room for never leave the host
+    let mut config = Config::default();
+    config.settings[0].interpolation = Interpolation::MonotoneCubic;
+    let err = client.upload_config(config).unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Protocol(error::Error::ConfigLayout)
+    ));
+    // nor can it take parts of a config on their own
+    let err = client.upload_general(GeneralConfig::default()).unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Unsupported {
+            msg: Msg::UploadGeneral,
+            ..
+        }
+    ));
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_surface_device_errors() {
+    let (host, mut device) = LoopbackTransport::pair();
+    let handle = thread::spawn(move || {
+        let request = read_request(&mut device);
+        assert_eq!(request.msg, Msg::Reload);
+        let reply = OTW::serialised_frame(
+            request.seq,
+            Msg::Result,
+            DataRef::Result(&Response::Error(error::Error::FlashRead)),
+        )
+        .unwrap();
+        device.send(&reply).unwrap();
+        device
+    });
+
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    client.set_timeout(TIMEOUT);
+    let err = client.reload().unwrap_err();
+    assert!(matches!(
+        err,
+        ClientError::Device(DeviceError {
+            msg: Msg::Reload,
+            error: error::Error::FlashRead
+        })
+    ));
+    handle.join().unwrap();
+}
+
+#[test]
+fn should_report_a_closed_link() {
+    let (host, device) = LoopbackTransport::pair();
+    drop(device);
+    let mut client = OpilioDevice::with_transport("loopback", host);
+    let err = client.ping().unwrap_err();
+    assert!(matches!(err, ClientError::Disconnected), "{err:?}");
+    assert!(err.is_disconnected());
+}
+
+/// Link that is replaced by the next one handed out by the test whenever
+/// the client reconnects, like a device coming back after a replug.
+struct Replugged {
+    link: LoopbackTransport,
+    next: Receiver<LoopbackTransport>,
+}
+
+impl Transport for Replugged {
+    fn send(&mut self, bytes: &[u8]) -> std::result::Result<(), ClientError> {
+        self.link.send(bytes)
+    }
+
+    fn receive(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> std::result::Result<usize, ClientError> {
+        self.link.receive(buf, timeout)
+    }
+
+    fn clear(&mut self) -> std::result::Result<(), ClientError> {
+        self.link.clear()
+    }
+
+    fn reconnect(&mut self) -> std::result::Result<(), ClientError> {
+        self.link = self.next.try_recv().map_err(|_| ClientError::NotFound)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn should_reconnect_after_a_replug() {
+    let (host, device) = LoopbackTransport::pair();
+    let (plug, next) = channel();
+    // unplugged straight away
+    drop(device);
+
+    let mut client =
+        OpilioDevice::with_transport("replug", Replugged { link: host, next });
+    client.set_timeout(TIMEOUT);
+    client.set_retry_policy(RetryPolicy {
+        retries: 2,
+        backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(5),
+    });
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let seen = states.clone();
+    client.on_state_change(move |state| seen.lock().unwrap().push(state));
+
+    let err = client.ping().unwrap_err();
+    assert!(err.is_disconnected(), "{err:?}");
+    assert_eq!(client.state(), ConnectionState::Disconnected);
+
+    let (host, device) = LoopbackTransport::pair();
+    plug.send(host).unwrap();
+    let handle = thread::spawn(move || serve(device, 1));
+    assert_eq!(client.ping().unwrap(), 42);
+    assert_eq!(client.state(), ConnectionState::Connected);
+    handle.join().unwrap();
+
+    assert_eq!(
+        *states.lock().unwrap(),
+        [
+            ConnectionState::Reconnecting { attempt: 1 },
+            ConnectionState::Disconnected,
+            ConnectionState::Reconnecting { attempt: 2 },
+            ConnectionState::Disconnected,
+            ConnectionState::Reconnecting { attempt: 1 },
+            ConnectionState::Connected,
+        ]
+    );
+}
+
+#[test]
+fn should_back_off_exponentially() {
+    let policy = RetryPolicy {
+        retries: 5,
+        backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(350),
+    };
+    assert_eq!(policy.delay(1), Duration::from_millis(100));
+    assert_eq!(policy.delay(2), Duration::from_millis(200));
+    assert_eq!(policy.delay(3), Duration::from_millis(350));
+    assert_eq!(RetryPolicy::NONE.delay(1), Duration::ZERO);
+}
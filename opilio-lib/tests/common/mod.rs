@@ -0,0 +1,90 @@
+//! Device side of the client tests, answering the way the firmware would.
+// every test binary uses a different part
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use opilio_lib::{
+    otw::{FrameDecoder, MAX_FRAME_SIZE},
+    transport::Transport,
+    wire::{ConfigLayout, WireConfig},
+    *,
+};
+
+pub fn stats() -> Stats {
+    Stats {
+        pump1_rpm: 2400.0,
+        fan1_rpm: 800.0,
+        fan2_rpm: 810.0,
+        fan3_rpm: 820.0,
+        coolant_temp: 30.0,
+        ambient_temp: 22.0,
+        coolant_out_temp: 28.0,
+        ..Default::default()
+    }
+}
+
+pub fn version(protocol: u16) -> Version {
+    Version {
+        firmware: SemVer {
+            major: 1,
+            minor: 2,
+            patch: 3,
+        },
+        protocol,
+        hardware: 1,
+    }
+}
+
+pub fn read_request(transport: &mut impl Transport) -> OTW {
+    let mut decoder = FrameDecoder::new();
+    let mut buf = [0; MAX_FRAME_SIZE];
+    loop {
+        let n = transport.receive(&mut buf, Duration::from_secs(5)).unwrap();
+        assert!(n > 0, "client did not send a request");
+        for byte in &buf[..n] {
+            if let Some(request) = decoder.push(*byte) {
+                return request.unwrap();
+            }
+        }
+    }
+}
+
+/// Answers `count` requests the way the firmware would.
+pub fn serve(mut transport: impl Transport, count: usize) {
+    for _ in 0..count {
+        let reply = answer(&read_request(&mut transport));
+        // dribble the reply out to make sure the client reassembles it
+        let (head, tail) = reply.split_at(reply.len() / 2);
+        transport.send(head).unwrap();
+        transport.send(tail).unwrap();
+    }
+}
+
+/// Reply the firmware would send to `request`.
+pub fn answer(request: &OTW) -> Vec<u8> {
+    let seq = request.seq;
+    match request.msg {
+        Msg::Ping => OTW::serialised_frame(seq, Msg::Pong, DataRef::Pong(&42)),
+        Msg::GetStats => {
+            OTW::serialised_frame(seq, Msg::Stats, DataRef::Stats(&stats()))
+        }
+        Msg::GetVersion => OTW::serialised_frame(
+            seq,
+            Msg::Version,
+            DataRef::Version(&version(PROTOCOL_VERSION)),
+        ),
+        Msg::GetConfig => {
+            let config =
+                WireConfig::new(&Config::default(), ConfigLayout::V2).unwrap();
+            OTW::serialised_frame(seq, Msg::Config, config.data())
+        }
+        _ => OTW::serialised_frame(
+            seq,
+            Msg::Result,
+            DataRef::Result(&Response::Ok),
+        ),
+    }
+    .unwrap()
+    .to_vec()
+}
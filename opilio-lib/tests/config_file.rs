@@ -0,0 +1,133 @@
+#![cfg(feature = "std")]
+
+use std::{env, fs, process};
+
+use opilio_lib::{
+    config_file::{self, CONFIG_FILE_VERSION},
+    error::ConfigFileError,
+    *,
+};
+
+/// Layout written before the led and buzzer could be switched off.
+const UNVERSIONED: &str = r#"{
+  "general": { "sleep_after": 60 },
+  "smart_mode": null,
+  "settings": [
+    { "id": "P1", "curve": [[10, 90], [20, 90], [30, 100], [40, 100]] },
+    { "id": "F1", "curve": [[20, 20], [25, 30], [30, 50], [40, 100]] }
+  ]
+}"#;
+
+#[test]
+fn should_migrate_unversioned_files() {
+    let (config, migrations) = config_file::from_json(UNVERSIONED).unwrap();
+    assert_eq!(migrations.len(), 2);
+    assert_eq!(migrations[0].from, 0);
+    assert_eq!(config.mode, ControlMode::Curves);
+    assert_eq!(config.general.sleep_after, 60);
+    assert_eq!(config.general.led, SwitchMode::On);
+    assert_eq!(config.general.buzzer, SwitchMode::On);
+    assert_eq!(config.settings.len(), 2);
+
+    // written files are current and need no migration
+    let json = config_file::to_json(&config).unwrap();
+    assert!(
+        json.starts_with(&format!("{{\n  \"version\": {CONFIG_FILE_VERSION},"))
+    );
+    let (read, migrations) = config_file::from_json(&json).unwrap();
+    assert_eq!(read, config);
+    assert!(migrations.is_empty());
+}
+
+#[test]
+fn should_read_values_as_the_device_holds_them() {
+    let json = UNVERSIONED.replace("[25, 30]", "[25.3, 30.1]");
+    let (config, _) = config_file::from_json(&json).unwrap();
+    assert_eq!(config.settings[1].curve[1], (25.3125, 30.125));
+    assert_eq!(config, config.quantized());
+}
+
+#[test]
+fn should_migrate_smart_mode() {
+    let v1 = |smart_mode: &str| {
+        let json = format!(
+            r#"{{
+              "version": 1,
+              "general": {{ "sleep_after": 60, "led": "on", "buzzer": "off" }},
+              "smart_mode": {smart_mode},
+              "settings": []
+            }}"#
+        );
+        let (config, migrations) = config_file::from_json(&json).unwrap();
+        assert_eq!(migrations.len(), 1);
+        assert_eq!(migrations[0].from, 1);
+        config.mode
+    };
+    let smart =
+        r#"{ "trigger_above_ambient": 4, "upper_temp": 38, "pump_duty": 90 }"#;
+    assert_eq!(
+        v1(smart),
+        ControlMode::Smart(SmartMode {
+            trigger_above_ambient: 4.0,
+            upper_temp: 38.0,
+            pump_duty: 90.0,
+        })
+    );
+    assert_eq!(v1("null"), ControlMode::Curves);
+
+    // the mode is written out by name
+    let config = Config {
+        mode: ControlMode::Curves,
+        ..Default::default()
+    };
+    let json = config_file::to_json(&config).unwrap();
+    assert!(json.contains(r#""mode": "curves""#), "{json}");
+    let config = Config::default();
+    let json = config_file::to_json(&config).unwrap();
+    assert!(
+        json.contains(
+            r#""mode": {
+    "smart": {"#
+        ),
+        "{json}"
+    );
+}
+
+#[test]
+fn should_refuse_unknown_versions() {
+    let json = format!(r#"{{"version": {}}}"#, CONFIG_FILE_VERSION + 1);
+    assert!(matches!(
+        config_file::from_json(&json),
+        Err(ConfigFileError::TooNew(v)) if v == CONFIG_FILE_VERSION + 1
+    ));
+    assert!(matches!(
+        config_file::from_json(r#"{"version": "one"}"#),
+        Err(ConfigFileError::Layout(_))
+    ));
+    assert!(matches!(
+        config_file::from_json("[]"),
+        Err(ConfigFileError::Layout(_))
+    ));
+}
+
+#[test]
+fn should_back_up_migrated_files() {
+    let dir = env::temp_dir().join(format!("opilio-config-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("opilio.json");
+    fs::write(&path, UNVERSIONED).unwrap();
+
+    let loaded = config_file::load(&path).unwrap();
+    assert_eq!(loaded.migrations.len(), 2);
+    let backup = loaded.backup.unwrap();
+    assert_eq!(backup, dir.join("opilio.json.bak"));
+    assert_eq!(fs::read_to_string(&backup).unwrap(), UNVERSIONED);
+
+    // the file was rewritten, loading it again changes nothing
+    let again = config_file::load(&path).unwrap();
+    assert_eq!(again.config, loaded.config);
+    assert!(again.migrations.is_empty());
+    assert!(again.backup.is_none());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "std")]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use opilio_lib::{
+    error::ClientError,
+    hotplug::{HotplugEvent, HotplugWatcher, PortDiff},
+    manager::Enumerator,
+    serial::PortWithSerialNumber,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn port(port_name: &str, serial_number: &str) -> PortWithSerialNumber {
+    PortWithSerialNumber {
+        port_name: port_name.to_string(),
+        serial_number: Some(serial_number.to_string()),
+    }
+}
+
+/// Reports whatever the test put on the bus.
+#[derive(Clone, Default)]
+struct FakeEnumerator(Arc<Mutex<Vec<PortWithSerialNumber>>>);
+
+impl Enumerator for FakeEnumerator {
+    fn ports(&mut self) -> Result<Vec<PortWithSerialNumber>, ClientError> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+}
+
+#[test]
+fn should_diff_enumerations() {
+    let mut diff = PortDiff::default();
+    let a = port("/dev/ttyACM0", "A1");
+    let b = port("/dev/ttyACM1", "B2");
+
+    assert_eq!(
+        diff.update(vec![a.clone()]),
+        [HotplugEvent::Attached(a.clone())]
+    );
+    assert_eq!(diff.update(vec![a.clone()]), []);
+    assert_eq!(
+        diff.update(vec![a.clone(), b.clone()]),
+        [HotplugEvent::Attached(b.clone())]
+    );
+
+    // A1 comes back on another port
+    let moved = port("/dev/ttyACM2", "A1");
+    assert_eq!(
+        diff.update(vec![b.clone(), moved.clone()]),
+        [
+            HotplugEvent::Detached(a),
+            HotplugEvent::Attached(moved.clone())
+        ]
+    );
+    assert_eq!(diff.ports(), [b, moved]);
+}
+
+#[test]
+fn should_report_devices_coming_and_going() {
+    let bus = FakeEnumerator::default();
+    let a = port("/dev/ttyACM0", "A1");
+    bus.0.lock().unwrap().push(a.clone());
+
+    let watcher =
+        HotplugWatcher::polling(bus.clone(), Duration::from_millis(5));
+    let events = watcher.events();
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        HotplugEvent::Attached(a.clone())
+    );
+
+    bus.0.lock().unwrap().clear();
+    assert_eq!(
+        events.recv_timeout(TIMEOUT).unwrap(),
+        HotplugEvent::Detached(a)
+    );
+    // stops the thread
+    drop(watcher);
+}
@@ -1,8 +1,18 @@
-use opilio_lib::*;
+use std::time::Duration;
+
+use opilio_lib::{
+    control::{Controller, DutyController},
+    wire::{
+        ConfigLayout, ConfigV1, ControlModeV2, FanSettingV2, FixedDuties,
+        FixedOverride, FixedSample, FixedStats, SmartModeV2, WireConfig,
+    },
+    *,
+};
 
 #[test]
 fn should_fail_with_invalid_pair() {
-    let default_config = Config::default();
+    let default_config =
+        WireConfig::new(&Config::default(), ConfigLayout::V2).unwrap();
     let empty = DataRef::Empty;
     let config = DataRef::Config(&default_config);
 
@@ -14,43 +24,54 @@ fn should_fail_with_invalid_pair() {
         coolant_temp: f32::MAX,
         coolant_out_temp: f32::MAX,
         ambient_temp: f32::MAX,
+        ..Default::default()
     });
 
     let response = DataRef::Result(&Response::Ok);
 
-    OTW::serialised_vec(Msg::SaveConfig, empty.clone()).unwrap();
-    OTW::serialised_vec(Msg::SaveConfig, config.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::SaveConfig, stats.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::SaveConfig, response.clone()).unwrap_err();
-
-    OTW::serialised_vec(Msg::GetStats, empty.clone()).unwrap();
-    OTW::serialised_vec(Msg::GetStats, config.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::GetStats, stats.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::GetStats, response.clone()).unwrap_err();
-
-    OTW::serialised_vec(Msg::Config, empty.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Config, config.clone()).unwrap();
-    OTW::serialised_vec(Msg::Config, stats.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Config, response.clone()).unwrap_err();
-
-    OTW::serialised_vec(Msg::Ping, empty.clone()).unwrap();
-    OTW::serialised_vec(Msg::Ping, stats.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Ping, response.clone()).unwrap_err();
-
-    OTW::serialised_vec(Msg::Stats, empty.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Stats, config.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Stats, stats.clone()).unwrap();
-    OTW::serialised_vec(Msg::Stats, response.clone()).unwrap_err();
-
-    OTW::serialised_vec(Msg::GetConfig, empty.clone()).unwrap();
-    OTW::serialised_vec(Msg::GetConfig, config.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::GetConfig, stats.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::GetConfig, response.clone()).unwrap_err();
-
-    OTW::serialised_vec(Msg::Result, empty.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Result, config.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Result, stats.clone()).unwrap_err();
-    OTW::serialised_vec(Msg::Result, response.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::SaveConfig, empty.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::SaveConfig, config.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::SaveConfig, stats.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::SaveConfig, response.clone()).unwrap_err();
+
+    OTW::serialised_vec(0, Msg::GetStats, empty.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::GetStats, config.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::GetStats, stats.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::GetStats, response.clone()).unwrap_err();
+
+    OTW::serialised_vec(0, Msg::Config, empty.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Config, config.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::Config, stats.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Config, response.clone()).unwrap_err();
+
+    OTW::serialised_vec(0, Msg::Ping, empty.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::Ping, stats.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Ping, response.clone()).unwrap_err();
+
+    OTW::serialised_vec(0, Msg::Stats, empty.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Stats, config.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Stats, stats.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::Stats, response.clone()).unwrap_err();
+
+    OTW::serialised_vec(0, Msg::GetConfig, empty.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::GetConfig, ConfigLayout::V2.request()).unwrap();
+    OTW::serialised_vec(0, Msg::GetConfig, config.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::GetConfig, stats.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::GetConfig, response.clone()).unwrap_err();
+
+    OTW::serialised_vec(0, Msg::Result, empty.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Result, config.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Result, stats.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::Result, response.clone()).unwrap();
+
+    let applied = DataRef::Applied(&ConfigPart::General);
+    OTW::serialised_vec(0, Msg::Applied, applied.clone()).unwrap();
+    OTW::serialised_vec(0, Msg::Applied, response.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::UploadGeneral, applied.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::GetFanSetting, empty.clone()).unwrap_err();
+    OTW::serialised_vec(0, Msg::GetFanSetting, DataRef::Id(&Id::F1)).unwrap();
+    OTW::serialised_vec(0, Msg::ClearOverride, DataRef::Id(&Id::P1)).unwrap();
+    OTW::serialised_vec(0, Msg::SetOverride, DataRef::Id(&Id::P1)).unwrap_err();
     // consider a testing framework at this point
 }
 
@@ -64,14 +85,17 @@ fn should_serde_stats_data() {
         coolant_temp: 23.0,
         coolant_out_temp: 23.0,
         ambient_temp: 20.0,
+        ..Default::default()
     };
 
-    let vec = OTW::serialised_vec(Msg::Stats, DataRef::Stats(&stats)).unwrap();
+    let vec =
+        OTW::serialised_vec(0, Msg::Stats, DataRef::Stats(&stats)).unwrap();
     println!("{:?}", vec);
     let otw = OTW::from_bytes(&vec).unwrap();
     assert_eq!(
         otw,
         OTW {
+            seq: 0,
             msg: Msg::Stats,
             data: Data::Stats(stats)
         }
@@ -92,7 +116,7 @@ fn should_serde_configs() {
     let res = Config::from_bytes(&vec).unwrap();
     assert_eq!(res, configs);
 
-    configs.smart_mode = None;
+    configs.mode = ControlMode::Curves;
     println!("{:#?}", configs);
     let vec = configs.to_vec().unwrap();
     println!("{}\n {:?}", vec.len(), vec);
@@ -109,13 +133,19 @@ fn should_serde_configs() {
 fn should_calculate_duty() {
     let mut setting = FanSetting::new(Id::P1);
     // liner curve 0 to 100 inclusive
-    setting.curve = [(0.0, 0.0), (25.0, 25.0), (50.0, 50.0), (100.0, 100.0)];
+    setting.curve = Curve::from_slice(&[
+        (0.0, 0.0),
+        (25.0, 25.0),
+        (50.0, 50.0),
+        (100.0, 100.0),
+    ])
+    .unwrap();
     println!("{:#?}", setting);
     println!("{}", serde_json::to_string_pretty(&setting).unwrap());
     let vec: heapless::Vec<u8, 256> = postcard::to_vec(&setting).unwrap();
     println!("{}", vec.len());
 
-    let temps = (0u16..=100).into_iter().collect::<Vec<_>>();
+    let temps = (0u16..=100).collect::<Vec<_>>();
 
     let duties = temps
         .iter()
@@ -135,6 +165,369 @@ fn should_calculate_duty() {
     );
 }
 
+fn curve(points: &[TempDuty]) -> Curve {
+    Curve::from_slice(points).unwrap()
+}
+
+#[test]
+fn should_step_between_points() {
+    let curve = curve(&[(20.0, 30.0), (30.0, 60.0), (40.0, 100.0)]);
+    let duty = |t| Interpolation::Step.duty(&curve, t);
+    assert_eq!(duty(10.0), 30.0);
+    assert_eq!(duty(20.0), 30.0);
+    assert_eq!(duty(29.9), 30.0);
+    assert_eq!(duty(30.0), 60.0);
+    assert_eq!(duty(39.9), 60.0);
+    assert_eq!(duty(50.0), 100.0);
+}
+
+#[test]
+fn should_interpolate_linearly() {
+    let curve = curve(&[(20.0, 30.0), (30.0, 60.0), (40.0, 100.0)]);
+    let duty = |t| Interpolation::Linear.duty(&curve, t);
+    // held flat outside the curve
+    assert_eq!(duty(0.0), 30.0);
+    assert_eq!(duty(25.0), 45.0);
+    assert_eq!(duty(35.0), 80.0);
+    assert_eq!(duty(60.0), 100.0);
+
+    // two points are enough
+    let curve = self::curve(&[(20.0, 0.0), (40.0, 100.0)]);
+    assert_eq!(Interpolation::Linear.duty(&curve, 30.0), 50.0);
+    assert_eq!(Interpolation::Linear.duty(&[], 30.0), 0.0);
+}
+
+#[test]
+fn should_run_at_the_last_duty_without_a_temperature() {
+    let curve = curve(&[(20.0, 30.0), (30.0, 60.0), (40.0, 90.0)]);
+    for interpolation in [
+        Interpolation::Step,
+        Interpolation::Linear,
+        Interpolation::MonotoneCubic,
+    ] {
+        assert_eq!(interpolation.duty(&curve, f32::NAN), 90.0);
+        assert_eq!(interpolation.duty(&curve[..1], f32::NAN), 30.0);
+    }
+}
+
+#[test]
+fn should_interpolate_monotone_cubic() {
+    let points = [
+        (20.0, 20.0),
+        (25.0, 21.0),
+        (30.0, 22.0),
+        (32.0, 80.0),
+        (40.0, 100.0),
+    ];
+    let curve = curve(&points);
+    let duty = |t| Interpolation::MonotoneCubic.duty(&curve, t);
+
+    // passes through every point
+    for (temp, expected) in points {
+        assert!((duty(temp) - expected).abs() < 1e-4, "{temp}");
+    }
+    // never drops while the temperature rises and never overshoots, even
+    // next to the steep segment
+    let mut previous = duty(15.0);
+    for step in 0..=300 {
+        let temp = 15.0 + step as f32 * 0.1;
+        let current = duty(temp);
+        assert!(current >= previous, "{temp}");
+        assert!((20.0..=100.0).contains(&current), "{temp}");
+        previous = current;
+    }
+    // smoother than the linear curve through the same points
+    assert_ne!(duty(27.5), Interpolation::Linear.duty(&curve, 27.5));
+
+    // a two point cubic is a straight line
+    let curve = self::curve(&[(20.0, 0.0), (40.0, 100.0)]);
+    assert!(
+        (Interpolation::MonotoneCubic.duty(&curve, 25.0) - 25.0).abs() < 1e-4
+    );
+}
+
+#[test]
+fn should_serde_variable_length_curves() {
+    let mut config = Config {
+        mode: ControlMode::Curves,
+        ..Default::default()
+    };
+    for setting in config.settings.iter_mut() {
+        setting.curve.clear();
+        for i in 0..curve::MAX_CURVE_POINTS {
+            let i = i as f32;
+            setting
+                .curve
+                .push((20.0 + i * 2.5, 30.0 + i * 10.0))
+                .unwrap();
+        }
+        setting.interpolation = Interpolation::MonotoneCubic;
+        assert!(setting.is_valid());
+    }
+    // the largest config still fits a single message
+    let wire = WireConfig::new(&config, ConfigLayout::CURRENT).unwrap();
+    let vec = OTW::serialised_vec(1, Msg::UploadConfig, wire.data()).unwrap();
+    assert_eq!(OTW::from_bytes(&vec).unwrap().data, Data::Config(wire));
+
+    // files written before the interpolation was selectable are linear
+    let setting: FanSetting =
+        serde_json::from_str(r#"{"id": "F1", "curve": [[20, 20], [40, 100]]}"#)
+            .unwrap();
+    assert_eq!(setting.interpolation, Interpolation::Linear);
+    assert_eq!(setting.curve.len(), 2);
+
+    let mut setting = FanSetting::new(Id::F1);
+    setting.curve.truncate(1);
+    assert!(!setting.is_valid());
+}
+
+#[test]
+fn should_report_config_issues() {
+    assert_eq!(Config::default().validate(), []);
+
+    let mut config = Config::default();
+    config.general.sleep_after = 1;
+    // flat segments are fine
+    config.settings[0].curve =
+        Curve::from_slice(&[(10.0, 90.0), (20.0, 90.0), (30.0, 100.0)])
+            .unwrap();
+    config.settings[1].curve =
+        Curve::from_slice(&[(30.0, 50.0), (30.0, 40.0), (35.0, 5.0)]).unwrap();
+    config.settings[2].hysteresis_c = -1.0;
+    config.settings[3].source = TempSource::CoolantDelta;
+    config.settings[3].curve =
+        Curve::from_slice(&[(2.0, 20.0), (10.0, 100.0)]).unwrap();
+
+    let report = config.validate();
+    let found: Vec<_> = report
+        .iter()
+        .map(|issue| (issue.path.to_string(), issue.severity))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("general.sleep_after".to_string(), Severity::Error),
+            ("settings.P1.curve[0]".to_string(), Severity::Warning),
+            ("settings.F1.curve[1]".to_string(), Severity::Error),
+            ("settings.F1.curve[1]".to_string(), Severity::Error),
+            ("settings.F1.curve[2]".to_string(), Severity::Warning),
+            ("settings.F1.curve[2]".to_string(), Severity::Error),
+            ("settings.F2.hysteresis_c".to_string(), Severity::Error),
+        ]
+    );
+    assert_eq!(
+        report[2].to_string(),
+        "settings.F1.curve[1]: temperature must rise from the last point"
+    );
+    // curves are checked even while smart mode ignores them
+    assert!(matches!(config.mode, ControlMode::Smart(_)));
+    assert!(!config.is_valid());
+
+    config.general.sleep_after = 60;
+    config.settings[1] = FanSetting::new(Id::F1);
+    config.settings[2].hysteresis_c = 1.0;
+    assert!(config.is_valid());
+    assert!(!config.validate().is_empty());
+
+    config.mode = ControlMode::Smart(SmartMode {
+        pump_duty: 20.0,
+        ..Default::default()
+    });
+    config.settings.pop();
+    let report = config.validate();
+    assert_eq!(report.len(), 3);
+    assert_eq!(report[0].path, validate::Field::PumpDuty);
+    assert_eq!(report[1].path, validate::Field::Setting(Id::F3));
+    assert!(!report[1].is_error());
+}
+
+#[test]
+fn should_not_lose_errors_behind_warnings() {
+    let mut config = Config::default();
+    for setting in config.settings.iter_mut() {
+        setting.curve.clear();
+        for i in 0..curve::MAX_CURVE_POINTS {
+            // too cold and too slow to spin, two warnings a point
+            let i = i as f32;
+            setting.curve.push((5.0 + i, 1.0 + i)).unwrap();
+        }
+    }
+    config.settings[3].hysteresis_c = -1.0;
+
+    let report = config.validate();
+    assert!(report.len() >= validate::MAX_ISSUES);
+    assert!(report
+        .iter()
+        .any(|issue| issue.path == validate::Field::Hysteresis(Id::F3)));
+    assert!(!config.is_valid());
+    assert!(!config.settings[3].is_valid());
+}
+
+#[test]
+fn should_resolve_temp_sources() {
+    let mut stats = Stats {
+        pump1_rpm: 2400.0,
+        fan1_rpm: 800.0,
+        fan2_rpm: 800.0,
+        fan3_rpm: 800.0,
+        coolant_temp: 32.0,
+        coolant_out_temp: 34.0,
+        ambient_temp: 24.0,
+        ..Default::default()
+    };
+    let temp =
+        |source: TempSource, stats: &Stats| source.temp(stats, None).unwrap();
+    assert_eq!(temp(TempSource::CoolantIn, &stats), 32.0);
+    assert_eq!(temp(TempSource::CoolantOut, &stats), 34.0);
+    assert_eq!(temp(TempSource::Ambient, &stats), 24.0);
+    assert_eq!(temp(TempSource::MaxCoolant, &stats), 34.0);
+    assert_eq!(temp(TempSource::CoolantDelta, &stats), 8.0);
+    assert_eq!(TempSource::Host.temp(&stats, None), None);
+    assert_eq!(TempSource::Host.temp(&stats, Some(70.0)), Some(70.0));
+
+    // an unplugged ambient thermistor reads far below zero
+    stats.ambient_temp = -40.0;
+    assert_eq!(temp(TempSource::Ambient, &stats), DEFAULT_AMBIENT_TEMP);
+    assert_eq!(
+        temp(TempSource::CoolantDelta, &stats),
+        32.0 - DEFAULT_AMBIENT_TEMP
+    );
+
+    let mut setting = FanSetting::new(Id::F1);
+    setting.curve = curve(&[(0.0, 0.0), (20.0, 100.0)]);
+    setting.source = TempSource::CoolantDelta;
+    stats.ambient_temp = 22.0;
+    assert_eq!(setting.get_duty_from(&stats, None, 100), 50);
+
+    // a CPU curve runs flat out once the host goes quiet, the coolant would
+    // sit far below it
+    setting.curve = curve(&[(40.0, 20.0), (80.0, 90.0)]);
+    setting.source = TempSource::Host;
+    assert_eq!(setting.get_duty_from(&stats, Some(60.0), 100), 55);
+    assert_eq!(setting.get_duty_from(&stats, None, 100), 90);
+
+    let setting: FanSetting = serde_json::from_str(
+        r#"{"id": "F2", "curve": [[0, 20], [10, 100]], "source": "coolant_delta"}"#,
+    )
+    .unwrap();
+    assert_eq!(setting.source, TempSource::CoolantDelta);
+    let setting: FanSetting =
+        serde_json::from_str(r#"{"id": "F2", "curve": [[0, 20], [10, 100]]}"#)
+            .unwrap();
+    assert_eq!(setting.source, TempSource::CoolantIn);
+}
+
+#[test]
+fn should_hold_duty_within_hysteresis() {
+    let mut setting = FanSetting::new(Id::F1);
+    setting.curve = curve(&[(20.0, 0.0), (40.0, 100.0)]);
+    setting.hysteresis_c = 2.0;
+    let mut controller = DutyController::default();
+    let mut duty =
+        |temp| controller.update(&setting, temp, Duration::ZERO, 100);
+
+    assert_eq!(duty(30.0), 50);
+    // rising is followed at once
+    assert_eq!(duty(31.0), 55);
+    // wiggling within the hysteresis changes nothing
+    assert_eq!(duty(30.0), 55);
+    assert_eq!(duty(29.5), 55);
+    assert_eq!(duty(31.0), 55);
+    // dropping further follows, still held up by the hysteresis
+    assert_eq!(duty(28.0), 50);
+    assert_eq!(duty(32.0), 60);
+}
+
+#[test]
+fn should_limit_ramp_rate() {
+    let mut setting = FanSetting::new(Id::F1);
+    setting.curve = curve(&[(20.0, 0.0), (40.0, 100.0)]);
+    setting.ramp_up_pct_per_s = 10.0;
+    setting.ramp_down_pct_per_s = 5.0;
+    let mut controller = DutyController::default();
+    let second = Duration::from_secs(1);
+    let mut duty = |temp, dt| controller.update(&setting, temp, dt, 100);
+
+    // the first update has nothing to ramp from
+    assert_eq!(duty(20.0, second), 0);
+    assert_eq!(duty(40.0, second), 10);
+    assert_eq!(duty(40.0, second * 2), 30);
+    assert_eq!(duty(40.0, Duration::from_millis(500)), 35);
+    assert_eq!(duty(20.0, second), 30);
+    assert_eq!(duty(20.0, second * 4), 10);
+    // approaching the target does not overshoot it
+    assert_eq!(duty(24.0, second * 10), 20);
+
+    // without limits the duty jumps
+    setting.ramp_up_pct_per_s = 0.0;
+    let mut controller = DutyController::default();
+    controller.update(&setting, 20.0, second, 100);
+    assert_eq!(controller.update(&setting, 40.0, Duration::ZERO, 100), 100);
+}
+
+#[test]
+fn should_hand_control_to_the_host() {
+    let stats = Stats {
+        coolant_temp: 32.0,
+        coolant_out_temp: 30.0,
+        ambient_temp: 22.0,
+        ..Default::default()
+    };
+    let second = Duration::from_secs(1);
+    let mut config = Config::default();
+    let mut controller = Controller::default();
+
+    // smart mode and the curves come out as they do on their own
+    let smart = get_smart_duty(32.0, 22.0, 5.0, 40.0, 1000, false);
+    let duties = controller.update(&config, &stats, None, second);
+    assert_eq!(duties[0], 95.0);
+    assert_eq!((duties[1] * 10.0) as u16, smart);
+    config.mode = ControlMode::Curves;
+    let duties = controller.update(&config, &stats, None, second);
+    assert_eq!(duties[1] as u16, config.settings[1].get_duty(32.0, 100));
+
+    let host = [60.0, 70.0, 80.0, 90.0];
+    assert_eq!(
+        controller.set_host_duties(&config, host),
+        Err(error::Error::NoHostControl)
+    );
+    config.host_control = Some(HostControl { watchdog_s: 2 });
+    assert!(config.is_valid());
+    assert_eq!(
+        controller.set_host_duties(&config, [0.0, 0.0, 101.0, 0.0]),
+        Err(error::Error::InvalidDuty)
+    );
+    controller.set_host_duties(&config, host).unwrap();
+    assert_eq!(controller.update(&config, &stats, None, second), host);
+    assert_eq!(controller.update(&config, &stats, None, second), host);
+    assert!(controller.is_host_driven());
+    // the host went quiet for longer than the watchdog
+    assert_eq!(controller.update(&config, &stats, None, second), duties);
+    assert!(!controller.is_host_driven());
+
+    config.host_control = Some(HostControl { watchdog_s: 0 });
+    assert_eq!(config.validate()[0].path, validate::Field::Watchdog);
+
+    // only the envelope has room for host control
+    config.host_control = Some(HostControl::default());
+    assert_eq!(
+        WireConfig::new(&config, ConfigLayout::V1),
+        Err(error::Error::ConfigLayout)
+    );
+    let wire = WireConfig::new(&config, ConfigLayout::V2).unwrap();
+    let vec = OTW::serialised_vec(0, Msg::UploadConfig, wire.data()).unwrap();
+    let Data::Config(read) = OTW::from_bytes(&vec).unwrap().data else {
+        panic!("expected a config");
+    };
+    assert_eq!(Config::from(read), config);
+
+    let duties = FixedDuties::from(&host);
+    let vec = OTW::serialised_vec(0, Msg::HostDuties, DataRef::Duties(&duties))
+        .unwrap();
+    assert_eq!(OTW::from_bytes(&vec).unwrap().data, Data::Duties(duties));
+    assert_eq!(<[f32; 4]>::from(duties), host);
+}
+
 #[test]
 fn should_calculate_smart_duty() {
     let duty = get_smart_duty(40.0, 20.0, 5.0, 100.0, 100, true);
@@ -204,16 +597,18 @@ fn should_calculate_smart_duty() {
 
 #[test]
 fn should_create_default_ok() {
-    let bytes = OTW::serialised_ok();
+    let frame = OTW::serialised_ok(7).unwrap();
 
-    println!("{bytes:?}");
+    println!("{frame:?}");
 
-    let otw = OTW::from_bytes(bytes).unwrap();
+    let mut decoder = otw::FrameDecoder::new();
+    let otw = frame.iter().find_map(|byte| decoder.push(*byte)).unwrap();
     println!("{otw:?}");
 
     assert_eq!(
-        otw,
+        otw.unwrap(),
         OTW {
+            seq: 7,
             msg: Msg::Result,
             data: Data::Result(Response::Ok)
         }
@@ -243,3 +638,820 @@ fn should_test_fixed() {
     println!("MAX_TEMP: {:?}", max_temp.to_bits());
     assert_eq!(max_temp, Fixed::from_num(MAX_TEMP));
 }
+
+#[test]
+fn should_send_fixed_point_on_the_wire() {
+    assert_eq!(wire::to_fixed(MAX_DUTY_PERCENT).to_bits(), 1600);
+    assert_eq!(wire::to_fixed(f32::NAN), Fixed::ZERO);
+    assert_eq!(wire::to_fixed(f32::INFINITY), Fixed::MAX);
+    assert_eq!(wire::quantize(27.3), 27.3125);
+    // converting back is exact, a second trip changes nothing
+    for value in [-12.5, 0.0, 0.03, 27.3, 1234.56, 3000.0] {
+        let quantized = wire::quantize(value);
+        assert!((quantized - value).abs() <= 1.0 / 32.0);
+        assert_eq!(wire::quantize(quantized), quantized);
+    }
+
+    let stats = Stats {
+        pump1_rpm: 2104.0,
+        fan1_rpm: 812.0,
+        fan2_rpm: 0.0,
+        fan3_rpm: 0.0,
+        coolant_temp: 31.3,
+        coolant_out_temp: 29.8,
+        ambient_temp: 22.1,
+        ..Default::default()
+    };
+    let fixed = FixedStats::from(&stats);
+    let vec = OTW::serialised_vec(0, Msg::Stats, DataRef::FixedStats(&fixed))
+        .unwrap();
+    let float =
+        OTW::serialised_vec(0, Msg::Stats, DataRef::Stats(&stats)).unwrap();
+    assert!(vec.len() < float.len());
+    let Data::FixedStats(read) = OTW::from_bytes(&vec).unwrap().data else {
+        panic!("expected fixed point stats");
+    };
+    let read = Stats::from(read);
+    assert_eq!(read.pump1_rpm, 2104.0);
+    assert_eq!(read.coolant_temp, wire::quantize(31.3));
+    assert_eq!(FixedStats::from(&read), fixed);
+
+    let sample = FixedSample::from(&Sample { index: 7, stats });
+    let vec =
+        OTW::serialised_vec(0, Msg::StatsSample, DataRef::FixedSample(&sample))
+            .unwrap();
+    assert_eq!(
+        OTW::from_bytes(&vec).unwrap().data,
+        Data::FixedSample(sample)
+    );
+
+    // variants sharing a command are told apart by their index, which
+    // follows the order of `DataRef`
+    let legacy = ConfigV1::try_from(&Config::default()).unwrap();
+    let plain = Sample { index: 7, stats };
+    for (msg, data, variant) in [
+        (
+            Msg::Config,
+            DataRef::LegacyConfig(&legacy),
+            otw::LEGACY_CONFIG_VARIANT,
+        ),
+        (Msg::Stats, DataRef::Stats(&stats), otw::STATS_VARIANT),
+        (
+            Msg::StatsSample,
+            DataRef::Sample(&plain),
+            otw::SAMPLE_VARIANT,
+        ),
+    ] {
+        // seq and command take a byte each
+        let vec = OTW::serialised_vec(0, msg, data).unwrap();
+        assert_eq!(vec[2], variant);
+    }
+
+    // both ends hold the same config once it was sent in fixed point
+    let mut config = Config::default();
+    config.settings[1].curve =
+        Curve::from_slice(&[(20.3, 20.1), (33.7, 55.5), (40.0, 100.0)])
+            .unwrap();
+    config.settings[1].hysteresis_c = 1.3;
+    let wire = WireConfig::new(&config, ConfigLayout::V2).unwrap();
+    let vec = OTW::serialised_vec(0, Msg::UploadConfig, wire.data()).unwrap();
+    let Data::Config(read) = OTW::from_bytes(&vec).unwrap().data else {
+        panic!("expected a config");
+    };
+    let device = Config::from(read);
+    assert_eq!(device.settings[1].curve[1], (33.6875, 55.5));
+    assert_eq!(device.settings[1].hysteresis_c, wire::quantize(1.3));
+    let host =
+        Config::from(WireConfig::new(&device, ConfigLayout::V2).unwrap());
+    assert_eq!(host, device);
+
+    // hosts announce their revision, older ones ask with no data
+    let vec = OTW::serialised_vec(
+        0,
+        Msg::GetVersion,
+        DataRef::Protocol(&PROTOCOL_VERSION),
+    )
+    .unwrap();
+    assert_eq!(
+        OTW::from_bytes(&vec).unwrap().data,
+        Data::Protocol(PROTOCOL_VERSION)
+    );
+    let vec = OTW::serialised_vec(0, Msg::GetVersion, DataRef::Empty).unwrap();
+    assert_eq!(OTW::from_bytes(&vec).unwrap().data, Data::Empty);
+}
+
+#[test]
+fn should_carry_config_parts_on_their_own() {
+    let config = Config::default();
+    let setting = FanSettingV2::from(&config.settings[1]);
+    let smart_mode = Some(SmartModeV2::from(&SmartMode::default()));
+    let mode = ControlModeV2::from(&ControlMode::Fixed(FixedMode::default()));
+    for (msg, data, expected) in [
+        (Msg::GetFanSetting, DataRef::Id(&Id::F2), Data::Id(Id::F2)),
+        (
+            Msg::UploadFanSetting,
+            DataRef::FanSetting(&setting),
+            Data::FanSetting(setting.clone()),
+        ),
+        (Msg::GetGeneral, DataRef::Empty, Data::Empty),
+        (
+            Msg::UploadGeneral,
+            DataRef::General(&config.general),
+            Data::General(config.general.clone()),
+        ),
+        (
+            Msg::UploadSmartMode,
+            DataRef::SmartMode(&smart_mode),
+            Data::SmartMode(smart_mode),
+        ),
+        (
+            Msg::UploadSmartMode,
+            DataRef::SmartMode(&None),
+            Data::SmartMode(None),
+        ),
+        (Msg::GetControlMode, DataRef::Empty, Data::Empty),
+        (
+            Msg::UploadControlMode,
+            DataRef::ControlMode(&mode),
+            Data::ControlMode(mode),
+        ),
+        (
+            Msg::Applied,
+            DataRef::Applied(&ConfigPart::FanSetting(Id::F1)),
+            Data::Applied(ConfigPart::FanSetting(Id::F1)),
+        ),
+    ] {
+        let vec = OTW::serialised_vec(3, msg, data).unwrap();
+        assert_eq!(
+            OTW::from_bytes(&vec).unwrap(),
+            OTW {
+                seq: 3,
+                msg,
+                data: expected
+            }
+        );
+        assert!(vec.len() <= MAX_SERIAL_DATA_SIZE);
+    }
+    assert_eq!(FanSetting::from(setting), config.settings[1]);
+}
+
+#[test]
+fn should_report_overrides_in_stats() {
+    let full = Override {
+        id: Id::F2,
+        duty_percent: 100.0,
+        timeout_s: 300,
+    };
+    assert!(full.is_valid());
+    assert!(!Override {
+        timeout_s: 0,
+        ..full
+    }
+    .is_valid());
+    assert!(!Override {
+        duty_percent: 120.0,
+        ..full
+    }
+    .is_valid());
+    assert!(!Override {
+        duty_percent: f32::NAN,
+        ..full
+    }
+    .is_valid());
+
+    let held = FixedOverride::from(&full);
+    let vec =
+        OTW::serialised_vec(1, Msg::SetOverride, DataRef::Override(&held))
+            .unwrap();
+    assert_eq!(OTW::from_bytes(&vec).unwrap().data, Data::Override(held));
+    assert_eq!(Override::from(held), full);
+
+    let mut stats = Stats {
+        pump1_rpm: 2000.0,
+        coolant_temp: 30.0,
+        ..Default::default()
+    };
+    stats.overrides[Id::F2.index()] = Some(full);
+    // overrides only make it to hosts that know about them
+    let fixed = FixedStats::from(&stats);
+    let vec = OTW::serialised_vec(0, Msg::Stats, DataRef::FixedStats(&fixed))
+        .unwrap();
+    let Data::FixedStats(read) = OTW::from_bytes(&vec).unwrap().data else {
+        panic!("expected stats with overrides");
+    };
+    assert_eq!(Stats::from(read), stats);
+    let vec =
+        OTW::serialised_vec(0, Msg::Stats, DataRef::Stats(&stats)).unwrap();
+    let Data::Stats(read) = OTW::from_bytes(&vec).unwrap().data else {
+        panic!("expected stats");
+    };
+    assert_eq!(read.overrides, [None; 4]);
+
+    let sample = FixedSample::from(&Sample { index: 9, stats });
+    let vec =
+        OTW::serialised_vec(0, Msg::StatsSample, DataRef::FixedSample(&sample))
+            .unwrap();
+    assert_eq!(
+        OTW::from_bytes(&vec).unwrap().data,
+        Data::FixedSample(sample)
+    );
+}
+
+#[test]
+fn should_reassemble_chunked_transfers() {
+    let payload: Vec<u8> = (0..700).map(|i| (i % 251) as u8).collect();
+    let transfer =
+        otw::Transfer::new(otw::TransferKind::Config, &payload).unwrap();
+    assert_eq!(transfer.size, 700);
+
+    let mut reassembly = otw::Reassembly::<1024>::new();
+    reassembly.begin(transfer).unwrap();
+    let chunks: Vec<_> = otw::chunks(&payload).collect();
+    assert_eq!(chunks.len(), payload.len().div_ceil(otw::MAX_CHUNK_SIZE));
+    for chunk in &chunks {
+        // every chunk fits a message of its own
+        let vec =
+            OTW::serialised_vec(9, Msg::Chunk, DataRef::Chunk(chunk)).unwrap();
+        let Data::Chunk(read) = OTW::from_bytes(&vec).unwrap().data else {
+            panic!("expected a chunk");
+        };
+        assert_eq!(&read, chunk);
+        assert_eq!(reassembly.chunk(&read), Ok(chunk.end()));
+    }
+    assert_eq!(
+        reassembly.commit(),
+        Ok(Some((otw::TransferKind::Config, payload.as_slice())))
+    );
+    // a commit sent again does not hand the payload over twice
+    assert_eq!(reassembly.commit(), Ok(None));
+    assert_eq!(reassembly.chunk(&chunks[0]), Err(error::Error::NoTransfer));
+}
+
+#[test]
+fn should_fit_a_full_chunk_in_a_message() {
+    let chunk = otw::Chunk {
+        offset: u32::MAX,
+        bytes: [u8::MAX; otw::MAX_CHUNK_SIZE].into_iter().collect(),
+    };
+    let vec = OTW::serialised_vec(u16::MAX, Msg::Chunk, DataRef::Chunk(&chunk))
+        .unwrap();
+    assert!(vec.len() <= MAX_SERIAL_DATA_SIZE);
+    assert_eq!(OTW::from_bytes(&vec).unwrap().data, Data::Chunk(chunk));
+}
+
+#[test]
+fn should_recover_from_interrupted_transfers() {
+    use error::Error;
+
+    let payload = [7; 2 * otw::MAX_CHUNK_SIZE + 100];
+    let transfer =
+        otw::Transfer::new(otw::TransferKind::Config, &payload).unwrap();
+    let chunks: Vec<_> = otw::chunks(&payload).collect();
+    let mut reassembly = otw::Reassembly::<1024>::new();
+
+    assert_eq!(reassembly.chunk(&chunks[0]), Err(Error::NoTransfer));
+    assert_eq!(reassembly.commit(), Err(Error::NoTransfer));
+    assert_eq!(
+        otw::Reassembly::<512>::new().begin(transfer),
+        Err(Error::TransferTooLarge)
+    );
+
+    reassembly.begin(transfer).unwrap();
+    assert_eq!(reassembly.chunk(&chunks[1]), Err(Error::TransferOffset));
+    assert_eq!(reassembly.chunk(&chunks[0]), Ok(chunks[0].end()));
+    // resent after a lost ack, acked again without adding it twice
+    assert_eq!(reassembly.chunk(&chunks[0]), Ok(chunks[0].end()));
+    assert_eq!(reassembly.commit(), Err(Error::TransferIncomplete));
+
+    // aborted halfway, whatever arrives next is refused
+    reassembly.abort();
+    assert_eq!(reassembly.chunk(&chunks[1]), Err(Error::NoTransfer));
+
+    // begun again, a payload mangled on the way is caught on commit
+    reassembly.begin(transfer).unwrap();
+    let mut corrupt = chunks[1].clone();
+    corrupt.bytes[3] ^= 0x10;
+    for chunk in [&chunks[0], &corrupt, &chunks[2]] {
+        reassembly.chunk(chunk).unwrap();
+    }
+    assert_eq!(reassembly.commit(), Err(Error::TransferCrc));
+
+    // chunks past the announced size are refused
+    let short = otw::Transfer::new(
+        otw::TransferKind::Config,
+        &payload[..otw::MAX_CHUNK_SIZE + 50],
+    )
+    .unwrap();
+    reassembly.begin(short).unwrap();
+    reassembly.chunk(&chunks[0]).unwrap();
+    assert_eq!(reassembly.chunk(&chunks[1]), Err(Error::TransferTooLarge));
+}
+
+#[test]
+fn should_decode_frames_split_across_reads() {
+    let stats = Stats {
+        pump1_rpm: 2000.0,
+        fan1_rpm: 0.0,
+        fan2_rpm: 500.0,
+        fan3_rpm: 0.0,
+        coolant_temp: 30.0,
+        coolant_out_temp: 28.0,
+        ambient_temp: 22.0,
+        ..Default::default()
+    };
+    let frame =
+        OTW::serialised_frame(0, Msg::Stats, DataRef::Stats(&stats)).unwrap();
+    assert_eq!(frame.last(), Some(&otw::FRAME_DELIMITER));
+    assert!(!frame[..frame.len() - 1].contains(&otw::FRAME_DELIMITER));
+
+    let mut decoder = otw::FrameDecoder::new();
+    // a byte at a time, only the delimiter completes the frame
+    for byte in &frame[..frame.len() - 1] {
+        assert!(decoder.push(*byte).is_none());
+    }
+    let otw = decoder.push(otw::FRAME_DELIMITER).unwrap().unwrap();
+    assert_eq!(otw.data, Data::Stats(stats));
+
+    // two frames back to back in a single read
+    let ping = OTW::serialised_frame(0, Msg::Ping, DataRef::Empty).unwrap();
+    let stream: Vec<u8> = frame.iter().chain(ping.iter()).copied().collect();
+    let decoded: Vec<_> = stream
+        .iter()
+        .filter_map(|b| decoder.push(*b))
+        .map(|r| r.unwrap().msg)
+        .collect();
+    assert_eq!(decoded, [Msg::Stats, Msg::Ping]);
+}
+
+#[test]
+fn should_reject_corrupt_frames() {
+    let config = WireConfig::new(&Config::default(), ConfigLayout::V2).unwrap();
+    let frame = OTW::serialised_frame(0, Msg::Config, config.data()).unwrap();
+
+    let mut decoder = otw::FrameDecoder::new();
+    let mut corrupt = frame.clone();
+    corrupt[5] ^= 0x01;
+    let result = corrupt.iter().find_map(|b| decoder.push(*b)).unwrap();
+    assert_eq!(result, Err(error::Error::Crc));
+
+    // the decoder recovers on the next frame
+    let result = frame.iter().find_map(|b| decoder.push(*b)).unwrap();
+    assert_eq!(result.unwrap().data, Data::Config(config));
+
+    // garbage without a delimiter never becomes a message
+    let result = core::iter::repeat_n(0xAA, otw::MAX_FRAME_SIZE + 10)
+        .chain(core::iter::once(otw::FRAME_DELIMITER))
+        .find_map(|b| decoder.push(b))
+        .unwrap();
+    assert_eq!(result, Err(error::Error::FrameTooLong));
+}
+
+#[test]
+fn should_carry_sequence_number() {
+    for seq in [0, 1, 127, 128, 300, u16::MAX] {
+        let vec =
+            OTW::serialised_vec(seq, Msg::Pong, DataRef::Pong(&60)).unwrap();
+        let otw = OTW::from_bytes(&vec).unwrap();
+        assert_eq!(
+            otw,
+            OTW {
+                seq,
+                msg: Msg::Pong,
+                data: Data::Pong(60)
+            }
+        );
+    }
+    // requests are never numbered like replies that answer none of them
+    assert_eq!(otw::next_seq(1), 2);
+    assert_eq!(otw::next_seq(u16::MAX), 1);
+    assert_eq!(otw::next_seq(otw::UNCORRELATED_SEQ), 1);
+}
+
+#[test]
+fn should_serde_stats_subscriptions() {
+    let vec =
+        OTW::serialised_vec(3, Msg::SubscribeStats, DataRef::Interval(&500))
+            .unwrap();
+    assert_eq!(OTW::from_bytes(&vec).unwrap().data, Data::Interval(500));
+
+    let sample = Sample {
+        index: 70_000,
+        stats: Stats {
+            pump1_rpm: 2400.0,
+            fan1_rpm: 800.0,
+            fan2_rpm: 0.0,
+            fan3_rpm: 0.0,
+            coolant_temp: 30.0,
+            coolant_out_temp: 28.0,
+            ambient_temp: 22.0,
+            ..Default::default()
+        },
+    };
+    let vec =
+        OTW::serialised_vec(0, Msg::StatsSample, DataRef::Sample(&sample))
+            .unwrap();
+    assert_eq!(
+        OTW::from_bytes(&vec).unwrap(),
+        OTW {
+            seq: 0,
+            msg: Msg::StatsSample,
+            data: Data::Sample(sample)
+        }
+    );
+    assert_eq!(
+        OTW::serialised_vec(0, Msg::SubscribeStats, DataRef::Empty),
+        Err(error::Error::InvalidMsgDataPair)
+    );
+}
+
+#[test]
+fn should_check_protocol_compatibility() {
+    let mut version = Version {
+        firmware: SemVer {
+            major: 0,
+            minor: 3,
+            patch: 1,
+        },
+        protocol: PROTOCOL_VERSION,
+        hardware: 2,
+    };
+    let vec = OTW::serialised_vec(9, Msg::Version, DataRef::Version(&version))
+        .unwrap();
+    let otw = OTW::from_bytes(&vec).unwrap();
+    assert_eq!(otw.data, Data::Version(version));
+    assert_eq!(
+        version.to_string(),
+        format!("0.3.1 (protocol {PROTOCOL_VERSION}, hw rev 2)")
+    );
+    assert_eq!(version.compatibility(), Compatibility::Full);
+
+    version.protocol = PROTOCOL_VERSION + 1;
+    assert_eq!(version.compatibility(), Compatibility::Degraded);
+
+    version.protocol = MIN_PROTOCOL_VERSION - 1;
+    assert_eq!(version.compatibility(), Compatibility::Incompatible);
+
+    version.protocol = wire::ENVELOPE_PROTOCOL - 1;
+    assert_eq!(version.compatibility(), Compatibility::Degraded);
+}
+
+#[test]
+fn should_convert_between_config_layouts() {
+    assert_eq!(ConfigLayout::for_protocol(2), ConfigLayout::V1);
+    assert_eq!(
+        ConfigLayout::for_protocol(wire::ENVELOPE_PROTOCOL - 1),
+        ConfigLayout::V1
+    );
+    assert_eq!(
+        ConfigLayout::for_protocol(wire::ENVELOPE_PROTOCOL),
+        ConfigLayout::V2
+    );
+    assert_eq!(ConfigLayout::for_protocol(u16::MAX), ConfigLayout::CURRENT);
+
+    // plain linear curves fit the old layout, short ones are padded
+    let mut config = Config::default();
+    config.settings[1].curve =
+        Curve::from_slice(&[(20.0, 20.0), (40.0, 100.0)]).unwrap();
+    let legacy = WireConfig::new(&config, ConfigLayout::V1).unwrap();
+    let WireConfig::V1(ref v1) = legacy else {
+        panic!("expected the old layout, got {legacy:?}");
+    };
+    assert_eq!(
+        v1.settings[1].curve,
+        [(20.0, 20.0), (30.0, 60.0), (35.0, 80.0), (40.0, 100.0)]
+    );
+    let restored = Config::from(legacy.clone());
+    for temp in [10.0, 20.0, 27.5, 33.0, 45.0] {
+        assert_eq!(
+            restored.settings[1].get_duty(temp, 1000),
+            config.settings[1].get_duty(temp, 1000)
+        );
+    }
+
+    // old firmware reads a bare config, newer ones the envelope
+    let vec = OTW::serialised_vec(1, Msg::UploadConfig, legacy.data()).unwrap();
+    let bare = OTW::serialised_vec(
+        1,
+        Msg::UploadConfig,
+        DataRef::LegacyConfig(&ConfigV1::try_from(&config).unwrap()),
+    )
+    .unwrap();
+    assert_eq!(vec, bare);
+    let Data::LegacyConfig(read) = OTW::from_bytes(&vec).unwrap().data else {
+        panic!("expected a bare config");
+    };
+    assert_eq!(Config::from(read), restored);
+
+    // anything the old layout has no room for is refused, not dropped
+    for change in [
+        |s: &mut FanSetting| s.interpolation = Interpolation::Step,
+        |s: &mut FanSetting| s.source = TempSource::Ambient,
+        |s: &mut FanSetting| s.hysteresis_c = 1.0,
+        |s: &mut FanSetting| s.curve.push((45.0, 100.0)).unwrap(),
+        |s: &mut FanSetting| s.mode = ChannelMode::Fixed(50.0),
+    ] {
+        let mut config = Config::default();
+        change(&mut config.settings[2]);
+        assert_eq!(
+            WireConfig::new(&config, ConfigLayout::V1),
+            Err(error::Error::ConfigLayout)
+        );
+        assert!(WireConfig::new(&config, ConfigLayout::V2).is_ok());
+    }
+    for mode in [
+        ControlMode::Fixed(FixedMode::default()),
+        ControlMode::Pid(PidMode::default()),
+    ] {
+        let config = Config {
+            mode,
+            ..Default::default()
+        };
+        assert_eq!(
+            WireConfig::new(&config, ConfigLayout::V1),
+            Err(error::Error::ConfigLayout)
+        );
+        let wire = WireConfig::new(&config, ConfigLayout::V2).unwrap();
+        assert_eq!(Config::from(wire), config);
+    }
+
+    // the old layout only knows smart mode being on or off
+    let config = Config {
+        mode: ControlMode::Curves,
+        ..Default::default()
+    };
+    let wire = WireConfig::new(&config, ConfigLayout::V1).unwrap();
+    assert_eq!(Config::from(wire).mode, ControlMode::Curves);
+    let wire = WireConfig::new(&Config::default(), ConfigLayout::V1).unwrap();
+    assert_eq!(Config::from(wire).mode, ControlMode::default());
+}
+
+/// First order model of a loop: `watts` heat the coolant, the radiator
+/// sheds more the faster the fans spin. Returns the delta above ambient.
+struct Plant {
+    delta: f32,
+    watts: f32,
+}
+
+impl Plant {
+    /// J/°C the coolant and blocks soak up.
+    const CAPACITY: f32 = 2000.0;
+
+    fn step(&mut self, duty: f32, dt: Duration) -> f32 {
+        // W/°C shed by the radiator, still air does a little on its own
+        let shed = 5.0 + 40.0 * duty / 100.0;
+        self.delta += (self.watts - shed * self.delta) / Self::CAPACITY
+            * dt.as_secs_f32();
+        self.delta
+    }
+}
+
+#[test]
+fn should_hold_the_pid_target_delta() {
+    let mode = PidMode::default();
+    let mut pid = control::Pid::default();
+    let mut plant = Plant {
+        delta: 0.0,
+        watts: 200.0,
+    };
+    let second = Duration::from_secs(1);
+
+    let mut duty = pid.update(&mode, plant.delta, second);
+    for _ in 0..1200 {
+        let delta = plant.step(duty, second);
+        duty = pid.update(&mode, delta, second);
+        assert!((mode.min_duty..=mode.max_duty).contains(&duty), "{duty}");
+    }
+    println!("delta: {} duty: {duty}", plant.delta);
+    assert!((plant.delta - mode.target_delta).abs() < 0.2);
+    // 200 W need 25 W/°C at 8 °C, which the radiator sheds at 50 %
+    assert!((duty - 50.0).abs() < 2.0, "{duty}");
+}
+
+#[test]
+fn should_not_wind_up_the_pid_integral() {
+    let mode = PidMode::default();
+    let mut pid = control::Pid::default();
+    let mut plant = Plant {
+        delta: 0.0,
+        watts: 600.0,
+    };
+    let second = Duration::from_secs(1);
+
+    // more heat than the radiator can shed keeps the fans flat out
+    let mut duty = pid.update(&mode, plant.delta, second);
+    for _ in 0..1200 {
+        duty = pid.update(&mode, plant.step(duty, second), second);
+    }
+    assert_eq!(duty, mode.max_duty);
+    assert!(plant.delta > mode.target_delta + 4.0);
+
+    // the load goes away, the fans back off as soon as the target is
+    // reached instead of unwinding twenty minutes worth of integral
+    plant.watts = 200.0;
+    let mut undershoot = 0.0f32;
+    for _ in 0..1200 {
+        duty = pid.update(&mode, plant.step(duty, second), second);
+        undershoot = undershoot.max(mode.target_delta - plant.delta);
+    }
+    println!("undershoot: {undershoot} delta: {}", plant.delta);
+    assert!(undershoot < 1.0);
+    assert!((plant.delta - mode.target_delta).abs() < 0.2);
+}
+
+#[test]
+fn should_filter_the_pid_derivative() {
+    let second = Duration::from_secs(1);
+    // a steady delta with ±0.5 °C of sensor noise, around the target so
+    // the integral stays put
+    let jitter = |mode: &PidMode| {
+        let mut pid = control::Pid::default();
+        let mut last = pid.update(mode, mode.target_delta, second);
+        let mut jitter = 0.0f32;
+        for i in 0..120 {
+            let noise = if i % 2 == 0 { 0.5 } else { -0.5 };
+            let duty = pid.update(mode, mode.target_delta + noise, second);
+            jitter = jitter.max((duty - last).abs());
+            last = duty;
+        }
+        jitter
+    };
+    let mode = PidMode {
+        min_duty: 0.0,
+        ki: 0.0,
+        ..Default::default()
+    };
+    let unfiltered = jitter(&PidMode {
+        derivative_filter_s: 0.0,
+        ..mode
+    });
+    let filtered = jitter(&mode);
+    println!("unfiltered: {unfiltered} filtered: {filtered}");
+    assert!(filtered < unfiltered / 2.0);
+}
+
+#[test]
+fn should_drive_fans_with_pid_mode() {
+    let config = Config {
+        mode: ControlMode::Pid(PidMode::default()),
+        ..Default::default()
+    };
+    let stats = Stats {
+        coolant_temp: 45.0,
+        ambient_temp: 25.0,
+        ..Default::default()
+    };
+    let mut controller = Controller::default();
+    // 12 °C above the target asks for more than the fans can give
+    let duties = controller.update(&config, &stats, None, Duration::ZERO);
+    assert_eq!(duties, [95.0, 100.0, 100.0, 100.0]);
+
+    // below the target the fans idle at the lowest duty
+    let stats = Stats {
+        coolant_temp: 30.0,
+        ..stats
+    };
+    let duties = controller.update(&config, &stats, None, Duration::ZERO);
+    assert_eq!(duties, [95.0, 20.0, 20.0, 20.0]);
+}
+
+#[test]
+fn should_validate_pid_mode() {
+    assert!(Config {
+        mode: ControlMode::Pid(PidMode::default()),
+        ..Default::default()
+    }
+    .is_valid());
+
+    let issues = |pid_mode: PidMode| {
+        let config = Config {
+            mode: ControlMode::Pid(pid_mode),
+            ..Default::default()
+        };
+        config.validate().iter().map(|i| i.path).collect::<Vec<_>>()
+    };
+    use validate::Field;
+    assert_eq!(
+        issues(PidMode {
+            target_delta: 0.0,
+            ..Default::default()
+        }),
+        [Field::TargetDelta]
+    );
+    assert_eq!(
+        issues(PidMode {
+            ki: -1.0,
+            ..Default::default()
+        }),
+        [Field::PidMode]
+    );
+    assert_eq!(
+        issues(PidMode {
+            min_duty: 60.0,
+            max_duty: 50.0,
+            ..Default::default()
+        }),
+        [Field::MaxDuty]
+    );
+    assert_eq!(
+        issues(PidMode {
+            derivative_filter_s: f32::NAN,
+            ..Default::default()
+        }),
+        [Field::DerivativeFilter]
+    );
+    assert_eq!(
+        issues(PidMode {
+            pump_duty: 20.0,
+            ..Default::default()
+        }),
+        [Field::PidPumpDuty]
+    );
+}
+
+#[test]
+fn should_follow_the_control_mode() {
+    let stats = Stats {
+        coolant_temp: 32.0,
+        ambient_temp: 22.0,
+        ..Default::default()
+    };
+    let second = Duration::from_secs(1);
+    let mut config = Config {
+        mode: ControlMode::Fixed(FixedMode {
+            fan_duty: 60.0,
+            pump_duty: 80.0,
+        }),
+        ..Default::default()
+    };
+    let mut controller = Controller::default();
+    let duties = controller.update(&config, &stats, None, second);
+    assert_eq!(duties, [80.0, 60.0, 60.0, 60.0]);
+
+    // channels can step out of the mode
+    config.settings[2].mode = ChannelMode::Fixed(30.0);
+    config.settings[3].mode = ChannelMode::Curve;
+    let curve = config.settings[3].get_duty(32.0, 100) as f32;
+    let duties = controller.update(&config, &stats, None, second);
+    assert_eq!(duties, [80.0, 60.0, 30.0, curve]);
+
+    // following the curves is the same as falling back to them
+    config.mode = ControlMode::Curves;
+    config.settings[0].mode = ChannelMode::Fixed(70.0);
+    let duties = controller.update(&config, &stats, None, second);
+    assert_eq!(duties[0], 70.0);
+    assert_eq!(duties[1], config.settings[1].get_duty(32.0, 100) as f32);
+    assert_eq!(duties[2..], [30.0, curve]);
+
+    config.mode = ControlMode::default();
+    let smart = get_smart_duty(32.0, 22.0, 5.0, 40.0, 1000, false);
+    let duties = controller.update(&config, &stats, None, second);
+    assert_eq!((duties[1] * 10.0) as u16, smart);
+    assert_eq!(duties[2..], [30.0, curve]);
+}
+
+#[test]
+fn should_validate_control_modes() {
+    let issues = |config: &Config| {
+        config
+            .validate()
+            .iter()
+            .map(|i| (i.path.to_string(), i.is_error()))
+            .collect::<Vec<_>>()
+    };
+    let mut config = Config {
+        mode: ControlMode::Fixed(FixedMode {
+            fan_duty: 120.0,
+            pump_duty: 20.0,
+        }),
+        ..Default::default()
+    };
+    config.settings[1].mode = ChannelMode::Fixed(-1.0);
+    assert_eq!(
+        issues(&config),
+        [
+            ("mode.fixed.fan_duty".to_string(), true),
+            ("mode.fixed.pump_duty".to_string(), true),
+            ("settings.F1.mode".to_string(), true),
+        ]
+    );
+
+    config.mode = ControlMode::Fixed(FixedMode {
+        fan_duty: 5.0,
+        ..Default::default()
+    });
+    config.settings[1].mode = ChannelMode::Curve;
+    assert_eq!(
+        issues(&config),
+        [("mode.fixed.fan_duty".to_string(), false)]
+    );
+    assert!(config.is_valid());
+
+    config.mode = ControlMode::Smart(SmartMode {
+        upper_temp: 60.0,
+        ..Default::default()
+    });
+    assert_eq!(
+        issues(&config),
+        [("mode.smart.upper_temp".to_string(), true)]
+    );
+}
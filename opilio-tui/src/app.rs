@@ -1,5 +1,13 @@
-use anyhow::{anyhow, Result};
-use opilio_lib::{serial::OpilioSerialDevice, PID, VID};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use opilio_lib::{
+    error::ClientError,
+    manager::DeviceManager,
+    serial::{compatibility_warning, OpilioSerialDevice},
+    ControlMode, FixedMode, Id, Override, PidMode, Stats, ValidationIssue,
+    Version,
+};
 use tui::{
     style::{Color, Modifier, Style},
     symbols,
@@ -7,7 +15,7 @@ use tui::{
     widgets::{Axis, Block, Borders, Chart, Dataset, GraphType, Paragraph},
 };
 
-use crate::config::{config_file, from_disk};
+use crate::config::{aliases_file, config_file, from_disk, peek};
 
 const TIME_SPAN: f64 = 60.0;
 const TICK_DISTANCE: f64 = 0.5;
@@ -20,6 +28,10 @@ const RPM_Y_AXIS_MIN: f64 = 100.0;
 const TEMP_Y_AXIS_MIN: f64 = 10.0;
 const TEMP_Y_AXIS_MAX: f64 = 40.0;
 
+/// How long a channel is held by [`App::toggle_override`], in case the
+/// release is forgotten.
+const OVERRIDE_TIMEOUT: Duration = Duration::from_secs(300);
+
 #[derive(Default, Copy, Clone)]
 pub enum InputMode {
     #[default]
@@ -29,11 +41,16 @@ pub enum InputMode {
     SavePrompt,
     ShowError,
     ShowSuccess,
+    /// Stays up until dismissed, unlike errors.
+    ShowWarning,
 }
 
 pub struct App {
     config_path: String,
     serial: OpilioSerialDevice,
+    /// Whether the device pushes its stats or has to be polled.
+    streaming: bool,
+    version: Version,
     last_point: f64,
     coolant_temp: Vec<(f64, f64)>,
     coolant_out_temp: Vec<(f64, f64)>,
@@ -45,12 +62,18 @@ pub struct App {
     window: [f64; 2],
     current_temps: [f64; 3],
     current_rpms: [f64; 4],
+    /// Overrides in force as of the last stats.
+    overrides: [Option<Override>; 4],
+    /// Mode the device was last seen or told to be in.
+    mode: ControlMode,
     pub input_mode: InputMode,
     pub msg: String,
 }
 
 impl App {
-    pub fn new() -> Result<App> {
+    /// Opens the device called `device`, its alias, serial number or port
+    /// name, which may be left out when only one is attached.
+    pub fn new(device: Option<&str>) -> Result<App> {
         let pump1 = vec![(ZERO, ZERO)];
         let fan1 = vec![(ZERO, ZERO)];
         let fan2 = vec![(ZERO, ZERO)];
@@ -58,18 +81,25 @@ impl App {
         let coolant_temp = vec![(ZERO, ZERO)];
         let coolant_out_temp = vec![(ZERO, ZERO)];
         let ambient_temp = vec![(ZERO, ZERO)];
-        let ports = OpilioSerialDevice::find_ports(VID, PID)?;
-        let port = ports
-            .first()
-            .ok_or_else(|| anyhow!("No Opilio device found"))?;
-        let mut serial = OpilioSerialDevice::new(&port.port_name)?;
+        let mut manager = DeviceManager::new();
+        if let Err(e) = manager.load_aliases(&aliases_file()?) {
+            log::warn!("Failed to read device aliases ({e})");
+        }
+        let (id, mut serial) = manager.take(device)?;
+        log::info!("Opened {id}");
+        let version = serial.version()?;
+        log::info!("Opilio firmware {version}");
         let config_path = config_file()?.display().to_string();
 
         let config = serial.get_config()?;
         log::info!("{config:?}");
+        let streaming = subscribe(&mut serial);
+        let warning = compatibility_warning(&version);
 
         Ok(App {
             serial,
+            streaming,
+            version,
             pump1,
             fan1,
             fan2,
@@ -81,16 +111,47 @@ impl App {
             ambient_temp,
             current_temps: [ZERO; 3],
             current_rpms: [ZERO; 4],
+            overrides: [None; 4],
+            mode: config.mode,
             last_point: TIME_SPAN,
-            input_mode: InputMode::default(),
-            msg: String::new(),
+            input_mode: match warning {
+                Some(_) => InputMode::ShowWarning,
+                None => InputMode::default(),
+            },
+            msg: warning.unwrap_or_default(),
         })
     }
 
+    /// Checks the config on disk before asking to upload it, its warnings
+    /// and any migrations the upload would apply to the file are left in
+    /// `msg` for the prompt. The file is left alone until then.
+    pub fn review_config(&mut self) -> Result<()> {
+        self.msg = String::new();
+        let (config, migrations) = peek()?;
+        let report = config.validate();
+        let (errors, warnings): (Vec<_>, Vec<_>) =
+            report.iter().partition(|issue| issue.is_error());
+        if !errors.is_empty() {
+            bail!("invalid config, {}", join(&errors));
+        }
+        let mut notes: Vec<_> = migrations
+            .iter()
+            .map(|m| format!("config file will be migrated, {}", m.description))
+            .collect();
+        if !migrations.is_empty() {
+            notes.push(format!("old file kept as {}.bak", self.config_path));
+        }
+        notes.extend(warnings.iter().map(|issue| issue.to_string()));
+        self.msg = notes.join(", ");
+        Ok(())
+    }
+
     pub fn upload_config(&mut self) -> Result<()> {
-        let config = from_disk()?;
+        let config = from_disk()?.config;
         log::info!("{:#?}", &config);
+        let mode = config.mode.clone();
         self.serial.upload_config(config)?;
+        self.mode = mode;
 
         Ok(())
     }
@@ -101,6 +162,50 @@ impl App {
         Ok(())
     }
 
+    /// Holds channel `id` at `duty_percent`, or releases it if it is held
+    /// already. Returns what was done, for the success message.
+    pub fn toggle_override(
+        &mut self,
+        id: Id,
+        duty_percent: f32,
+    ) -> Result<String> {
+        let held = &mut self.overrides[id.index()];
+        if held.is_some() {
+            self.serial.clear_override(id)?;
+            *held = None;
+            return Ok(format!("{id:?} follows its curve again"));
+        }
+        self.serial
+            .set_override(id, duty_percent, OVERRIDE_TIMEOUT)?;
+        *held = Some(Override {
+            id,
+            duty_percent,
+            timeout_s: OVERRIDE_TIMEOUT.as_secs() as u32,
+        });
+        Ok(format!(
+            "{id:?} held at {duty_percent} % for {} minutes",
+            OVERRIDE_TIMEOUT.as_secs() / 60
+        ))
+    }
+
+    /// Switches the device to the next [`ControlMode`], with the settings
+    /// the config on disk has for it if it uses that mode.
+    pub fn next_mode(&mut self) -> Result<String> {
+        let next = match self.mode {
+            ControlMode::Smart(_) => ControlMode::Curves,
+            ControlMode::Curves => ControlMode::Fixed(FixedMode::default()),
+            ControlMode::Fixed(_) => ControlMode::Pid(PidMode::default()),
+            ControlMode::Pid(_) => ControlMode::default(),
+        };
+        let next = match peek() {
+            Ok((config, _)) if config.mode.name() == next.name() => config.mode,
+            _ => next,
+        };
+        self.serial.upload_control_mode(&next)?;
+        self.mode = next;
+        Ok(format!("switched to {} mode", self.mode.name()))
+    }
+
     pub fn on_tick(&mut self) {
         self.window[0] += TICK_DISTANCE;
         self.window[1] += TICK_DISTANCE;
@@ -115,8 +220,11 @@ impl App {
         }
 
         self.last_point += TICK_DISTANCE;
-        match self.serial.get_stats() {
-            Ok(stats) => {
+        match self.next_stats() {
+            Ok(None) => {
+                // the sample is late, keep showing the last one
+            }
+            Ok(Some(stats)) => {
                 let [current_coolant, current_ambient, current_coolant_out] =
                     self.current_temps;
                 self.current_temps = [
@@ -133,6 +241,7 @@ impl App {
                     (stats.fan2_rpm as f64 + rpm3) / 2.0,
                     (stats.fan3_rpm as f64 + rpm4) / 2.0,
                 ];
+                self.overrides = stats.overrides;
             }
             Err(e) => {
                 log::error!("{:?}", e);
@@ -154,7 +263,25 @@ impl App {
             .push((self.last_point, self.current_temps[2]));
     }
 
-    pub fn temp_chart(&self) -> Chart {
+    /// Takes the samples pushed since the last tick, devices that can not
+    /// push them are polled instead.
+    fn next_stats(&mut self) -> Result<Option<Stats>, ClientError> {
+        if !self.streaming {
+            return self.serial.get_stats().map(Some);
+        }
+        if !self.serial.is_subscribed() {
+            // the device was replugged, subscribing reconnects
+            self.serial
+                .subscribe_stats(Duration::from_secs_f64(TICK_DISTANCE))?;
+        }
+        let mut latest = None;
+        while let Some(sample) = self.serial.try_next_stats()? {
+            latest = Some(sample.stats);
+        }
+        Ok(latest)
+    }
+
+    pub fn temp_chart(&self) -> Chart<'_> {
         let temp_datasets = vec![
             Dataset::default()
                 .name(format!("Amb: {:.2}°C", self.current_temps[1]))
@@ -230,7 +357,7 @@ impl App {
         temp_chart
     }
 
-    pub fn rpm_chart(&self) -> Chart {
+    pub fn rpm_chart(&self) -> Chart<'_> {
         let rpm_datasets = vec![
             Dataset::default()
                 .name(format!("P1: {:.1}", self.current_rpms[0]))
@@ -295,7 +422,14 @@ impl App {
             .block(
                 Block::default()
                     .title(Span::styled(
-                        "Opilio",
+                        match self.serial.state() {
+                            state if state.is_connected() => {
+                                format!("Opilio {}", self.version)
+                            }
+                            state => {
+                                format!("Opilio {} ({state})", self.version)
+                            }
+                        },
                         Style::default()
                             .fg(Color::Cyan)
                             .add_modifier(Modifier::BOLD),
@@ -327,7 +461,21 @@ impl App {
             )
     }
 
-    pub fn info_block(&self) -> Paragraph {
+    /// Channels held by an override and for how long, empty if none are.
+    fn held(&self) -> String {
+        self.overrides
+            .iter()
+            .flatten()
+            .map(|o| {
+                format!(
+                    " {:?} held at {} % ({} s)",
+                    o.id, o.duty_percent, o.timeout_s
+                )
+            })
+            .collect()
+    }
+
+    pub fn info_block(&self) -> Paragraph<'_> {
         let (msg, style) = match self.input_mode {
             InputMode::Normal => (
                 vec![
@@ -359,6 +507,27 @@ impl App {
                             .fg(Color::Yellow),
                     ),
                     Span::raw("uit, "),
+                    Span::styled(
+                        "1-3",
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .fg(Color::Magenta),
+                    ),
+                    Span::raw(" fan to full speed, "),
+                    Span::styled(
+                        "P",
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .fg(Color::Magenta),
+                    ),
+                    Span::raw("ark pump, "),
+                    Span::styled(
+                        "M",
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .fg(Color::Cyan),
+                    ),
+                    Span::raw(format!("ode: {}, ", self.mode.name())),
                     Span::styled(
                         "Esc",
                         Style::default()
@@ -366,6 +535,10 @@ impl App {
                             .fg(Color::Green),
                     ),
                     Span::raw(" to go back."),
+                    Span::styled(
+                        self.held(),
+                        Style::default().fg(Color::Magenta),
+                    ),
                 ],
                 Style::default(),
             ),
@@ -409,18 +582,41 @@ impl App {
                 ],
                 Style::default(),
             ),
-            InputMode::UploadPrompt => (
-                vec![Span::raw(format!(
+            InputMode::ShowWarning => (
+                vec![
+                    Span::styled(
+                        "Warning: ",
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .fg(Color::Yellow),
+                    ),
+                    Span::raw(&self.msg),
+                ],
+                Style::default(),
+            ),
+            InputMode::UploadPrompt => {
+                let mut spans = Vec::new();
+                if !self.msg.is_empty() {
+                    spans.push(Span::styled(
+                        "Warning: ",
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .fg(Color::Yellow),
+                    ));
+                    spans.push(Span::raw(format!("{}. ", self.msg)));
+                }
+                spans.push(Span::raw(format!(
                     "Uploading config '{}' to opilio board?",
                     self.config_path
-                )), Span::styled(
+                )));
+                spans.push(Span::styled(
                     " Y/N:",
                     Style::default()
                         .add_modifier(Modifier::BOLD)
                         .fg(Color::Red),
-                ),],
-                Style::default(),
-            ),
+                ));
+                (spans, Style::default())
+            }
             InputMode::SavePrompt => (
                 vec![Span::raw(
                     "Would you like to save current configuration on controller?"
@@ -439,3 +635,23 @@ impl App {
         Paragraph::new(text)
     }
 }
+
+fn join(issues: &[&ValidationIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| issue.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Asks the device to push a sample every tick, returns `false` if it can
+/// only be polled.
+fn subscribe(serial: &mut OpilioSerialDevice) -> bool {
+    match serial.subscribe_stats(Duration::from_secs_f64(TICK_DISTANCE)) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("not streaming stats ({e}), polling instead");
+            false
+        }
+    }
+}
@@ -1,11 +1,24 @@
 use std::{fs, path::PathBuf};
 
 use anyhow::{anyhow, bail, Ok, Result};
-use opilio_lib::Config;
+use opilio_lib::{
+    config_file::{from_json, load, Loaded, Migration},
+    manager::ALIASES_FILE_NAME,
+    Config,
+};
 const CONFIG_DIR_NAME: &str = "opilio";
 const CONFIG_FILE_NAME: &str = "opilio.json";
 
 pub fn config_file() -> Result<PathBuf> {
+    Ok(config_dir()?.join(CONFIG_FILE_NAME))
+}
+
+/// Friendly names for devices, shared with the daemon.
+pub fn aliases_file() -> Result<PathBuf> {
+    Ok(config_dir()?.join(ALIASES_FILE_NAME))
+}
+
+fn config_dir() -> Result<PathBuf> {
     let dir = dirs::config_dir()
         .ok_or_else(|| anyhow!("User config directory does not exist"))?
         .join(CONFIG_DIR_NAME);
@@ -13,16 +26,25 @@ pub fn config_file() -> Result<PathBuf> {
     if !dir.exists() {
         fs::create_dir_all(&dir)?;
     }
-    Ok(dir.join(CONFIG_FILE_NAME))
+    Ok(dir)
+}
+
+/// Reads the config file, upgrading it first if it has an older layout.
+pub fn from_disk() -> Result<Loaded> {
+    Ok(load(&existing_file()?)?)
+}
+
+/// Reads the config file without touching it, along with the migrations
+/// [`from_disk`] would apply.
+pub fn peek() -> Result<(Config, Vec<&'static Migration>)> {
+    let path = existing_file()?;
+    Ok(from_json(&fs::read_to_string(path)?)?)
 }
 
-pub fn from_disk() -> Result<Config> {
+fn existing_file() -> Result<PathBuf> {
     let path = config_file()?;
-    if path.exists() {
-        let data = fs::read_to_string(path)?;
-        let configs: Config = serde_json::from_str(&data)?;
-        Ok(configs)
-    } else {
+    if !path.exists() {
         bail!("Config file does not exists")
     }
+    Ok(path)
 }
@@ -2,6 +2,7 @@ use anyhow::Result;
 use app::App;
 use fast_log::Config;
 use log::error;
+use opilio_lib::{Id, MAX_DUTY_PERCENT};
 
 mod app;
 mod config;
@@ -39,7 +40,9 @@ fn main() -> Result<()> {
 
     fast_log::init(Config::new().file("/tmp/opilio.log"))?;
 
-    let mut app = App::new()?;
+    // alias, serial number or port name of the device to open
+    let device = std::env::args().nth(1);
+    let mut app = App::new(device.as_deref())?;
     // setup terminal
     enable_raw_mode().map_err(|e| {
         log::error!("Failed to enable raw mode: {}", e);
@@ -94,12 +97,31 @@ fn run_app<B: Backend>(
                     KeyCode::Esc => app.input_mode = InputMode::Normal,
                     KeyCode::Char('q') => return Ok(()),
                     KeyCode::Char('h') => app.input_mode = InputMode::ShowHelp,
-                    KeyCode::Char('u') => {
-                        app.input_mode = InputMode::UploadPrompt
-                    }
+                    KeyCode::Char('u') => match app.review_config() {
+                        Err(e) => {
+                            app.msg = e.to_string();
+                            app.input_mode = InputMode::ShowError
+                        }
+                        _ => app.input_mode = InputMode::UploadPrompt,
+                    },
                     KeyCode::Char('s') => {
                         app.input_mode = InputMode::SavePrompt
                     }
+                    KeyCode::Char(c @ '1'..='3') => {
+                        let id = Id::ALL[c as usize - '0' as usize];
+                        toggle_override(app, id, MAX_DUTY_PERCENT)
+                    }
+                    KeyCode::Char('p') => toggle_override(app, Id::P1, 0.0),
+                    KeyCode::Char('m') => match app.next_mode() {
+                        Err(e) => {
+                            app.msg = e.to_string();
+                            app.input_mode = InputMode::ShowError
+                        }
+                        Ok(done) => {
+                            app.msg = done;
+                            app.input_mode = InputMode::ShowSuccess
+                        }
+                    },
                     KeyCode::Char('y') | KeyCode::Char('Y') => {
                         match current_input_mode {
                             InputMode::UploadPrompt => {
@@ -155,6 +177,19 @@ fn run_app<B: Backend>(
     }
 }
 
+fn toggle_override(app: &mut App, id: Id, duty_percent: f32) {
+    match app.toggle_override(id, duty_percent) {
+        Err(e) => {
+            app.msg = e.to_string();
+            app.input_mode = InputMode::ShowError
+        }
+        Ok(done) => {
+            app.msg = done;
+            app.input_mode = InputMode::ShowSuccess
+        }
+    }
+}
+
 fn ui<B: Backend>(f: &mut Frame<B>, app: &App) {
     let layout_chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -194,7 +229,7 @@ fn listen_tcp(bool: Arc<AtomicBool>) {
                     let b = bool.clone();
                     thread::spawn(move || {
                         let reader = BufReader::new(stream);
-                        for line in reader.lines().flatten() {
+                        for line in reader.lines().map_while(|line| line.ok()) {
                             log::info!("{}", line);
                             if line == QUIT {
                                 b.store(true, Ordering::Relaxed);
@@ -127,7 +127,7 @@ impl ChartGroup {
             .push_data(data.timestamp, data.liq_out_temp);
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
         Column::new()
             .width(Length::Fill)
             .height(Length::Fill)
@@ -141,7 +141,7 @@ impl ChartGroup {
             .into()
     }
 
-    pub fn new_row(&self) -> Row<Message> {
+    pub fn new_row(&self) -> Row<'_, Message> {
         Row::new()
             .spacing(0)
             .padding(0)
@@ -209,7 +209,7 @@ impl MonitoringChart {
         self.cache.clear();
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         Container::new(
             Column::new().width(Length::Fill).height(Length::Fill).push(
                 ChartWidget::new(self).height(Length::Fill).resolve_font(
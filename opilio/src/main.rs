@@ -17,10 +17,11 @@ use iced::{
     Theme,
 };
 use opilio_lib::{
-    serial::{OpilioSerialDevice, PortWithSerialNumber},
-    PID, VID,
+    hotplug::{HotplugEvent, HotplugWatcher},
+    manager::{DeviceId, DeviceManager, ALIASES_FILE_NAME},
+    Id, Stats,
 };
-use running::RunningState;
+use running::{Connection, RunningState};
 use tray_icon::{
     menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
     TrayIconBuilder,
@@ -36,6 +37,8 @@ const FONT_BOLD: Font = Font::External {
     bytes: include_bytes!("../fonts/notosans-bold.ttf"),
 };
 
+const CONFIG_DIR_NAME: &str = "opilio";
+
 const ICON: &[u8; 16384] =
     include_bytes!(concat!(env!("OUT_DIR"), "/icon.bin"));
 
@@ -95,10 +98,27 @@ pub enum Message {
     SetSleepAfter(u32),
     SetTriggerAboveAmbient(f32),
     SetUpperTemp(f32),
+    /// Pump duty of whichever mode is in control.
     SetPumpDuty(f32),
+    /// Switches to the mode of that [`opilio_lib::ControlMode::name`].
+    SetMode(&'static str),
+    SetFanDuty(f32),
     ToggleBuzzer(bool),
     ToggleLed(bool),
-    PortSelected(PortWithSerialNumber),
+    PortSelected(DeviceId),
+    Connected(Result<Box<Connection>, String>),
+    StatsReceived(Result<Stats, String>),
+    Uploaded(Result<(), String>),
+    /// A single edit was applied while testing.
+    Applied(Result<(), String>),
+    /// Hold a channel at a duty in percent.
+    Override(Id, f32),
+    /// Hand a channel back to its curve.
+    ClearOverride(Id),
+    /// A channel was held or handed back.
+    Overridden(Result<(), String>),
+    Saved(Result<(), String>),
+    Reloaded(Result<(), String>),
     ChangeState,
     Hide,
     Test,
@@ -111,25 +131,51 @@ pub struct PortIdent {
     path: std::path::PathBuf,
 }
 
-#[derive(Default)]
 struct HomeState {
-    selected_port: Option<PortWithSerialNumber>,
+    /// Names attached devices by their alias.
+    manager: DeviceManager,
+    ports: Vec<DeviceId>,
+    watcher: HotplugWatcher,
+    selected_port: Option<DeviceId>,
     error_text: Option<String>,
 }
 
 impl HomeState {
     pub fn new() -> Self {
-        let mut ports: Vec<PortWithSerialNumber> =
-            OpilioSerialDevice::find_ports(VID, PID).unwrap_or_default();
-        let selected_port = if ports.len() == 1 { ports.pop() } else { None };
+        let mut manager = DeviceManager::new();
+        load_aliases(&mut manager);
+        let ports = manager.devices().unwrap_or_default();
+        let selected_port = if ports.len() == 1 {
+            ports.first().cloned()
+        } else {
+            None
+        };
 
         Self {
+            manager,
+            ports,
+            watcher: HotplugWatcher::usb(),
             selected_port,
             error_text: None,
         }
     }
     pub fn update(&mut self, message: Message) -> Command<Message> {
         match message {
+            Message::Tick => {
+                for event in self.watcher.events().try_iter() {
+                    match event {
+                        HotplugEvent::Attached(port) => {
+                            let id = self.manager.id(port);
+                            if !self.ports.contains(&id) {
+                                self.ports.push(id);
+                            }
+                        }
+                        HotplugEvent::Detached(port) => {
+                            self.ports.retain(|id| id.port != port)
+                        }
+                    }
+                }
+            }
             Message::PortSelected(port) => {
                 self.selected_port = Some(port);
             }
@@ -144,13 +190,8 @@ impl HomeState {
     pub fn view(&self) -> Element<'_, Message> {
         let label = Text::new("Select Serial Port").size(48);
 
-        let options: Vec<_> = OpilioSerialDevice::find_ports(VID, PID)
-            .unwrap_or_default()
-            .into_iter()
-            .collect();
-
         let pick_list = iced::widget::pick_list(
-            options,
+            self.ports.clone(),
             self.selected_port.clone(),
             Message::PortSelected,
         )
@@ -282,6 +323,9 @@ impl Application for OpilioController {
                 ),
             ));
         }
+        if let Message::Connected(connection) = message {
+            return self.connected(connection);
+        }
         if let Some(value) = self.run_if_port_is_selected() {
             return value;
         }
@@ -308,27 +352,47 @@ impl Application for OpilioController {
 impl OpilioController {
     fn run_if_port_is_selected(&mut self) -> Option<Command<Message>> {
         if let State::Home(ref mut home) = &mut self.state {
-            if let Some(port) = home.selected_port.take() {
-                match RunningState::new(port) {
-                    Ok(running_state) => {
-                        self.state = State::Running(running_state);
-                    }
-                    Err(error) => {
-                        home.error_text = Some(format!("{error}"));
-                        return Some(iced_native::Command::none());
-                    }
-                }
-
-                return Some(Command::single(
-                    iced_native::command::Action::Window(
-                        iced_native::window::Action::Resize {
-                            width: 1400,
-                            height: 1000,
-                        },
-                    ),
+            if let Some(id) = home.selected_port.take() {
+                return Some(Command::perform(
+                    RunningState::connect(id.port),
+                    |connection| Message::Connected(connection.map(Box::new)),
                 ));
             }
         }
         None
     }
+
+    fn connected(
+        &mut self,
+        connection: Result<Box<Connection>, String>,
+    ) -> Command<Message> {
+        match connection {
+            Ok(connection) => {
+                self.state = State::Running(RunningState::new(*connection));
+                Command::single(iced_native::command::Action::Window(
+                    iced_native::window::Action::Resize {
+                        width: 1400,
+                        height: 1000,
+                    },
+                ))
+            }
+            Err(error) => {
+                if let State::Home(ref mut home) = self.state {
+                    home.error_text = Some(error);
+                }
+                Command::none()
+            }
+        }
+    }
+}
+
+/// Friendly names for devices, shared with the TUI and the daemon.
+fn load_aliases(manager: &mut DeviceManager) {
+    let Some(dir) = dirs::config_dir() else {
+        return;
+    };
+    let path = dir.join(CONFIG_DIR_NAME).join(ALIASES_FILE_NAME);
+    if let Err(e) = manager.load_aliases(&path) {
+        log::warn!("Failed to read device aliases ({e})");
+    }
 }
@@ -1,4 +1,7 @@
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use chrono::Local;
 use iced::{
@@ -11,47 +14,113 @@ use iced::{
 };
 use iced_aw::NumberInput;
 use opilio_lib::{
-    serial::{OpilioSerialDevice, PortWithSerialNumber},
-    Config, SwitchMode,
+    async_client::AsyncOpilioSerialDevice,
+    error::ClientError,
+    serial::{compatibility_warning, PortWithSerialNumber},
+    ChannelMode, Config, ControlMode, FixedMode, Id, Override, PidMode, Stats,
+    SwitchMode, ValidationIssue, Version, MAX_DUTY_PERCENT,
 };
+use tokio::sync::Mutex;
 
 use crate::{
     graphs::{ChartGroup, MonitoringData},
     Message,
 };
 
+/// How often the device pushes its stats.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a channel is held, in case the release is forgotten.
+const OVERRIDE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Modes to pick from, by [`ControlMode::name`].
+const MODES: [&str; 4] = ["smart", "curves", "fixed", "pid"];
+
+/// Device shared with the commands talking to it in the background.
+type SharedDevice = Arc<Mutex<AsyncOpilioSerialDevice>>;
+
+/// What is needed to show a freshly opened device.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    device: SharedDevice,
+    version: Version,
+    config: Config,
+    serial_number: String,
+    /// Whether the device pushes its stats or has to be polled.
+    streaming: bool,
+    /// Set if the firmware speaks another protocol revision.
+    warning: Option<String>,
+}
+
 pub struct RunningState {
     last_sample_time: Instant,
-    opilio_serial: OpilioSerialDevice,
-    version: String,
+    opilio_serial: SharedDevice,
+    version: Version,
+    serial_number: String,
     chart: ChartGroup,
     config: Config,
     error_text: Option<String>,
+    /// Issues with an uploaded config the device took anyway.
+    warning_text: Option<String>,
     update_interval: Duration,
+    streaming: bool,
     testing: bool,
+    /// Overrides in force as of the last stats.
+    overrides: [Option<Override>; 4],
 }
 
 impl RunningState {
-    pub fn new(
+    /// Opens the device and reads what the running view needs from it.
+    pub async fn connect(
         port_with_serial: PortWithSerialNumber,
-    ) -> Result<Self, anyhow::Error> {
-        let mut opilio_serial =
-            OpilioSerialDevice::new(&port_with_serial.port_name)?;
+    ) -> Result<Connection, String> {
+        let connect = async {
+            let mut device =
+                AsyncOpilioSerialDevice::new(&port_with_serial.port_name)
+                    .await?;
+            let version = device.version().await?;
+            let config = device.get_config().await?;
+            let streaming = match device.subscribe_stats(UPDATE_INTERVAL).await
+            {
+                Ok(()) => true,
+                Err(e @ ClientError::Unsupported { .. }) => {
+                    log::warn!("{e}, polling instead");
+                    false
+                }
+                Err(e) => return Err(e),
+            };
+            Ok::<_, ClientError>((device, version, config, streaming))
+        };
+        let (device, version, config, streaming) =
+            connect.await.map_err(|e| e.to_string())?;
 
-        let config = opilio_serial.get_config()?;
+        Ok(Connection {
+            device: Arc::new(Mutex::new(device)),
+            version,
+            config,
+            serial_number: port_with_serial
+                .serial_number
+                .unwrap_or_else(|| "Unknown".to_string()),
+            streaming,
+            warning: compatibility_warning(&version),
+        })
+    }
 
-        Ok(RunningState {
+    pub fn new(connection: Connection) -> Self {
+        RunningState {
             last_sample_time: Instant::now(),
-            opilio_serial,
+            opilio_serial: connection.device,
             chart: Default::default(),
-            config,
+            config: connection.config,
             error_text: None,
-            update_interval: Duration::from_millis(500),
+            warning_text: connection.warning,
+            update_interval: UPDATE_INTERVAL,
+            streaming: connection.streaming,
             testing: false,
-            version: port_with_serial
-                .serial_number
-                .unwrap_or_else(|| "Unknown".to_string()),
-        })
+            overrides: [None; 4],
+            version: connection.version,
+            serial_number: connection.serial_number,
+        }
     }
     #[inline]
     pub fn should_update(&self) -> bool {
@@ -66,44 +135,86 @@ impl RunningState {
                 }
 
                 self.last_sample_time = Instant::now();
-                match self.opilio_serial.get_stats() {
-                    Ok(stats) => {
-                        let data = MonitoringData {
-                            timestamp: Local::now(),
-                            pump_rpm: stats.pump1_rpm,
-                            f1_rpm: stats.fan1_rpm,
-                            f2_rpm: stats.fan2_rpm,
-                            f3_rpm: stats.fan3_rpm,
-                            ambient_temp: stats.ambient_temp,
-                            liq_in_temp: stats.coolant_temp,
-                            liq_out_temp: stats.coolant_out_temp,
+                let device = self.opilio_serial.clone();
+                let streaming = self.streaming;
+                return Command::perform(
+                    async move {
+                        let mut device = device.lock().await;
+                        let stats = if streaming {
+                            next_sample(&mut device).await
+                        } else {
+                            device.get_stats().await
                         };
-                        self.chart.update(data)
-                    }
-                    Err(err) => {
-                        self.error_text = Some(format!(
-                            "Failed to get data from opilio ({err})"
-                        ));
-                    }
-                }
+                        // the next request looks for a lost device again
+                        stats.map_err(|err| {
+                            if err.is_disconnected() {
+                                format!(
+                                    "Opilio is {}, plug it back in to carry on",
+                                    device.state()
+                                )
+                            } else {
+                                format!(
+                                    "Failed to get data from opilio ({err})"
+                                )
+                            }
+                        })
+                    },
+                    Message::StatsReceived,
+                );
             }
+            Message::StatsReceived(Ok(stats)) => self.show_stats(stats),
+            Message::StatsReceived(Err(err)) => self.error_text = Some(err),
             Message::SetSleepAfter(sleep_after) => {
                 self.config.general.sleep_after = sleep_after;
+                return self.apply_general();
             }
             Message::SetTriggerAboveAmbient(trigger_above_ambient) => {
-                if let Some(smart_mode) = self.config.smart_mode.as_mut() {
+                if let ControlMode::Smart(ref mut smart_mode) = self.config.mode
+                {
                     smart_mode.trigger_above_ambient = trigger_above_ambient;
                 }
+                return self.apply_smart_mode();
             }
             Message::SetUpperTemp(upper_temp) => {
-                if let Some(smart_mode) = self.config.smart_mode.as_mut() {
+                if let ControlMode::Smart(ref mut smart_mode) = self.config.mode
+                {
                     smart_mode.upper_temp = upper_temp;
                 }
+                return self.apply_smart_mode();
             }
             Message::SetPumpDuty(pump_duty) => {
-                if let Some(smart_mode) = self.config.smart_mode.as_mut() {
-                    smart_mode.pump_duty = pump_duty;
+                match self.config.mode {
+                    ControlMode::Smart(ref mut smart_mode) => {
+                        smart_mode.pump_duty = pump_duty;
+                        return self.apply_smart_mode();
+                    }
+                    ControlMode::Curves => {}
+                    ControlMode::Fixed(ref mut fixed_mode) => {
+                        fixed_mode.pump_duty = pump_duty
+                    }
+                    ControlMode::Pid(ref mut pid_mode) => {
+                        pid_mode.pump_duty = pump_duty
+                    }
                 }
+                return self.apply_mode();
+            }
+            Message::SetMode(name) => {
+                if self.config.mode.name() != name {
+                    self.config.mode = match name {
+                        "curves" => ControlMode::Curves,
+                        "fixed" => ControlMode::Fixed(FixedMode::default()),
+                        "pid" => ControlMode::Pid(PidMode::default()),
+                        _ => ControlMode::default(),
+                    };
+                }
+                return self.apply_mode();
+            }
+            Message::SetFanDuty(fan_duty) => {
+                if let ControlMode::Fixed(ref mut fixed_mode) = self.config.mode
+                {
+                    fixed_mode.fan_duty = fan_duty;
+                }
+                return self.apply_mode();
             }
             Message::ToggleBuzzer(enable) => {
                 if enable {
@@ -111,6 +222,7 @@ impl RunningState {
                 } else {
                     self.config.general.buzzer = SwitchMode::Off;
                 }
+                return self.apply_general();
             }
             Message::ToggleLed(enable) => {
                 if enable {
@@ -118,55 +230,244 @@ impl RunningState {
                 } else {
                     self.config.general.led = SwitchMode::Off;
                 }
+                return self.apply_general();
             }
             Message::Test => {
                 if self.testing {
-                    match self.opilio_serial.reload() {
-                        Err(e) => {
-                            self.error_text = Some(format!(
-                                "Failed to upload config to opilio {e}"
-                            ))
-                        }
-                        _ => self.testing = false,
-                    };
+                    let device = self.opilio_serial.clone();
+                    return Command::perform(
+                        async move {
+                            device.lock().await.reload().await.map_err(|e| {
+                                error_text("Failed to restore saved config", &e)
+                            })
+                        },
+                        Message::Reloaded,
+                    );
                 } else {
-                    self.upload_config();
+                    return self.upload_config();
                 }
             }
             Message::Save => {
-                self.save_config();
+                return self.save_config();
             }
             Message::Reset => {
                 self.config = Config::default();
-                self.save_config()
+                return self.save_config();
+            }
+            Message::Override(id, duty_percent) => {
+                let device = self.opilio_serial.clone();
+                return Command::perform(
+                    async move {
+                        device
+                            .lock()
+                            .await
+                            .set_override(id, duty_percent, OVERRIDE_TIMEOUT)
+                            .await
+                            .map_err(|e| {
+                                error_text(
+                                    &format!("Failed to hold {id:?}"),
+                                    &e,
+                                )
+                            })
+                    },
+                    Message::Overridden,
+                );
             }
+            Message::ClearOverride(id) => {
+                let device = self.opilio_serial.clone();
+                return Command::perform(
+                    async move {
+                        device.lock().await.clear_override(id).await.map_err(
+                            |e| {
+                                error_text(
+                                    &format!("Failed to release {id:?}"),
+                                    &e,
+                                )
+                            },
+                        )
+                    },
+                    Message::Overridden,
+                );
+            }
+            Message::Uploaded(Ok(())) => self.testing = true,
+            Message::Saved(Ok(())) | Message::Reloaded(Ok(())) => {
+                self.testing = false
+            }
+            Message::Applied(Ok(())) | Message::Overridden(Ok(())) => {}
+            Message::Uploaded(Err(err))
+            | Message::Applied(Err(err))
+            | Message::Overridden(Err(err))
+            | Message::Saved(Err(err))
+            | Message::Reloaded(Err(err)) => self.error_text = Some(err),
             Message::CloseModal => {
                 self.error_text = None;
+                self.warning_text = None;
             }
             _ => {}
         }
         Command::none()
     }
 
-    fn save_config(&mut self) {
-        self.upload_config();
-        match self.opilio_serial.save_config() {
-            Err(e) => {
-                self.error_text =
-                    Some(format!("Failed to save config to opilio {e}"))
-            }
-            _ => self.testing = false,
+    fn show_stats(&mut self, stats: Stats) {
+        let data = MonitoringData {
+            timestamp: Local::now(),
+            pump_rpm: stats.pump1_rpm,
+            f1_rpm: stats.fan1_rpm,
+            f2_rpm: stats.fan2_rpm,
+            f3_rpm: stats.fan3_rpm,
+            ambient_temp: stats.ambient_temp,
+            liq_in_temp: stats.coolant_temp,
+            liq_out_temp: stats.coolant_out_temp,
         };
+        self.overrides = stats.overrides;
+        self.chart.update(data)
     }
 
-    fn upload_config(&mut self) {
-        match self.opilio_serial.upload_config(self.config.clone()) {
-            Err(e) => {
-                self.error_text =
-                    Some(format!("Failed to upload config to opilio {e}"))
-            }
-            _ => self.testing = true,
+    /// Uploads the config and persists it once the device took it.
+    fn save_config(&mut self) -> Command<Message> {
+        if !self.review_config() {
+            return Command::none();
+        }
+        let device = self.opilio_serial.clone();
+        let config = self.config.clone();
+        Command::perform(
+            async move {
+                let mut device = device.lock().await;
+                device.upload_config(config).await.map_err(|e| {
+                    error_text("Failed to upload config to opilio", &e)
+                })?;
+                device.save_config().await.map_err(|e| {
+                    error_text("Failed to save config to opilio", &e)
+                })
+            },
+            Message::Saved,
+        )
+    }
+
+    fn upload_config(&mut self) -> Command<Message> {
+        if !self.review_config() {
+            return Command::none();
+        }
+        let device = self.opilio_serial.clone();
+        let config = self.config.clone();
+        Command::perform(
+            async move {
+                device
+                    .lock()
+                    .await
+                    .upload_config(config)
+                    .await
+                    .map_err(|e| {
+                        error_text("Failed to upload config to opilio", &e)
+                    })
+            },
+            Message::Uploaded,
+        )
+    }
+
+    /// Sends the general settings alone while testing, so an edit shows
+    /// up on the device straight away.
+    fn apply_general(&mut self) -> Command<Message> {
+        if !self.live() {
+            return Command::none();
+        }
+        let device = self.opilio_serial.clone();
+        let config = self.config.clone();
+        Command::perform(
+            async move {
+                let mut device = device.lock().await;
+                let applied =
+                    match device.upload_general(config.general.clone()).await {
+                        // firmware before partial updates takes it all
+                        Err(ClientError::Unsupported { .. }) => {
+                            device.upload_config(config).await
+                        }
+                        result => result,
+                    };
+                applied
+                    .map_err(|e| error_text("Failed to apply the change", &e))
+            },
+            Message::Applied,
+        )
+    }
+
+    /// Same as [`RunningState::apply_general`] for smart mode.
+    fn apply_smart_mode(&mut self) -> Command<Message> {
+        if !self.live() {
+            return Command::none();
+        }
+        let device = self.opilio_serial.clone();
+        let config = self.config.clone();
+        Command::perform(
+            async move {
+                let mut device = device.lock().await;
+                let smart_mode = match config.mode {
+                    ControlMode::Smart(ref smart_mode) => {
+                        Some(smart_mode.clone())
+                    }
+                    _ => None,
+                };
+                let applied = match device.upload_smart_mode(smart_mode).await {
+                    Err(ClientError::Unsupported { .. }) => {
+                        device.upload_config(config).await
+                    }
+                    result => result,
+                };
+                applied
+                    .map_err(|e| error_text("Failed to apply the change", &e))
+            },
+            Message::Applied,
+        )
+    }
+
+    /// Same as [`RunningState::apply_general`] for the control mode.
+    fn apply_mode(&mut self) -> Command<Message> {
+        if !self.live() {
+            return Command::none();
+        }
+        let device = self.opilio_serial.clone();
+        let config = self.config.clone();
+        Command::perform(
+            async move {
+                let mut device = device.lock().await;
+                let applied =
+                    match device.upload_control_mode(&config.mode).await {
+                        Err(ClientError::Unsupported { .. }) => {
+                            device.upload_config(config).await
+                        }
+                        result => result,
+                    };
+                applied
+                    .map_err(|e| error_text("Failed to apply the change", &e))
+            },
+            Message::Applied,
+        )
+    }
+
+    /// Whether edits go to the device as they are made: only while testing,
+    /// and not while the config would be refused, e.g. halfway through
+    /// typing a number.
+    fn live(&self) -> bool {
+        self.testing && self.config.is_valid()
+    }
+
+    /// Shows what is wrong with the config, `false` if the device would
+    /// refuse it.
+    fn review_config(&mut self) -> bool {
+        // show what the device will hold
+        self.config = self.config.quantized();
+        let report = self.config.validate();
+        let (errors, warnings): (Vec<_>, Vec<_>) =
+            report.iter().partition(|issue| issue.is_error());
+        if !errors.is_empty() {
+            self.error_text =
+                Some(format!("Config not uploaded\n{}", lines(&errors)));
+            return false;
         }
+        if !warnings.is_empty() {
+            self.warning_text = Some(lines(&warnings));
+        }
+        true
     }
 
     pub fn view(&self) -> Element<'_, Message> {
@@ -206,15 +507,24 @@ impl RunningState {
             .push(
                 Row::new()
                     .push(
-                        Column::new().push(
-                            Row::new().push(
-                                Text::new(format!(
-                                    "Firmware Version: {}",
-                                    self.version
-                                ))
-                                .size(28),
-                            ),
-                        ),
+                        Column::new()
+                            .push(
+                                Row::new().push(
+                                    Text::new(format!(
+                                        "Firmware Version: {}",
+                                        self.version.firmware
+                                    ))
+                                    .size(28),
+                                ),
+                            )
+                            .push(Text::new(format!(
+                                "Protocol {}, Hardware Rev {}",
+                                self.version.protocol, self.version.hardware
+                            )))
+                            .push(Text::new(format!(
+                                "Serial Number: {}",
+                                self.serial_number
+                            ))),
                     )
                     .padding(15),
             )
@@ -260,62 +570,159 @@ impl RunningState {
                     .spacing(5),
             );
 
-        if let Some(ref smart_mode) = self.config.smart_mode {
-            content = content
-                .push(horizontal_rule(10))
-                .push(Text::new("Smart Settings").size(28))
-                .push(
-                    Row::new()
-                        .push(Text::new("Trigger Above Ambient (C)"))
-                        .push(horizontal_space(Length::Fill))
-                        .push(
-                            NumberInput::new(
-                                smart_mode.trigger_above_ambient,
-                                6.0,
-                                Message::SetTriggerAboveAmbient,
+        content = content.push(horizontal_rule(10)).push(
+            Row::new()
+                .push(Text::new("Control Mode").size(28))
+                .push(horizontal_space(Length::Fill))
+                .push(iced::widget::pick_list(
+                    &MODES[..],
+                    Some(self.config.mode.name()),
+                    Message::SetMode,
+                ))
+                .spacing(5)
+                .align_items(Alignment::Center),
+        );
+        match self.config.mode {
+            ControlMode::Smart(ref smart_mode) => {
+                content = content
+                    .push(
+                        Row::new()
+                            .push(Text::new("Trigger Above Ambient (C)"))
+                            .push(horizontal_space(Length::Fill))
+                            .push(
+                                NumberInput::new(
+                                    smart_mode.trigger_above_ambient,
+                                    6.0,
+                                    Message::SetTriggerAboveAmbient,
+                                )
+                                .style(
+                                    iced_aw::style::NumberInputStyles::Default,
+                                )
+                                .step(0.5)
+                                .min(1.0),
                             )
-                            .style(iced_aw::style::NumberInputStyles::Default)
-                            .step(0.5)
-                            .min(1.0),
-                        )
-                        .padding(5)
-                        .spacing(5),
-                )
-                .push(
-                    Row::new()
-                        .push(Text::new("Upper Temp (C)"))
-                        .push(horizontal_space(Length::Fill))
-                        .push(
-                            NumberInput::new(
-                                smart_mode.upper_temp,
-                                50.0,
-                                Message::SetUpperTemp,
+                            .padding(5)
+                            .spacing(5),
+                    )
+                    .push(
+                        Row::new()
+                            .push(Text::new("Upper Temp (C)"))
+                            .push(horizontal_space(Length::Fill))
+                            .push(
+                                NumberInput::new(
+                                    smart_mode.upper_temp,
+                                    50.0,
+                                    Message::SetUpperTemp,
+                                )
+                                .style(
+                                    iced_aw::style::NumberInputStyles::Default,
+                                )
+                                .step(0.5)
+                                .min(20.0),
                             )
-                            .style(iced_aw::style::NumberInputStyles::Default)
-                            .step(0.5)
-                            .min(20.0),
-                        )
-                        .padding(5)
-                        .spacing(5),
-                )
-                .push(
+                            .padding(5)
+                            .spacing(5),
+                    );
+            }
+            ControlMode::Curves => {
+                content =
+                    content.push(Text::new("Every channel follows its curve."));
+            }
+            ControlMode::Fixed(ref fixed_mode) => {
+                content = content.push(
                     Row::new()
-                        .push(Text::new("Pump Speed (%)"))
+                        .push(Text::new("Fan Speed (%)"))
                         .push(horizontal_space(Length::Fill))
                         .push(
                             NumberInput::new(
-                                smart_mode.pump_duty,
-                                100.0,
-                                Message::SetPumpDuty,
+                                fixed_mode.fan_duty,
+                                MAX_DUTY_PERCENT,
+                                Message::SetFanDuty,
                             )
                             .style(iced_aw::style::NumberInputStyles::Default)
                             .step(1.0)
-                            .min(30.0),
+                            .min(0.0),
                         )
                         .padding(5)
                         .spacing(5),
                 );
-        };
+            }
+            ControlMode::Pid(ref pid_mode) => {
+                content = content.push(Text::new(format!(
+                    "Fans hold the coolant {} C above ambient.",
+                    pid_mode.target_delta
+                )));
+            }
+        }
+        if let Some(pump_duty) = self.config.mode.pump_duty() {
+            content = content.push(
+                Row::new()
+                    .push(Text::new("Pump Speed (%)"))
+                    .push(horizontal_space(Length::Fill))
+                    .push(
+                        NumberInput::new(
+                            pump_duty,
+                            100.0,
+                            Message::SetPumpDuty,
+                        )
+                        .style(iced_aw::style::NumberInputStyles::Default)
+                        .step(1.0)
+                        .min(30.0),
+                    )
+                    .padding(5)
+                    .spacing(5),
+            );
+        }
+
+        content = content
+            .push(horizontal_rule(10))
+            .push(Text::new("Overrides").size(28));
+        for id in Id::ALL {
+            // the pump is parked for maintenance, fans spun up to test them
+            let (label, duty_percent) = match id {
+                Id::P1 => ("Park", 0.0),
+                _ => ("Full speed", MAX_DUTY_PERCENT),
+            };
+            let (state, button) = match self.overrides[id.index()] {
+                Some(held) => (
+                    format!(
+                        "{id:?} at {} % ({} s)",
+                        held.duty_percent, held.timeout_s
+                    ),
+                    iced::widget::button("Release")
+                        .style(iced::theme::Button::Destructive)
+                        .on_press(Message::ClearOverride(id)),
+                ),
+                None => (
+                    match self
+                        .config
+                        .settings
+                        .iter()
+                        .find(|setting| setting.id == id)
+                        .map(|setting| setting.mode)
+                    {
+                        Some(ChannelMode::Fixed(duty_percent)) => {
+                            format!("{id:?} fixed at {duty_percent} %")
+                        }
+                        Some(ChannelMode::Curve) => {
+                            format!("{id:?} follows its curve")
+                        }
+                        _ => format!("{id:?} follows the mode"),
+                    },
+                    iced::widget::button(label)
+                        .on_press(Message::Override(id, duty_percent)),
+                ),
+            };
+            content = content.push(
+                Row::new()
+                    .push(Text::new(state))
+                    .push(horizontal_space(Length::Fill))
+                    .push(button.width(Length::Fixed(110.0)))
+                    .padding(5)
+                    .spacing(5)
+                    .align_items(Alignment::Center),
+            );
+        }
 
         let hide_button = iced::widget::button("Minimize")
             .style(iced::theme::Button::Primary)
@@ -396,27 +803,29 @@ impl RunningState {
                     .width(Length::Fill),
             );
 
-        iced_aw::Modal::new(self.error_text.is_some(), content, || {
-            iced_aw::Card::new(
-                Text::new("Error"),
-                Text::new(
-                    self.error_text.clone().unwrap_or_else(|| "".to_owned()),
-                ),
-            )
-            .foot(
-                Column::new().padding(5).width(Length::Fill).push(
-                    iced::widget::Button::new(
-                        Text::new("Ok").horizontal_alignment(
-                            alignment::Horizontal::Center,
-                        ),
-                    )
-                    .width(Length::Fixed(100.0))
-                    .on_press(Message::CloseModal),
-                ),
-            )
-            .max_width(300.0)
-            .on_close(Message::CloseModal)
-            .into()
+        let (title, text) = match (&self.error_text, &self.warning_text) {
+            (Some(error), _) => ("Error", error.clone()),
+            (None, Some(warning)) => ("Warning", warning.clone()),
+            (None, None) => ("", String::new()),
+        };
+        let show_modal =
+            self.error_text.is_some() || self.warning_text.is_some();
+        iced_aw::Modal::new(show_modal, content, move || {
+            iced_aw::Card::new(Text::new(title), Text::new(text.clone()))
+                .foot(
+                    Column::new().padding(5).width(Length::Fill).push(
+                        iced::widget::Button::new(
+                            Text::new("Ok").horizontal_alignment(
+                                alignment::Horizontal::Center,
+                            ),
+                        )
+                        .width(Length::Fixed(100.0))
+                        .on_press(Message::CloseModal),
+                    ),
+                )
+                .max_width(300.0)
+                .on_close(Message::CloseModal)
+                .into()
         })
         .backdrop(Message::CloseModal)
         .on_esc(Message::CloseModal)
@@ -534,3 +943,32 @@ impl RunningState {
 
 //     col.into()
 // }
+
+/// One issue per line.
+fn lines(issues: &[&ValidationIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| issue.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Errors reported by the device already say what failed, anything else
+/// gets `context` in front.
+fn error_text(context: &str, e: &ClientError) -> String {
+    match e {
+        ClientError::Device(e) => e.to_string(),
+        e => format!("{context} ({e})"),
+    }
+}
+
+/// Next pushed sample, the device forgets the subscription when it is
+/// replugged so it is renewed after a reconnect.
+async fn next_sample(
+    device: &mut AsyncOpilioSerialDevice,
+) -> Result<Stats, ClientError> {
+    if !device.is_subscribed() {
+        device.subscribe_stats(UPDATE_INTERVAL).await?;
+    }
+    device.next_stats().await.map(|sample| sample.stats)
+}